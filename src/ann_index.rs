@@ -0,0 +1,9 @@
+use std::io;
+
+/// Common interface of the data structures that can be evaluated against an exact ground truth.
+pub trait AnnIndex {
+    /// Given a query `q`, return the ids of at most `k` stored points with the highest dot
+    /// product with `q`, sorted by decreasing dot product. The id of a point is its index in
+    /// the data used to build the structure.
+    fn query_k(&self, q: &[f64], k: usize) -> Result<Vec<usize>, io::Error>;
+}
@@ -1,27 +1,48 @@
+use clap::Parser;
 use savefile::prelude::*; // For save_file
-use savefile_derive::Savefile; // For #[derive(Savefile)]
 use std::fs::create_dir_all;
-use std::io::{Error, ErrorKind}; // Import only Error and ErrorKind
+use std::io::Error; // Import only Error
+use std::path::Path;
 use rayon::prelude::*;
 
-use ann_rust::utils::{generate_normal_gaussian_vectors_parallel, normalize_vector}; // Import generate_gaussian_vectors
+use ann_rust::cli::GaussianVectors;
+use ann_rust::utils::{
+    generate_normal_gaussian_vectors_parallel, generate_normal_gaussian_vectors_seeded,
+    normalize_vector,
+}; // Import generate_gaussian_vectors
 
-#[derive(Savefile)] // Derive Savefile for serialization
-struct GaussianVectors {
-    vectors: Vec<Vec<f64>>,
+/// Generate normalized Gaussian vectors and save them to a data file.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Number of vectors
+    #[arg(long, default_value_t = 10_000_000)]
+    n: usize,
+    /// Dimension of each vector
+    #[arg(long, default_value_t = 100)]
+    d: usize,
+    /// Seed of the generated vectors
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Output file, `data/dimension_{d}/sample_{n}.bin` if not given
+    #[arg(long)]
+    data: Option<String>,
 }
 
 fn main() -> std::io::Result<()> {
-    let n = 10_000_000; // Number of vectors
-    let d = 100; // Dimension of each vector
+    let args = Args::parse();
+    let (n, d) = (args.n, args.d);
 
-    // Define the folder and file name
-    let folder_name = format!("data/dimension_{}", d);
-    let file_name = format!("{}/sample_{}.bin", folder_name, n);
+    // Define the file name
+    let file_name = args
+        .data
+        .unwrap_or_else(|| format!("data/dimension_{}/sample_{}.bin", d, n));
 
     // Generate the Gaussian vectors
     println!("Generating {} Gaussian vectors of dimension {}...", n, d);
-    let mut vectors = generate_normal_gaussian_vectors_parallel(n, d)?;
+    let mut vectors = match args.seed {
+        Some(seed) => generate_normal_gaussian_vectors_seeded(n, d, seed)?,
+        None => generate_normal_gaussian_vectors_parallel(n, d)?,
+    };
 
     println!("Normalizing the vectors...");
     // Normalize the vectors
@@ -33,7 +54,9 @@ fn main() -> std::io::Result<()> {
     let data = GaussianVectors { vectors };
 
     // Create the folder if not present
-    create_dir_all(&folder_name)?;
+    if let Some(folder) = Path::new(&file_name).parent() {
+        create_dir_all(folder)?;
+    }
 
     // Save the file
     save_vectors(&file_name, &data)?;
@@ -45,6 +68,5 @@ fn main() -> std::io::Result<()> {
 
 /// Save the Gaussian vectors to a binary file.
 fn save_vectors(file_name: &str, data: &GaussianVectors) -> std::io::Result<()> {
-    save_file(file_name, 0, data)
-        .map_err(|e| Error::new(ErrorKind::Other, format!("Failed to save file: {}", e)))
+    save_file(file_name, 0, data).map_err(|e| Error::other(format!("Failed to save file: {}", e)))
 }
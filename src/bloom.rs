@@ -0,0 +1,116 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Number of bits of the filter per expected element.
+const BITS_PER_ELEMENT: usize = 10;
+/// Number of hash functions. With 10 bits per element, 7 hash functions give a false positive
+/// rate of about 0.8% while the filter holds at most its capacity.
+const NUM_HASHES: u64 = 7;
+
+/// Bloom filter over vectors, for approximate membership tests.
+///
+/// `contains` never returns false for an inserted vector (no false negatives), but may return
+/// true for a vector that was not inserted, with probability about 0.8% while the number of
+/// inserted vectors is at most `capacity`. The rate grows beyond the capacity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    capacity: usize,
+    len: usize,
+}
+
+impl BloomFilter {
+    /// Create an empty filter sized for `capacity` vectors.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let num_bits = (capacity * BITS_PER_ELEMENT).max(64) as u64;
+        BloomFilter {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            capacity,
+            len: 0,
+        }
+    }
+
+    /// Insert a vector in the filter.
+    pub fn insert(&mut self, vector: &[f64]) {
+        for bit in self.bit_indices(vector) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+    }
+
+    /// Return false if the vector was definitely not inserted, true if it may have been.
+    pub fn contains(&self, vector: &[f64]) -> bool {
+        self.bit_indices(vector)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Return the number of inserted vectors.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return true if no vector was inserted.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the number of bytes of the bits of the filter.
+    pub fn memory_footprint(&self) -> usize {
+        self.bits.capacity() * std::mem::size_of::<u64>()
+    }
+
+    /// Return the number of vectors the filter is sized for.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Return the indices of the bits of `vector`, by double hashing of its coordinates.
+    fn bit_indices(&self, vector: &[f64]) -> impl Iterator<Item = u64> {
+        let hash = |salt: u64| {
+            let mut hasher = DefaultHasher::new();
+            salt.hash(&mut hasher);
+            for x in vector {
+                // Hash the bits, with 0.0 and -0.0 hashed equally since they compare equal
+                (x + 0.0).to_bits().hash(&mut hasher);
+            }
+            hasher.finish()
+        };
+        let (h1, h2) = (hash(0), hash(1) | 1);
+        let num_bits = self.num_bits;
+        (0..NUM_HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+/// Test function for BloomFilter struct.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::generate_normal_gaussian_vectors_seeded;
+
+    // test that inserted vectors are always found and that false positives are rare
+    #[test]
+    fn test_bloom_filter() {
+        let vectors = generate_normal_gaussian_vectors_seeded(2000, 4, 1).unwrap();
+        let mut filter = BloomFilter::new(1000);
+        for v in vectors.iter().take(1000) {
+            filter.insert(v);
+        }
+        assert_eq!(filter.len(), 1000);
+        assert!(vectors.iter().take(1000).all(|v| filter.contains(v)));
+
+        let false_positives = vectors
+            .iter()
+            .skip(1000)
+            .filter(|v| filter.contains(v))
+            .count();
+        assert!(
+            false_positives < 50,
+            "false positives = {}",
+            false_positives
+        );
+        assert!(filter.contains(&[-0.0, 0.0]) == filter.contains(&[0.0, 0.0]));
+    }
+}
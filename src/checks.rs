@@ -1,10 +1,21 @@
+use crate::error::AnnError;
+
+/// Check that a vector of dimension `found` can be used with a structure of dimension
+/// `expected`.
+pub fn check_dimension(expected: usize, found: usize) -> Result<(), AnnError> {
+    if expected != found {
+        return Err(AnnError::DimensionMismatch { expected, found });
+    }
+    Ok(())
+}
+
 /// Check if the input data is valid.
-pub fn check_input(
-    data: &Vec<Vec<f64>>,
-    alpha: f64,
-    beta: f64,
-    theta: f64,
-) -> Result<(), String> {
+pub fn check_input(data: &[Vec<f64>], alpha: f64, beta: f64, theta: f64) -> Result<(), String> {
+    check_input_with(data, alpha, beta, theta, true)
+}
+
+/// Check that `0 < beta < alpha < 1` and that `theta` is positive.
+pub fn check_parameters(alpha: f64, beta: f64, theta: f64) -> Result<(), String> {
     // Validate alpha
     if !(0.0 < alpha && alpha < 1.0) {
         return Err("Invalid value for alpha. Alpha must be in the range (0, 1).".to_string());
@@ -16,10 +27,24 @@ pub fn check_input(
     }
 
     // Validate theta
-    if !(theta > 0.0) {
+    if theta <= 0.0 || theta.is_nan() {
         return Err("Invalid value for theta. Theta must be positive.".to_string());
     }
 
+    Ok(())
+}
+
+/// Like `check_input`, but the vectors are only required to be normalized if
+/// `require_normalized` is true.
+pub fn check_input_with(
+    data: &[Vec<f64>],
+    alpha: f64,
+    beta: f64,
+    theta: f64,
+    require_normalized: bool,
+) -> Result<(), String> {
+    check_parameters(alpha, beta, theta)?;
+
     // Validate data is non-empty
     if data.is_empty() {
         return Err("Data cannot be empty.".to_string());
@@ -42,9 +67,17 @@ pub fn check_input(
             ));
         }
 
+        // Check if all coordinates are finite, NaN would otherwise pass the norm check
+        if let Some(j) = vector.iter().position(|x| !x.is_finite()) {
+            return Err(format!(
+                "Vector at index {} has a non finite coordinate at index {} (value = {}).",
+                i, j, vector[j]
+            ));
+        }
+
         // Check if the vector is normalized (sum of squares equals 1)
         let norm = vector.iter().map(|x| x * x).sum::<f64>();
-        if (norm - 1.0).abs() > 1e-6 {
+        if require_normalized && (norm - 1.0).abs() > 1e-6 {
             return Err(format!(
                 "Vector at index {} is not normalized (norm = {}).",
                 i, norm
@@ -53,4 +86,29 @@ pub fn check_input(
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Test function for check_input.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test function to check that NaN and infinite coordinates are rejected.
+    #[test]
+    fn test_check_input_non_finite() {
+        let valid = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        assert!(check_input(&valid, 0.9, 0.5, 0.5).is_ok());
+
+        let nan = vec![vec![1.0, 0.0], vec![f64::NAN, 0.0]];
+        let err = check_input(&nan, 0.9, 0.5, 0.5).unwrap_err();
+        assert!(
+            err.contains("index 1") && err.contains("index 0"),
+            "{}",
+            err
+        );
+
+        let inf = vec![vec![0.0, f64::INFINITY], vec![0.0, 1.0]];
+        let err = check_input(&inf, 0.9, 0.5, 0.5).unwrap_err();
+        assert!(err.contains("non finite"), "{}", err);
+    }
+}
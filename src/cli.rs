@@ -0,0 +1,172 @@
+use crate::error::AnnError;
+use crate::utils::{
+    generate_normal_gaussian_vectors, generate_normal_gaussian_vectors_seeded, normalize_vector,
+    optimal_theta,
+};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use savefile::prelude::*;
+use savefile_derive::Savefile;
+use std::io;
+
+/// Vectors as stored in the data files of the binaries.
+#[derive(Savefile)]
+pub struct GaussianVectors {
+    pub vectors: Vec<Vec<f64>>,
+}
+
+/// Command line arguments shared by the experiment binaries.
+#[derive(Parser, Debug, Clone)]
+pub struct ExperimentArgs {
+    /// Number of vectors
+    #[arg(long, default_value_t = 100)]
+    pub n: usize,
+    /// Dimension of each vector
+    #[arg(long, default_value_t = 100)]
+    pub d: usize,
+    /// Close point according to cosine similarity
+    #[arg(long, default_value_t = 0.9)]
+    pub alpha: f64,
+    /// Far point according to cosine similarity
+    #[arg(long, default_value_t = 0.55)]
+    pub beta: f64,
+    /// Exponent of the number of Gaussian vectors, `optimal_theta(alpha, beta)` if not given
+    #[arg(long)]
+    pub theta: Option<f64>,
+    /// Use the fast preprocessing of TensorTop1
+    #[arg(long)]
+    pub fast_preprocessing: bool,
+    /// Seed of the generated data and of the Gaussian vectors
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Data file, `data/dimension_{d}/sample_{n}.bin` if not given
+    #[arg(long)]
+    pub data: Option<String>,
+}
+
+impl ExperimentArgs {
+    /// Parse the arguments of the process like `parse`, with `n` vectors by default, e.g.
+    /// `"1000000"`.
+    pub fn parse_with_default_n(n: &'static str) -> Self {
+        let matches = Self::command()
+            .mut_arg("n", |arg| arg.default_value(n))
+            .get_matches();
+        Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+    }
+
+    /// Check the arguments and resolve the exponent `theta`.
+    pub fn theta(&self) -> Result<f64, AnnError> {
+        if self.n == 0 {
+            return Err(AnnError::InvalidParameter("n must be positive".to_string()));
+        }
+        if self.d == 0 {
+            return Err(AnnError::InvalidParameter("d must be positive".to_string()));
+        }
+        // optimal_theta checks alpha and beta, an explicit theta does not
+        if !(0.0 < self.beta && self.beta < self.alpha && self.alpha < 1.0) {
+            return Err(AnnError::InvalidParameter(format!(
+                "expected 0 < beta < alpha < 1, got alpha = {} and beta = {}",
+                self.alpha, self.beta
+            )));
+        }
+        match self.theta {
+            Some(theta) if theta > 0.0 && theta < 1.0 => Ok(theta),
+            Some(theta) => Err(AnnError::InvalidParameter(format!(
+                "theta must be in (0, 1), got {}",
+                theta
+            ))),
+            None => optimal_theta(self.alpha, self.beta).map_err(AnnError::InvalidParameter),
+        }
+    }
+
+    /// Return the path of the data file.
+    pub fn data_file(&self) -> String {
+        match &self.data {
+            Some(path) => path.clone(),
+            None => format!("data/dimension_{}/sample_{}.bin", self.d, self.n),
+        }
+    }
+
+    /// Load the data file, or generate `n` normalized Gaussian vectors of dimension `d` if it
+    /// cannot be loaded. An error is returned if the loaded data does not have `n` vectors of
+    /// dimension `d`.
+    pub fn load_or_generate(&self) -> Result<Vec<Vec<f64>>, AnnError> {
+        let file_name = self.data_file();
+        match load_vectors(&file_name) {
+            Ok(data) => {
+                if data.vectors.len() != self.n {
+                    return Err(AnnError::InvalidParameter(format!(
+                        "'{}' holds {} vectors, expected n = {}",
+                        file_name,
+                        data.vectors.len(),
+                        self.n
+                    )));
+                }
+                if let Some(v) = data.vectors.iter().find(|v| v.len() != self.d) {
+                    return Err(AnnError::DimensionMismatch {
+                        expected: self.d,
+                        found: v.len(),
+                    });
+                }
+                println!(
+                    "Successfully loaded {} vectors from '{}'.",
+                    data.vectors.len(),
+                    file_name
+                );
+                Ok(data.vectors)
+            }
+            Err(e) => {
+                eprintln!("Failed to load vectors: {}. Generating new vectors...", e);
+                let mut vectors = match self.seed {
+                    Some(seed) => {
+                        generate_normal_gaussian_vectors_seeded(self.n, self.d, seed).unwrap()
+                    }
+                    None => generate_normal_gaussian_vectors(self.n, self.d).unwrap(),
+                };
+                vectors
+                    .iter_mut()
+                    .for_each(|vector| normalize_vector(vector));
+                Ok(vectors)
+            }
+        }
+    }
+}
+
+/// Load the vectors of a data file.
+pub fn load_vectors(file_name: &str) -> io::Result<GaussianVectors> {
+    load_file(file_name, 0).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Failed to load file: {}", e),
+        )
+    })
+}
+
+/// Test function for ExperimentArgs struct.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test parsing and validation of the arguments
+    #[test]
+    fn test_experiment_args() {
+        let args = ExperimentArgs::parse_from(["bin", "--n", "10", "--d", "3", "--theta", "0.2"]);
+        assert_eq!((args.n, args.d), (10, 3));
+        assert_eq!(args.theta(), Ok(0.2));
+        assert_eq!(args.data_file(), "data/dimension_3/sample_10.bin");
+
+        let args = ExperimentArgs::parse_from(["bin", "--alpha", "0.5", "--beta", "0.6"]);
+        assert!(matches!(args.theta(), Err(AnnError::InvalidParameter(_))));
+        let args = ExperimentArgs::parse_from(["bin", "--n", "0"]);
+        assert!(matches!(args.theta(), Err(AnnError::InvalidParameter(_))));
+
+        // alpha and beta are checked even if theta is given
+        let args = ExperimentArgs::parse_from([
+            "bin", "--alpha", "0.5", "--beta", "0.6", "--theta", "0.2",
+        ]);
+        assert!(matches!(args.theta(), Err(AnnError::InvalidParameter(_))));
+        let args = ExperimentArgs::parse_from([
+            "bin", "--alpha", "1.0", "--beta", "0.6", "--theta", "0.2",
+        ]);
+        assert!(matches!(args.theta(), Err(AnnError::InvalidParameter(_))));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::error::AnnError;
+use std::collections::HashSet;
+
+/// How the candidates reached through several buckets, e.g. in several tables of a
+/// MultiTableTop1 structure or under several composite keys of a TensorTop1 structure, are
+/// collapsed before being counted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DedupStrategy<'a> {
+    /// Every occurrence of a point is counted.
+    None,
+    /// The points are collapsed by their id, i.e. their index in the data.
+    ByIndex,
+    /// The points are collapsed by their exact coordinates, so equal points stored under
+    /// different ids are counted once. `-0.0` and `0.0` are equal coordinates.
+    ByValue,
+    /// The points are collapsed by a key per id, e.g. derived from a payload or rounded
+    /// coordinates: the point of id `i` has key `keys[i]`.
+    ByKey(&'a [u64]),
+}
+
+/// Remember the candidates already seen under a `DedupStrategy`.
+pub(crate) struct Dedup<'a> {
+    strategy: DedupStrategy<'a>,
+    ids: HashSet<usize>,
+    values: HashSet<Vec<u64>>,
+    keys: HashSet<u64>,
+}
+
+impl<'a> Dedup<'a> {
+    pub(crate) fn new(strategy: DedupStrategy<'a>) -> Self {
+        Dedup {
+            strategy,
+            ids: HashSet::new(),
+            values: HashSet::new(),
+            keys: HashSet::new(),
+        }
+    }
+
+    /// Return true if the candidate of id `id` and coordinates `vector` was not seen yet, and
+    /// remember it. An error is returned if a key is missing for `id`.
+    pub(crate) fn insert(&mut self, id: usize, vector: &[f64]) -> Result<bool, AnnError> {
+        match self.strategy {
+            DedupStrategy::None => Ok(true),
+            DedupStrategy::ByIndex => Ok(self.ids.insert(id)),
+            DedupStrategy::ByValue => {
+                // Adding 0.0 maps -0.0 to 0.0, whose bits differ
+                Ok(self
+                    .values
+                    .insert(vector.iter().map(|x| (x + 0.0).to_bits()).collect()))
+            }
+            DedupStrategy::ByKey(keys) => match keys.get(id) {
+                Some(key) => Ok(self.keys.insert(*key)),
+                None => Err(AnnError::InvalidParameter(format!(
+                    "no key for the point {}",
+                    id
+                ))),
+            },
+        }
+    }
+}
+
+/// Test function for Dedup struct.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test each strategy on repeated candidates
+    #[test]
+    fn test_dedup_strategies() {
+        let candidates = [
+            (0, vec![1.0, 0.0]),
+            (0, vec![1.0, 0.0]),
+            (1, vec![1.0, 0.0]),
+            (2, vec![0.0, 1.0]),
+        ];
+        let keys = [7, 8, 7];
+        let count = |strategy| {
+            let mut dedup = Dedup::new(strategy);
+            candidates
+                .iter()
+                .filter(|(id, v)| dedup.insert(*id, v).unwrap())
+                .count()
+        };
+        assert_eq!(count(DedupStrategy::None), 4);
+        assert_eq!(count(DedupStrategy::ByIndex), 3);
+        assert_eq!(count(DedupStrategy::ByValue), 2);
+        assert_eq!(count(DedupStrategy::ByKey(&keys)), 2);
+        assert!(Dedup::new(DedupStrategy::ByKey(&keys[..1]))
+            .insert(1, &[0.0, 1.0])
+            .is_err());
+
+        // -0.0 and 0.0 are the same coordinate
+        let mut dedup = Dedup::new(DedupStrategy::ByValue);
+        assert!(dedup.insert(0, &[0.0, 1.0]).unwrap());
+        assert!(!dedup.insert(1, &[-0.0, 1.0]).unwrap());
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+use std::io;
+
+/// Errors returned by the operations on the data structures.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnnError {
+    /// The Gaussian vectors of two structures differ, so their buckets are incompatible.
+    IncompatibleProjections,
+    /// A parameter of two structures differs.
+    IncompatibleParameters(String),
+    /// A TensorTop1 structure made of several Top1 structures cannot be flattened.
+    NotFlattenable { structures: usize },
+    /// A point or a structure does not have the dimension of the structure.
+    DimensionMismatch { expected: usize, found: usize },
+    /// A parameter is out of its valid range.
+    InvalidParameter(String),
+}
+
+impl fmt::Display for AnnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnError::IncompatibleProjections => {
+                write!(f, "The structures do not share the same Gaussian vectors.")
+            }
+            AnnError::IncompatibleParameters(parameter) => {
+                write!(
+                    f,
+                    "The structures have a different value for {}.",
+                    parameter
+                )
+            }
+            AnnError::NotFlattenable { structures } => write!(
+                f,
+                "The structure has {} Top1 structures, flattening it requires a rebuild.",
+                structures
+            ),
+            AnnError::DimensionMismatch { expected, found } => write!(
+                f,
+                "Expected vectors of dimension {}, got dimension {}.",
+                expected, found
+            ),
+            AnnError::InvalidParameter(reason) => write!(f, "Invalid parameter: {}.", reason),
+        }
+    }
+}
+
+impl std::error::Error for AnnError {}
+
+impl From<AnnError> for io::Error {
+    fn from(err: AnnError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+/// Errors returned by a private counter, see `privacy::PrivateCounter`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BudgetError {
+    /// Releasing one more count would spend more than the privacy budget.
+    Exhausted {
+        budget: f64,
+        spent: f64,
+        requested: f64,
+    },
+    /// The query or its epsilon is invalid. No budget is spent.
+    InvalidQuery(String),
+}
+
+impl fmt::Display for BudgetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetError::Exhausted {
+                budget,
+                spent,
+                requested,
+            } => write!(
+                f,
+                "The privacy budget {} is exhausted: {} spent, {} requested.",
+                budget, spent, requested
+            ),
+            BudgetError::InvalidQuery(reason) => write!(f, "Invalid query: {}.", reason),
+        }
+    }
+}
+
+impl std::error::Error for BudgetError {}
+
+impl From<BudgetError> for io::Error {
+    fn from(err: BudgetError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
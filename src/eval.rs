@@ -0,0 +1,374 @@
+use crate::ann_index::AnnIndex;
+use crate::simple_data_structures::top1::{get_closest_gaussian, Top1};
+use crate::utils::dot_product;
+use rayon::prelude::*;
+use std::cmp::Ordering;
+
+/// For each query, return the indices of the `k` points of `data` with the highest dot product,
+/// sorted by decreasing dot product (ties are broken by the lowest index).
+/// It is the exact ground truth used to evaluate the approximate data structures.
+///
+/// If `k` is larger than the number of points, all the points are returned sorted.
+pub fn exact_topk(data: &[Vec<f64>], queries: &[Vec<f64>], k: usize) -> Vec<Vec<usize>> {
+    queries
+        .par_iter()
+        .map(|query| {
+            let mut scores: Vec<(usize, f64)> = data
+                .iter()
+                .enumerate()
+                .map(|(i, point)| (i, dot_product(query, point)))
+                .collect();
+            let k = k.min(scores.len());
+            if k == 0 {
+                return Vec::new();
+            }
+
+            // Partial sort: move the k best points to the front, then sort only them
+            if k < scores.len() {
+                scores.select_nth_unstable_by(k - 1, compare_scores);
+                scores.truncate(k);
+            }
+            scores.sort_unstable_by(compare_scores);
+            scores.into_iter().map(|(i, _)| i).collect()
+        })
+        .collect()
+}
+
+/// Run `query_k` for each query and return the average fraction of the true top-k
+/// (the first `k` entries of `ground_truth`, e.g. from `exact_topk`) found among the `k`
+/// returned ids. A query that returns an error recalls nothing.
+pub fn recall_at_k(
+    index: &impl AnnIndex,
+    queries: &[Vec<f64>],
+    ground_truth: &[Vec<usize>],
+    k: usize,
+) -> f64 {
+    let mut total_recall = 0.;
+    let mut count = 0;
+    for (query, truth) in queries.iter().zip(ground_truth.iter()) {
+        let truth = &truth[..k.min(truth.len())];
+        if truth.is_empty() {
+            continue;
+        }
+        count += 1;
+        if let Ok(result) = index.query_k(query, k) {
+            let found = truth.iter().filter(|id| result.contains(id)).count();
+            total_recall += found as f64 / truth.len() as f64;
+        }
+    }
+    if count == 0 {
+        return 0.;
+    }
+    total_recall / count as f64
+}
+
+/// Estimate how often `index` misses a true `beta`-neighbor in the self-neighbor setting. For
+/// `sample` points of `data` taken at evenly spaced indices, check by brute force whether the
+/// point has another point at dot product at least `beta`, and whether the two best ids returned
+/// by `query_k` for the point include such a neighbor. Return the fraction of misses among the
+/// points that truly have a neighbor, or 0 if none has. A query that returns an error misses.
+pub fn false_negative_rate(
+    index: &impl AnnIndex,
+    data: &[Vec<f64>],
+    beta: f64,
+    sample: usize,
+) -> f64 {
+    let (misses, with_neighbor) = self_neighbor_misses(index, data, beta, sample);
+    if with_neighbor == 0 {
+        return 0.;
+    }
+    misses as f64 / with_neighbor as f64
+}
+
+/// Return the number of misses of `false_negative_rate` and the number of sampled points that
+/// truly have a `beta`-neighbor.
+pub(crate) fn self_neighbor_misses(
+    index: &impl AnnIndex,
+    data: &[Vec<f64>],
+    beta: f64,
+    sample: usize,
+) -> (usize, usize) {
+    let sample = sample.min(data.len());
+    let mut with_neighbor = 0;
+    let mut misses = 0;
+    for s in 0..sample {
+        let i = s * data.len() / sample;
+        let point = &data[i];
+        let is_neighbor = |j: usize| j != i && dot_product(point, &data[j]) >= beta;
+        if !(0..data.len()).any(is_neighbor) {
+            continue;
+        }
+        with_neighbor += 1;
+        // The point itself is at most one of the two best ids
+        let found = index
+            .query_k(point, 2)
+            .is_ok_and(|ids| ids.into_iter().any(|j| j < data.len() && is_neighbor(j)));
+        if !found {
+            misses += 1;
+        }
+    }
+    (misses, with_neighbor)
+}
+
+/// For each (query, point) pair, check whether the query probes the bucket of the point, i.e.
+/// whether the closest Gaussian vector of the point meets the threshold of `index` for the
+/// query, and return the fraction of the pairs that collide, or 0 if there is no pair. This
+/// is the measured counterpart of `utils::collision_probability`.
+pub fn empirical_collision_rate(index: &Top1, pairs: &[(Vec<f64>, Vec<f64>)]) -> f64 {
+    if pairs.is_empty() {
+        return 0.;
+    }
+    let gaussian_vectors = index.projections();
+    let collisions = pairs
+        .iter()
+        .filter(|(query, point)| {
+            let bucket = get_closest_gaussian(point, gaussian_vectors);
+            dot_product(query, &gaussian_vectors[bucket]) >= index.threshold
+        })
+        .count();
+    collisions as f64 / pairs.len() as f64
+}
+
+/// Exact search over all the points, the baseline of the benchmarks of the approximate data
+/// structures.
+pub struct BruteForce {
+    pub data: Vec<Vec<f64>>,
+}
+
+impl BruteForce {
+    /// Constructor for the BruteForce struct.
+    pub fn new(data: Vec<Vec<f64>>) -> Self {
+        BruteForce { data }
+    }
+
+    /// Given a query `q`, return the index of the point with the highest dot product with `q`
+    /// and this dot product, or None if there is no point. The points are scanned in parallel;
+    /// ties are broken by the lowest index, so the result does not depend on the scheduling.
+    pub fn query(&self, q: &[f64]) -> Option<(usize, f64)> {
+        self.data
+            .par_iter()
+            .enumerate()
+            .map(|(i, point)| (i, dot_product(q, point)))
+            .reduce_with(best_score)
+    }
+
+    /// Like `query`, scanning the points sequentially.
+    pub fn query_sequential(&self, q: &[f64]) -> Option<(usize, f64)> {
+        self.data
+            .iter()
+            .enumerate()
+            .map(|(i, point)| (i, dot_product(q, point)))
+            .reduce(best_score)
+    }
+}
+
+impl AnnIndex for BruteForce {
+    fn query_k(&self, q: &[f64], k: usize) -> Result<Vec<usize>, std::io::Error> {
+        Ok(exact_topk(&self.data, &[q.to_vec()], k).remove(0))
+    }
+}
+
+/// Return the best of two scores: the highest score, then the lowest index. This is a total
+/// order (NaN scores last), so a parallel reduction with it is deterministic.
+fn best_score(a: (usize, f64), b: (usize, f64)) -> (usize, f64) {
+    let a_key = if a.1.is_nan() { f64::NEG_INFINITY } else { a.1 };
+    let b_key = if b.1.is_nan() { f64::NEG_INFINITY } else { b.1 };
+    match b_key.total_cmp(&a_key).then(a.0.cmp(&b.0)) {
+        Ordering::Greater => b,
+        _ => a,
+    }
+}
+
+/// Order by decreasing score, then by increasing index.
+fn compare_scores(a: &(usize, f64), b: &(usize, f64)) -> Ordering {
+    b.1.partial_cmp(&a.1)
+        .unwrap_or(Ordering::Equal)
+        .then(a.0.cmp(&b.0))
+}
+
+/// Test function
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test exact top-k on a dataset with known ordering
+    #[test]
+    fn test_exact_topk() {
+        let data = vec![
+            vec![0.0, 1.0],
+            vec![1.0, 0.0],
+            vec![0.6, 0.8],
+            vec![0.8, 0.6],
+            vec![-1.0, 0.0],
+        ];
+        let queries = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+
+        let result = exact_topk(&data, &queries, 2);
+        assert_eq!(result, vec![vec![1, 3], vec![0, 2]]);
+
+        // k larger than n returns all the points sorted
+        let result = exact_topk(&data, &queries, 10);
+        assert_eq!(result[0], vec![1, 3, 2, 0, 4]);
+        assert_eq!(result[1], vec![0, 2, 3, 1, 4]);
+
+        // Ties are broken by the lowest index
+        let data = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 0.0]];
+        let result = exact_topk(&data, &[vec![1.0, 0.0]], 2);
+        assert_eq!(result, vec![vec![0, 2]]);
+
+        // k equal to 0 returns nothing
+        assert_eq!(
+            exact_topk(&data, &[vec![1.0, 0.0]], 0),
+            vec![Vec::<usize>::new()]
+        );
+    }
+
+    /// Index returning a fixed answer for every query.
+    struct FixedIndex(Vec<usize>);
+
+    impl AnnIndex for FixedIndex {
+        fn query_k(&self, _q: &[f64], k: usize) -> Result<Vec<usize>, std::io::Error> {
+            Ok(self.0.iter().take(k).copied().collect())
+        }
+    }
+
+    // Test recall@k on an exact and on a lossy index
+    #[test]
+    fn test_recall_at_k() {
+        let queries = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        let ground_truth = vec![vec![1, 3, 2], vec![1, 3, 0]];
+
+        // The index returns the exact top-2 of every query
+        assert_eq!(
+            recall_at_k(&FixedIndex(vec![3, 1]), &queries, &ground_truth, 2),
+            1.0
+        );
+        // The index misses one of the true top-2
+        assert_eq!(
+            recall_at_k(&FixedIndex(vec![1, 2]), &queries, &ground_truth, 2),
+            0.5
+        );
+    }
+
+    // Test that a Top1 struct probing every bucket has perfect recall
+    #[test]
+    fn test_recall_at_k_top1() {
+        use crate::simple_data_structures::top1::Top1;
+        let data = vec![
+            vec![0.0, 1.0],
+            vec![1.0, 0.0],
+            vec![0.6, 0.8],
+            vec![0.8, 0.6],
+        ];
+        let queries = data.clone();
+        let mut top1 = Top1::new(data.clone(), 0.9, 0.8, 0.5);
+        top1.threshold = f64::NEG_INFINITY;
+        let ground_truth = exact_topk(&data, &queries, 2);
+        assert_eq!(recall_at_k(&top1, &queries, &ground_truth, 2), 1.0);
+    }
+
+    // Test the empirical collision rate of pairs at a known similarity against the theory
+    #[test]
+    fn test_empirical_collision_rate() {
+        use crate::utils::{collision_probability, generate_spherical_vectors};
+        let d = 16;
+        let data = generate_spherical_vectors(1000, d, 13).unwrap();
+        let index = Top1::new_with_seed(data, 0.9, 0.8, 0.1, 14);
+        let gaussian_vectors = index.projections();
+        let points = generate_spherical_vectors(3000, d, 15).unwrap();
+        let directions = generate_spherical_vectors(3000, d, 16).unwrap();
+        for similarity in [0.5, 0.9] {
+            let mut pairs = Vec::new();
+            let mut predicted = 0.;
+            for (point, direction) in points.iter().zip(&directions) {
+                // Unit vector orthogonal to the point
+                let along = dot_product(point, direction);
+                let mut orthogonal: Vec<f64> = direction
+                    .iter()
+                    .zip(point)
+                    .map(|(u, p)| u - along * p)
+                    .collect();
+                crate::utils::normalize_vector(&mut orthogonal);
+                let query: Vec<f64> = point
+                    .iter()
+                    .zip(&orthogonal)
+                    .map(|(p, u)| similarity * p + (1. - similarity * similarity).sqrt() * u)
+                    .collect();
+                let bucket = get_closest_gaussian(point, gaussian_vectors);
+                let projection = dot_product(point, &gaussian_vectors[bucket]);
+                predicted += collision_probability(similarity, projection, index.threshold);
+                pairs.push((query, point.clone()));
+            }
+            predicted /= pairs.len() as f64;
+            let rate = empirical_collision_rate(&index, &pairs);
+            assert!(
+                (rate - predicted).abs() < 0.05,
+                "rate = {}, predicted = {}",
+                rate,
+                predicted
+            );
+        }
+        assert_eq!(empirical_collision_rate(&index, &[]), 0.);
+        // A point always collides with itself if its bucket meets the threshold
+        let point = points[0].clone();
+        let bucket = get_closest_gaussian(&point, gaussian_vectors);
+        let expected = if dot_product(&point, &gaussian_vectors[bucket]) >= index.threshold {
+            1.
+        } else {
+            0.
+        };
+        assert_eq!(
+            empirical_collision_rate(&index, &[(point.clone(), point)]),
+            expected
+        );
+    }
+
+    // Test that BruteForce returns the lowest index among tied points
+    #[test]
+    fn test_brute_force_ties() {
+        let mut data = vec![vec![0.0, 1.0]; 1000];
+        for i in [700, 300, 950] {
+            data[i] = vec![1.0, 0.0];
+        }
+        let brute_force = BruteForce::new(data.clone());
+        for num_threads in [1, 4] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap();
+            assert_eq!(
+                pool.install(|| brute_force.query(&[1.0, 0.0])),
+                Some((300, 1.0))
+            );
+        }
+        assert_eq!(brute_force.query_sequential(&[1.0, 0.0]), Some((300, 1.0)));
+        // Every point ties, and NaN scores lose
+        assert_eq!(brute_force.query(&[0.0, 0.0]), Some((0, 0.0)));
+        assert_eq!(brute_force.query(&[f64::NAN, 1.0]).map(|(i, _)| i), Some(0));
+        assert_eq!(brute_force.query_k(&[1.0, 0.0], 2).unwrap(), vec![300, 700]);
+        assert_eq!(BruteForce::new(Vec::new()).query(&[1.0, 0.0]), None);
+    }
+
+    // Test the false negative rate on clustered data, where every point has close neighbors
+    #[test]
+    fn test_false_negative_rate() {
+        use crate::simple_data_structures::top1::Top1;
+        use crate::utils::generate_clustered_vectors;
+        let data = generate_clustered_vectors(20, 25, 20, 0.3, 11).unwrap();
+        let beta = 0.8;
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, beta, 0.1, 12);
+        let rate = false_negative_rate(&top1, &data, beta, 100);
+        assert!(rate < 0.3, "rate = {}", rate);
+
+        // Probing every bucket misses nothing, returning nothing misses everything
+        let mut exhaustive = top1.clone();
+        exhaustive.threshold = f64::NEG_INFINITY;
+        assert_eq!(false_negative_rate(&exhaustive, &data, beta, 100), 0.0);
+        assert_eq!(
+            false_negative_rate(&FixedIndex(vec![]), &data, beta, 100),
+            1.0
+        );
+        // No point has a neighbor
+        assert_eq!(false_negative_rate(&top1, &data, 1.1, 100), 0.0);
+    }
+}
@@ -1,10 +1,30 @@
 pub mod utils;
+pub mod ann_index;
+pub mod bloom;
 pub mod checks;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod dedup;
+pub mod error;
+pub mod eval;
+pub mod hll;
+pub mod loaders;
+pub mod metric;
+pub mod prelude;
+pub mod privacy;
+pub(crate) mod spans;
+pub mod tuning;
 
 pub mod simple_data_structures {
     pub mod top1;
     pub mod query;
     pub mod close_top1;
+    pub mod fixed_top1;
+    pub mod multi_table_top1;
+    pub mod payload_top1;
+    pub mod quantized_top1;
+    pub mod persistence;
+    pub mod windowed_counter;
 }
 
 pub mod tensor_data_structures {
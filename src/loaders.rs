@@ -0,0 +1,259 @@
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+#[cfg(feature = "serde")]
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Size in bytes of the header of a flat file: the number of vectors and their dimension.
+const FLAT_HEADER_SIZE: usize = 16;
+
+/// Vectors of a flat file mapped in memory. The rows are read directly from the file
+/// without copying them into memory.
+///
+/// The flat layout is the number of vectors `n` and their dimension `d` as little endian `u64`,
+/// followed by the `n * d` coordinates as little endian `f64`, row after row.
+pub struct MmapVectors {
+    mmap: Mmap,
+    n: usize,
+    d: usize,
+}
+
+impl MmapVectors {
+    /// Return the number of vectors.
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    /// Return true if the file holds no vector.
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    /// Return the dimension of the vectors.
+    pub fn dim(&self) -> usize {
+        self.d
+    }
+
+    /// Return the i-th vector as a slice borrowed from the mapped file.
+    pub fn row(&self, i: usize) -> &[f64] {
+        assert!(i < self.n, "Row {} out of bounds ({} rows).", i, self.n);
+        &self.coordinates()[i * self.d..(i + 1) * self.d]
+    }
+
+    /// Return an iterator over the vectors.
+    pub fn rows(&self) -> impl Iterator<Item = &[f64]> {
+        (0..self.n).map(move |i| self.row(i))
+    }
+
+    /// Copy the vectors into memory.
+    pub fn to_vecs(&self) -> Vec<Vec<f64>> {
+        self.rows().map(|row| row.to_vec()).collect()
+    }
+
+    /// Return all the coordinates as a single slice.
+    fn coordinates(&self) -> &[f64] {
+        let bytes = &self.mmap[FLAT_HEADER_SIZE..];
+        // Safety: `load_mmap` checked that the bytes are aligned for f64 and hold n * d of them,
+        // and f64 has no invalid bit pattern.
+        unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const f64, self.n * self.d) }
+    }
+}
+
+/// Save the vectors to `path` in the flat layout read by `load_mmap`.
+/// All the vectors must have the same dimension.
+pub fn save_flat<P: AsRef<Path>>(path: P, vectors: &[Vec<f64>]) -> io::Result<()> {
+    let d = vectors.first().map_or(0, |v| v.len());
+    if let Some(i) = vectors.iter().position(|v| v.len() != d) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Vector at index {} has a different dimension (expected {}).",
+                i, d
+            ),
+        ));
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&(vectors.len() as u64).to_le_bytes())?;
+    writer.write_all(&(d as u64).to_le_bytes())?;
+    for x in vectors.iter().flatten() {
+        writer.write_all(&x.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+/// Map the flat file at `path` in memory, see `MmapVectors` for the layout.
+///
+/// The file must not be modified while it is mapped.
+pub fn load_mmap<P: AsRef<Path>>(path: P) -> io::Result<MmapVectors> {
+    if cfg!(target_endian = "big") {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Memory mapped vectors require a little endian target.",
+        ));
+    }
+
+    let file = File::open(path)?;
+    // Safety: the file is only read, and the caller must not modify it while it is mapped.
+    let mmap = unsafe { Mmap::map(&file)? };
+    if mmap.len() < FLAT_HEADER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "File is too short to contain the header.",
+        ));
+    }
+    let n = u64::from_le_bytes(mmap[0..8].try_into().unwrap()) as usize;
+    let d = u64::from_le_bytes(mmap[8..16].try_into().unwrap()) as usize;
+
+    // Check the size of the file and the alignment of the coordinates
+    let expected_len = n
+        .checked_mul(d)
+        .and_then(|count| count.checked_mul(8))
+        .and_then(|size| size.checked_add(FLAT_HEADER_SIZE));
+    if expected_len != Some(mmap.len()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("File size does not match {} vectors of dimension {}.", n, d),
+        ));
+    }
+    if mmap[FLAT_HEADER_SIZE..]
+        .as_ptr()
+        .align_offset(std::mem::align_of::<f64>())
+        != 0
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Mapped coordinates are not aligned.",
+        ));
+    }
+
+    Ok(MmapVectors { mmap, n, d })
+}
+
+/// Load the vectors of the JSON Lines file at `path`, one JSON array of numbers per line.
+/// Blank lines are skipped. An error naming the line (starting from 1) is returned if a line
+/// is not an array of numbers or if its dimension differs from the first vector.
+#[cfg(feature = "serde")]
+pub fn load_jsonl<P: AsRef<Path>>(path: P) -> io::Result<Vec<Vec<f64>>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut vectors: Vec<Vec<f64>> = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let vector: Vec<f64> = serde_json::from_str(&line).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Line {} is not an array of numbers: {}.", i + 1, err),
+            )
+        })?;
+        if let Some(d) = vectors.first().map(|v| v.len()) {
+            if vector.len() != d {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Line {} has a different dimension (expected {}, got {}).",
+                        i + 1,
+                        d,
+                        vector.len()
+                    ),
+                ));
+            }
+        }
+        vectors.push(vector);
+    }
+    Ok(vectors)
+}
+
+/// Test function
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::generate_normal_gaussian_vectors;
+    use std::fs::remove_file;
+    use std::path::PathBuf;
+
+    /// Return a path in the temporary directory that is unique to the test.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ann_rust_{}_{}.bin", name, std::process::id()))
+    }
+
+    // Test that the rows of the mapped file match the vectors in memory
+    #[test]
+    fn test_load_mmap() {
+        let path = temp_path("load_mmap");
+        let vectors = generate_normal_gaussian_vectors(20, 7).unwrap();
+        save_flat(&path, &vectors).unwrap();
+
+        let mmap_vectors = load_mmap(&path).unwrap();
+        assert_eq!(mmap_vectors.len(), 20);
+        assert_eq!(mmap_vectors.dim(), 7);
+        for (i, vector) in vectors.iter().enumerate() {
+            assert_eq!(mmap_vectors.row(i), &vector[..]);
+        }
+        assert_eq!(mmap_vectors.to_vecs(), vectors);
+
+        remove_file(&path).unwrap();
+    }
+
+    // Test loading a clean JSON Lines file
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_load_jsonl() {
+        let path = temp_path("load_jsonl");
+        std::fs::write(&path, "[1.0, 0.0, -2.5]\n\n[0, 1e-3, 3]\n").unwrap();
+        let vectors = load_jsonl(&path).unwrap();
+        assert_eq!(vectors, vec![vec![1.0, 0.0, -2.5], vec![0.0, 1e-3, 3.0]]);
+
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(load_jsonl(&path).unwrap(), Vec::<Vec<f64>>::new());
+
+        remove_file(&path).unwrap();
+    }
+
+    // Test that ragged and malformed JSON Lines files are rejected with the line number
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_load_jsonl_errors() {
+        let path = temp_path("load_jsonl_errors");
+        std::fs::write(&path, "[1.0, 0.0]\n[0.0, 1.0]\n[1.0]\n").unwrap();
+        let err = load_jsonl(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Line 3"), "{}", err);
+
+        for (contents, line) in [
+            ("[1.0, 0.0]\n{\"x\": 1}\n", "Line 2"),
+            ("\"1.0\"\n", "Line 1"),
+        ] {
+            std::fs::write(&path, contents).unwrap();
+            let err = load_jsonl(&path).unwrap_err();
+            assert!(err.to_string().contains(line), "{}", err);
+        }
+        assert!(load_jsonl(temp_path("load_jsonl_missing")).is_err());
+
+        remove_file(&path).unwrap();
+    }
+
+    // Test that malformed files are rejected
+    #[test]
+    fn test_load_mmap_errors() {
+        let path = temp_path("load_mmap_errors");
+
+        // Ragged vectors cannot be saved
+        assert!(save_flat(&path, &[vec![1.0, 0.0], vec![1.0]]).is_err());
+
+        // Truncated file
+        save_flat(&path, &[vec![1.0, 0.0], vec![0.0, 1.0]]).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 8]).unwrap();
+        assert!(load_mmap(&path).is_err());
+
+        // File shorter than the header
+        std::fs::write(&path, [0u8; 4]).unwrap();
+        assert!(load_mmap(&path).is_err());
+
+        remove_file(&path).unwrap();
+    }
+}
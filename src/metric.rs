@@ -0,0 +1,94 @@
+use crate::utils::{dot_product, l1_distance, l2_distance_sq};
+use savefile_derive::Savefile;
+
+/// Criterion used to accept a candidate found in the probed buckets.
+///
+/// The Gaussian buckets are always built according to the dot product, so the data structures
+/// are tuned for cosine similarity. The other metrics filter the same candidates with a
+/// different acceptance rule, which is only an approximate retrieval for those metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Savefile)]
+pub enum Metric {
+    /// Accept a candidate whose dot product with the query is at least `beta`.
+    #[default]
+    Cosine,
+    /// Accept a candidate within Euclidean (L2) distance `radius` from the query.
+    Euclidean { radius: f64 },
+    /// Accept a candidate within Manhattan (L1) distance `radius` from the query.
+    /// The Gaussian hashing is not optimal for L1, so close points may be missed more often.
+    L1 { radius: f64 },
+    /// Accept a candidate whose cosine similarity with the query is at least `beta`. The points
+    /// and the queries need not be normalized: the closest Gaussian vector of a point does not
+    /// depend on its norm, so raw points are stored and returned as given, and the queries are
+    /// normalized for the bucketing only.
+    CosineRaw,
+}
+
+impl Metric {
+    /// Return true if `candidate` is close to `query` according to the metric.
+    /// `beta` is only used by `Metric::Cosine`.
+    pub fn accepts(&self, query: &[f64], candidate: &[f64], beta: f64) -> bool {
+        match *self {
+            Metric::Cosine => dot_product(query, candidate) >= beta,
+            Metric::Euclidean { radius } => l2_distance_sq(query, candidate) <= radius.powi(2),
+            Metric::L1 { radius } => l1_distance(query, candidate) <= radius,
+            Metric::CosineRaw => {
+                let norms = (dot_product(query, query) * dot_product(candidate, candidate)).sqrt();
+                norms > 0.0 && dot_product(query, candidate) >= beta * norms
+            }
+        }
+    }
+
+    /// Return the similarity used to rank `candidate` for `query`: their cosine similarity with
+    /// `Metric::CosineRaw` (0 if either is zero), their dot product otherwise.
+    pub fn score(&self, query: &[f64], candidate: &[f64]) -> f64 {
+        match *self {
+            Metric::CosineRaw => {
+                let norms = (dot_product(query, query) * dot_product(candidate, candidate)).sqrt();
+                if norms > 0.0 {
+                    dot_product(query, candidate) / norms
+                } else {
+                    0.0
+                }
+            }
+            _ => dot_product(query, candidate),
+        }
+    }
+
+    /// Return true if the metric requires normalized points and queries.
+    pub fn requires_normalized(&self) -> bool {
+        !matches!(self, Metric::CosineRaw)
+    }
+}
+
+/// Test function
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test the acceptance rule of each metric
+    #[test]
+    fn test_accepts() {
+        let query = vec![1.0, 0.0];
+        let candidate = vec![0.6, 0.8];
+        assert!(Metric::Cosine.accepts(&query, &candidate, 0.5));
+        assert!(!Metric::Cosine.accepts(&query, &candidate, 0.7));
+        // L2 distance is sqrt(0.8)
+        assert!(Metric::Euclidean { radius: 0.9 }.accepts(&query, &candidate, 0.0));
+        assert!(!Metric::Euclidean { radius: 0.8 }.accepts(&query, &candidate, 0.0));
+        // L1 distance is 1.2
+        assert!(Metric::L1 { radius: 1.25 }.accepts(&query, &candidate, 0.0));
+        assert!(!Metric::L1 { radius: 1.15 }.accepts(&query, &candidate, 0.0));
+        // The cosine similarity does not depend on the norms
+        assert!(Metric::CosineRaw.accepts(&[3.0, 0.0], &[1.2, 1.6], 0.5));
+        assert!(!Metric::CosineRaw.accepts(&[3.0, 0.0], &[1.2, 1.6], 0.7));
+        assert!(!Metric::CosineRaw.accepts(&[3.0, 0.0], &[0.0, 0.0], -1.0));
+    }
+
+    // Test that only the raw cosine score ignores the norms
+    #[test]
+    fn test_score() {
+        assert_eq!(Metric::Cosine.score(&[2.0, 0.0], &[0.6, 0.8]), 1.2);
+        assert!((Metric::CosineRaw.score(&[2.0, 0.0], &[1.2, 1.6]) - 0.6).abs() < 1e-12);
+        assert_eq!(Metric::CosineRaw.score(&[2.0, 0.0], &[0.0, 0.0]), 0.0);
+    }
+}
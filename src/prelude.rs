@@ -0,0 +1,8 @@
+//! Common types and functions of the crate, imported with `use ann_rust::prelude::*;`.
+
+pub use crate::ann_index::AnnIndex;
+pub use crate::error::AnnError;
+pub use crate::simple_data_structures::close_top1::CloseTop1;
+pub use crate::simple_data_structures::top1::Top1;
+pub use crate::tensor_data_structures::tensor_top1::TensorTop1;
+pub use crate::utils::{dot_product, normalize_vector, optimal_theta};
@@ -0,0 +1,426 @@
+use crate::error::BudgetError;
+use crate::simple_data_structures::top1::Top1;
+use crate::utils::normal_cdf;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+use std::io;
+
+/// Slack allowed when comparing the spent epsilon to the budget, so that rounding in the sum
+/// of the per query epsilons does not refuse the last query that fits.
+const BUDGET_TOLERANCE: f64 = 1e-9;
+
+/// Relative margin added to the standard deviation found by `analytic_gaussian_sigma`, so that
+/// the rounding errors in `gaussian_delta` never make the returned deviation too small.
+const SIGMA_ROUNDING: f64 = 1e-9;
+
+/// Draw a sample of the Laplace distribution centered at 0 with the given `scale`, by inversion
+/// of its cumulative distribution function.
+pub fn laplace_noise<R: Rng + ?Sized>(rng: &mut R, scale: f64) -> f64 {
+    // u is uniform in (-1/2, 1/2), excluding -1/2 where the logarithm diverges
+    let u: f64 = rng.gen_range(-0.5..0.5);
+    if u == -0.5 {
+        return 0.0;
+    }
+    -scale * u.signum() * (1.0 - 2.0 * u.abs()).ln()
+}
+
+/// Noise added to a count to release it privately.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseMechanism {
+    /// Laplace noise of scale `1 / epsilon`, for epsilon-differential privacy.
+    Laplace { epsilon: f64 },
+    /// Gaussian noise calibrated by the analytic Gaussian mechanism, for
+    /// (epsilon, delta)-differential privacy, see `analytic_gaussian_sigma`.
+    Gaussian { epsilon: f64, delta: f64 },
+}
+
+impl NoiseMechanism {
+    /// Return the epsilon of the mechanism.
+    pub fn epsilon(&self) -> f64 {
+        match *self {
+            NoiseMechanism::Laplace { epsilon } | NoiseMechanism::Gaussian { epsilon, .. } => {
+                epsilon
+            }
+        }
+    }
+
+    /// Return the delta of the mechanism, 0 for the Laplace mechanism.
+    pub fn delta(&self) -> f64 {
+        match *self {
+            NoiseMechanism::Laplace { .. } => 0.0,
+            NoiseMechanism::Gaussian { delta, .. } => delta,
+        }
+    }
+
+    /// Check that epsilon is positive and finite, and that delta is in (0, 1).
+    pub fn check(&self) -> Result<(), io::Error> {
+        check_epsilon(self.epsilon())?;
+        if let NoiseMechanism::Gaussian { delta, .. } = *self {
+            if !(delta > 0.0 && delta < 1.0) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("delta must be in (0, 1), got {}", delta),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the scale of the noise for a count, whose sensitivity is 1: the scale of the
+    /// Laplace distribution or the standard deviation of the Gaussian one.
+    pub fn scale(&self) -> f64 {
+        match *self {
+            NoiseMechanism::Laplace { epsilon } => 1.0 / epsilon,
+            NoiseMechanism::Gaussian { epsilon, delta } => {
+                analytic_gaussian_sigma(epsilon, delta, 1.0)
+            }
+        }
+    }
+
+    /// Draw a sample of the noise of the mechanism.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        match self {
+            NoiseMechanism::Laplace { .. } => laplace_noise(rng, self.scale()),
+            NoiseMechanism::Gaussian { .. } => Normal::new(0.0, self.scale()).unwrap().sample(rng),
+        }
+    }
+}
+
+/// Return the delta reached by Gaussian noise of standard deviation `sigma` on a query of
+/// L2 `sensitivity` at `epsilon`, by Theorem 8 of Balle and Wang (2018):
+/// `Phi(s / (2 sigma) - epsilon sigma / s) - e^epsilon Phi(-s / (2 sigma) - epsilon sigma / s)`.
+pub fn gaussian_delta(epsilon: f64, sigma: f64, sensitivity: f64) -> f64 {
+    let a = sensitivity / (2.0 * sigma);
+    let b = epsilon * sigma / sensitivity;
+    normal_cdf(a - b) - epsilon.exp() * normal_cdf(-a - b)
+}
+
+/// Return the smallest standard deviation of the Gaussian noise giving
+/// (epsilon, delta)-differential privacy to a query of L2 `sensitivity`, following the analytic
+/// Gaussian mechanism of Balle and Wang (2018). `gaussian_delta` decreases with the standard
+/// deviation, which is found by bisection and rounded up.
+pub fn analytic_gaussian_sigma(epsilon: f64, delta: f64, sensitivity: f64) -> f64 {
+    let mut high = sensitivity;
+    while gaussian_delta(epsilon, high, sensitivity) > delta {
+        high *= 2.0;
+    }
+    let mut low = 0.0;
+    for _ in 0..100 {
+        let mid = 0.5 * (low + high);
+        if gaussian_delta(epsilon, mid, sensitivity) > delta {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    high * (1.0 + SIGMA_ROUNDING)
+}
+
+/// Release `count` with epsilon-differential privacy by adding Laplace noise of scale
+/// `1 / epsilon`, since adding or removing a point changes a count by at most 1.
+/// An error is returned if `epsilon` is not positive.
+pub fn private_count<R: Rng + ?Sized>(
+    rng: &mut R,
+    count: usize,
+    epsilon: f64,
+) -> Result<f64, io::Error> {
+    private_count_with(rng, count, NoiseMechanism::Laplace { epsilon })
+}
+
+/// Like `private_count`, with the noise of `mechanism`.
+/// An error is returned if the parameters of `mechanism` are invalid, see `NoiseMechanism::check`.
+pub fn private_count_with<R: Rng + ?Sized>(
+    rng: &mut R,
+    count: usize,
+    mechanism: NoiseMechanism,
+) -> Result<f64, io::Error> {
+    mechanism.check()?;
+    Ok(count as f64 + mechanism.sample(rng))
+}
+
+/// Check that the privacy parameter `epsilon` is positive and finite.
+pub fn check_epsilon(epsilon: f64) -> Result<(), io::Error> {
+    if !(epsilon > 0.0 && epsilon.is_finite()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("epsilon must be positive, got {}", epsilon),
+        ));
+    }
+    Ok(())
+}
+
+/// Release private counts of the close points of a Top1 structure while tracking the total
+/// epsilon spent, which by sequential composition must stay within the privacy `budget`.
+/// The deltas of the Gaussian mechanism add up in `spent_delta`, which is not bounded.
+pub struct PrivateCounter<'a> {
+    pub top1: &'a Top1,
+    budget: f64,
+    spent: f64,
+    spent_delta: f64,
+    rng: StdRng,
+}
+
+impl<'a> PrivateCounter<'a> {
+    /// Constructor for a counter of `top1` with the privacy `budget`. The noise is drawn from a
+    /// generator seeded with `seed`, or from entropy if it is None.
+    /// An error is returned if `budget` is not positive.
+    pub fn new(top1: &'a Top1, budget: f64, seed: Option<u64>) -> Result<Self, io::Error> {
+        check_epsilon(budget)?;
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Ok(PrivateCounter {
+            top1,
+            budget,
+            spent: 0.0,
+            spent_delta: 0.0,
+            rng,
+        })
+    }
+
+    /// Return the privacy budget.
+    pub fn budget(&self) -> f64 {
+        self.budget
+    }
+
+    /// Return the epsilon spent so far.
+    pub fn spent(&self) -> f64 {
+        self.spent
+    }
+
+    /// Return the sum of the deltas spent so far.
+    pub fn spent_delta(&self) -> f64 {
+        self.spent_delta
+    }
+
+    /// Return the epsilon left to spend.
+    pub fn remaining(&self) -> f64 {
+        (self.budget - self.spent).max(0.0)
+    }
+
+    /// Release the number of close points of the query `q` with `epsilon`-differential privacy
+    /// and charge `epsilon` to the budget. Nothing is spent if an error is returned.
+    pub fn count(&mut self, q: &[f64], epsilon: f64) -> Result<f64, BudgetError> {
+        self.count_with(q, NoiseMechanism::Laplace { epsilon })
+    }
+
+    /// Like `count`, with the noise of `mechanism`, whose epsilon is charged to the budget.
+    pub fn count_with(&mut self, q: &[f64], mechanism: NoiseMechanism) -> Result<f64, BudgetError> {
+        mechanism
+            .check()
+            .map_err(|err| BudgetError::InvalidQuery(err.to_string()))?;
+        let epsilon = mechanism.epsilon();
+        if self.spent + epsilon > self.budget + BUDGET_TOLERANCE {
+            return Err(BudgetError::Exhausted {
+                budget: self.budget,
+                spent: self.spent,
+                requested: epsilon,
+            });
+        }
+        let count = self
+            .top1
+            .count_neighbors(q)
+            .map_err(|err| BudgetError::InvalidQuery(err.to_string()))?;
+        let noised = private_count_with(&mut self.rng, count, mechanism)
+            .map_err(|err| BudgetError::InvalidQuery(err.to_string()))?;
+        self.spent += epsilon;
+        self.spent_delta += mechanism.delta();
+        Ok(noised)
+    }
+
+    /// Release a private count for each query of the stream with `per_query_epsilon`. Once the
+    /// budget cannot pay for the next query, a single `BudgetError::Exhausted` is yielded and
+    /// the stream ends.
+    pub fn count_stream<'s, I>(
+        &'s mut self,
+        queries: I,
+        per_query_epsilon: f64,
+    ) -> impl Iterator<Item = Result<f64, BudgetError>> + use<'s, 'a, I>
+    where
+        I: Iterator<Item = Vec<f64>> + 's,
+    {
+        let mut exhausted = false;
+        queries.map_while(move |q| {
+            if exhausted {
+                return None;
+            }
+            let result = self.count(&q, per_query_epsilon);
+            exhausted = matches!(result, Err(BudgetError::Exhausted { .. }));
+            Some(result)
+        })
+    }
+}
+
+/// Test function for the Laplace and the Gaussian mechanisms.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // test the mean and the variance of the Laplace noise
+    #[test]
+    fn test_laplace_noise() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let samples: Vec<f64> = (0..20_000).map(|_| laplace_noise(&mut rng, 2.0)).collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance =
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+        // The Laplace distribution of scale b has mean 0 and variance 2 b^2
+        assert!(mean.abs() < 0.1, "mean = {}", mean);
+        assert!((variance - 8.0).abs() < 0.8, "variance = {}", variance);
+
+        assert!(private_count(&mut rng, 3, 0.0).is_err());
+        assert!(private_count(&mut rng, 3, f64::NAN).is_err());
+    }
+
+    // test the scale of the analytic Gaussian mechanism
+    #[test]
+    fn test_gaussian_scale() {
+        assert_eq!(NoiseMechanism::Laplace { epsilon: 0.5 }.scale(), 2.0);
+        for (epsilon, delta) in [(0.1, 1e-5), (0.5, 1e-5), (1.0, 1e-3), (3.0, 1e-6)] {
+            let sigma = NoiseMechanism::Gaussian { epsilon, delta }.scale();
+            // sigma is the smallest standard deviation reaching delta
+            assert!(gaussian_delta(epsilon, sigma, 1.0) <= delta);
+            assert!(gaussian_delta(epsilon, 0.99 * sigma, 1.0) > delta);
+            // The scale is linear in the sensitivity
+            assert!(
+                (analytic_gaussian_sigma(epsilon, delta, 3.0) - 3.0 * sigma).abs() < 1e-6 * sigma
+            );
+            if epsilon < 1.0 {
+                // The analytic mechanism improves on the classical sqrt(2 ln(1.25 / delta)) / epsilon
+                let classical = (2.0 * (1.25 / delta).ln()).sqrt() / epsilon;
+                assert!(
+                    sigma < classical,
+                    "sigma = {}, classical = {}",
+                    sigma,
+                    classical
+                );
+            }
+        }
+        let invalid = [
+            NoiseMechanism::Gaussian {
+                epsilon: 1.0,
+                delta: 0.0,
+            },
+            NoiseMechanism::Gaussian {
+                epsilon: 1.0,
+                delta: 1.0,
+            },
+            NoiseMechanism::Gaussian {
+                epsilon: -1.0,
+                delta: 1e-5,
+            },
+        ];
+        for mechanism in invalid {
+            assert!(mechanism.check().is_err());
+        }
+    }
+
+    // test the analytic Gaussian mechanism against reference deviations, computed from
+    // Theorem 8 of Balle and Wang (2018) with 50 significant digits
+    #[test]
+    fn test_gaussian_reference_sigma() {
+        let references = [
+            (0.1, 1e-5, 30.749566132),
+            (0.5, 1e-5, 7.03182667558),
+            (1.0, 1e-5, 3.73063163482),
+            (1.0, 1e-3, 2.57465701864),
+            (3.0, 1e-6, 1.54386141778),
+            (10.0, 1e-10, 0.683043967227),
+            (0.01, 1e-8, 412.35694208),
+        ];
+        for (epsilon, delta, reference) in references {
+            let sigma = analytic_gaussian_sigma(epsilon, delta, 1.0);
+            // Rounded up, and close to the reference
+            assert!(
+                sigma >= reference * (1.0 - 1e-10),
+                "{} < {}",
+                sigma,
+                reference
+            );
+            assert!(
+                sigma <= reference * (1.0 + 1e-8),
+                "{} > {}",
+                sigma,
+                reference
+            );
+        }
+    }
+
+    // test that the mean of the Gaussian noised counts tracks the true count
+    #[test]
+    fn test_gaussian_mean() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mechanism = NoiseMechanism::Gaussian {
+            epsilon: 1.0,
+            delta: 1e-5,
+        };
+        let samples: Vec<f64> = (0..20_000)
+            .map(|_| private_count_with(&mut rng, 50, mechanism).unwrap())
+            .collect();
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let variance =
+            samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+        let sigma = mechanism.scale();
+        assert!((mean - 50.0).abs() < 0.1 * sigma, "mean = {}", mean);
+        assert!(
+            (variance / sigma.powi(2) - 1.0).abs() < 0.05,
+            "variance = {}",
+            variance
+        );
+
+        let data = crate::utils::generate_spherical_vectors(500, 3, 10).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.5, 0.1, 11);
+        let mut counter = PrivateCounter::new(&top1, 1.0, Some(12)).unwrap();
+        let mechanism = NoiseMechanism::Gaussian {
+            epsilon: 0.25,
+            delta: 1e-6,
+        };
+        for q in data.iter().take(4) {
+            counter.count_with(q, mechanism).unwrap();
+        }
+        assert!(counter.remaining() < 1e-9);
+        assert!((counter.spent_delta() - 4e-6).abs() < 1e-12);
+        assert!(matches!(
+            counter.count_with(&data[0], mechanism),
+            Err(BudgetError::Exhausted { .. })
+        ));
+    }
+
+    // test that a stream longer than the budget yields counts, then one error, then ends
+    #[test]
+    fn test_count_stream() {
+        let data = crate::utils::generate_spherical_vectors(500, 3, 7).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.5, 0.1, 8);
+        let mut counter = PrivateCounter::new(&top1, 1.0, Some(9)).unwrap();
+        // A budget of 1 pays for 10 queries with epsilon 0.1
+        let results: Vec<Result<f64, BudgetError>> = counter
+            .count_stream(data.clone().into_iter().take(20), 0.1)
+            .collect();
+        assert_eq!(results.len(), 11);
+        for (result, q) in results.iter().zip(data.iter()).take(10) {
+            let count = top1.count_neighbors(q).unwrap() as f64;
+            // The noise of scale 1 / 0.1 exceeds 100 with probability e^-10
+            assert!((result.as_ref().unwrap() - count).abs() < 100.0);
+        }
+        assert!(matches!(results[10], Err(BudgetError::Exhausted { .. })));
+        assert!(counter.remaining() < 1e-9);
+
+        // An invalid query spends nothing
+        let mut counter = PrivateCounter::new(&top1, 1.0, Some(9)).unwrap();
+        assert!(matches!(
+            counter.count(&[2.0, 0.0, 0.0], 0.5),
+            Err(BudgetError::InvalidQuery(_))
+        ));
+        assert!(matches!(
+            counter.count(&data[0], 0.0),
+            Err(BudgetError::InvalidQuery(_))
+        ));
+        assert_eq!(counter.spent(), 0.0);
+        assert_eq!(counter.budget(), 1.0);
+        assert!(PrivateCounter::new(&top1, -1.0, None).is_err());
+    }
+}
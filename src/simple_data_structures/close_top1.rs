@@ -1,11 +1,13 @@
-use crate::utils::{generate_normal_gaussian_vectors, dot_product, get_threshold};
+use super::query::{query, query_confident, query_ref, query_unchecked, ConfidentQuery};
 use crate::checks::check_input;
-use super::query::query;
-use rand_distr::num_traits::Pow;
+use crate::metric::Metric;
+use crate::utils::{compute_m, dot_product, generate_normal_gaussian_vectors, get_threshold};
 use std::collections::HashMap;
+use std::fmt;
 use std::io;
 
 
+#[derive(Clone)]
 pub struct CloseTop1 {
     pub gaussian_vectors: Vec<Vec<f64>>,
     pub hash_table: HashMap<usize, Vec<Vec<f64>>>,
@@ -13,6 +15,7 @@ pub struct CloseTop1 {
     pub beta: f64,
     pub threshold: f64,
     pub m: usize,
+    pub d: usize,
 }
 
 impl CloseTop1 {
@@ -26,7 +29,7 @@ impl CloseTop1 {
 
         let d = data[0].len(); // Dimension of the vectors
         let n = data.len(); // Number of vectors in the data
-        let m = (n as f64).pow(theta / (1. - alpha.powf(2.))).ceil() as usize; // Number of Gaussian vectors
+        let m = compute_m(n, alpha, theta); // Number of Gaussian vectors
 
         // Generate Gaussian vectors
         println!("Generating {} Gaussian vectors...", m);
@@ -43,30 +46,104 @@ impl CloseTop1 {
             alpha,
             beta,
             m,
+            d,
             threshold: get_threshold(alpha, m),
         }
     }
 
     /// Given a query `q`, return a close point according to dot product.
-    pub fn query(&self, q: &Vec<f64>) -> Result<Option<Vec<f64>>, io::Error> {
+    pub fn query(&self, q: &[f64]) -> Result<Option<Vec<f64>>, io::Error> {
         query(
             &self.gaussian_vectors,
             q,
             self.threshold,
             &self.hash_table,
             self.beta,
+            Metric::Cosine,
         )
     }
+
+    /// Given a query `q`, return a reference to a close point according to dot product,
+    /// avoiding the copy of the vector made by `query`.
+    pub fn query_ref(&self, q: &[f64]) -> Result<Option<&Vec<f64>>, io::Error> {
+        query_ref(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.beta,
+            Metric::Cosine,
+        )
+    }
+
+    /// Given a query `q`, return a close point according to dot product without checking
+    /// that `q` is normalized. The caller must guarantee it: garbage in, garbage out.
+    pub fn query_unchecked(&self, q: &[f64]) -> Option<Vec<f64>> {
+        query_unchecked(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.beta,
+            Metric::Cosine,
+        )
+    }
+
+    /// Given a query `q`, return a close point according to dot product, or the confidence
+    /// of the negative answer if no close point is found.
+    pub fn query_confident(&self, q: &[f64]) -> Result<ConfidentQuery, io::Error> {
+        query_confident(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.beta,
+            Metric::Cosine,
+        )
+    }
+
+    /// Return the number of points stored in the hash table.
+    pub fn len(&self) -> usize {
+        self.hash_table.values().map(|bucket| bucket.len()).sum()
+    }
+
+    /// Return true if no point is stored in the hash table.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the dimension of the indexed vectors.
+    pub fn dim(&self) -> usize {
+        self.d
+    }
+}
+
+/// Summarize the parameters of the structure instead of dumping its vectors.
+impl fmt::Debug for CloseTop1 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CloseTop1")
+            .field("n", &self.len())
+            .field("d", &self.d)
+            .field("m", &self.m)
+            .field("alpha", &self.alpha)
+            .field("beta", &self.beta)
+            .field("threshold", &self.threshold)
+            .field("num_buckets", &self.hash_table.len())
+            .finish()
+    }
 }
 
 /// For each vector in `data`, find the Gaussian vector with the highest dot product.
 /// Store the result in a `HashMap` where the key is the index of the Gaussian vector and
 /// the value is the list of data vectors that are closest to it.
 fn get_hash_table(
-    data: &Vec<Vec<f64>>,
-    gaussian_vectors: &Vec<Vec<f64>>,
+    data: &[Vec<f64>],
+    gaussian_vectors: &[Vec<f64>],
 ) -> HashMap<usize, Vec<Vec<f64>>> {
-    let mut closest_gaussian_vectors: HashMap<usize, Vec<Vec<f64>>> = HashMap::new();
+    // There is at most one bucket per Gaussian vector and per point
+    let capacity = gaussian_vectors.len().min(data.len());
+    let mut closest_gaussian_vectors: HashMap<usize, Vec<Vec<f64>>> =
+        HashMap::with_capacity(capacity);
 
     let m = gaussian_vectors.len() as f64;
     let ln_m = m.ln();
@@ -85,7 +162,7 @@ fn get_hash_table(
                 // Insert or update the list of data vectors for the closest Gaussian vector
                 closest_gaussian_vectors
                     .entry(i)
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push(data_vector.clone());
                 break;
             }
@@ -146,6 +223,23 @@ mod tests {
         assert!(result.is_err());
     }
 
+    /// Test function to check the len, is_empty and dim accessors.
+    /// Only the points that pass the filter are stored, so `len` is at most the input size.
+    #[test]
+    fn test_close_top1_len_and_dim() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let close_top1 = CloseTop1::new(data, 0.9, 0.8, 0.5);
+        let count_hash: usize = close_top1.hash_table.values().map(|v| v.len()).sum();
+        assert_eq!(close_top1.len(), count_hash);
+        assert!(close_top1.len() <= 3);
+        assert_eq!(close_top1.is_empty(), count_hash == 0);
+        assert_eq!(close_top1.dim(), 3);
+    }
+
     /// Test function to check if the get_hash_table function works.
     #[test]
     fn test_close_top_1_get_hash_table() {
@@ -202,4 +296,28 @@ mod tests {
             assert!(flag);
         }
     }
+
+    /// Test function to check that Debug prints the parameters without the vectors.
+    #[test]
+    fn test_debug_summary() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let top1 = CloseTop1::new(data, 0.9, 0.8, 0.5);
+        let debug = format!("{:?}", top1);
+        assert!(debug.starts_with("CloseTop1 { n: "), "{}", debug);
+        for field in [
+            "d: 3",
+            "m: ",
+            "alpha: 0.9",
+            "beta: 0.8",
+            "threshold: ",
+            "num_buckets: ",
+        ] {
+            assert!(debug.contains(field), "{}", debug);
+        }
+        assert!(!debug.contains("1.0, 0.0"), "{}", debug);
+    }
 }
\ No newline at end of file
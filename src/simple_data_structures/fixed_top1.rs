@@ -0,0 +1,168 @@
+use super::top1::get_closest_gaussian;
+use crate::checks::check_parameters;
+use crate::utils::{
+    compute_m, dot_product, generate_normal_gaussian_vectors,
+    generate_normal_gaussian_vectors_seeded, get_threshold, is_normalized,
+};
+use std::collections::HashMap;
+use std::io;
+
+/// Top1 structure for vectors of a dimension `D` fixed at compile time. Points are stored as
+/// arrays, without a heap allocation per point, and the dot products have a constant length
+/// the compiler can unroll and vectorize.
+#[derive(Clone)]
+pub struct Top1Fixed<const D: usize> {
+    pub gaussian_vectors: Vec<[f64; D]>,
+    pub hash_table: HashMap<usize, Vec<[f64; D]>>,
+    pub alpha: f64,
+    pub beta: f64,
+    pub threshold: f64,
+    pub m: usize,
+}
+
+impl<const D: usize> Top1Fixed<D> {
+    /// Constructor for the Top1Fixed struct.
+    pub fn new(data: Vec<[f64; D]>, alpha: f64, beta: f64, theta: f64) -> Self {
+        // Check inputs
+        if let Err(err) = check_fixed_input(&data, alpha, beta, theta) {
+            eprintln!("Input validation failed: {}", err);
+        }
+        let m = compute_m(data.len(), alpha, theta);
+        Self::build(
+            data,
+            alpha,
+            beta,
+            generate_normal_gaussian_vectors(m, D).unwrap(),
+        )
+    }
+
+    /// Constructor with Gaussian vectors drawn from a seeded generator. The same seed gives the
+    /// same Gaussian vectors as `Top1::new_with_seed`.
+    pub fn new_with_seed(
+        data: Vec<[f64; D]>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        seed: u64,
+    ) -> Self {
+        // Check inputs
+        if let Err(err) = check_fixed_input(&data, alpha, beta, theta) {
+            eprintln!("Input validation failed: {}", err);
+        }
+        let m = compute_m(data.len(), alpha, theta);
+        let gaussian_vectors = generate_normal_gaussian_vectors_seeded(m, D, seed).unwrap();
+        Self::build(data, alpha, beta, gaussian_vectors)
+    }
+
+    fn build(data: Vec<[f64; D]>, alpha: f64, beta: f64, gaussian_vectors: Vec<Vec<f64>>) -> Self {
+        let gaussian_vectors: Vec<[f64; D]> = gaussian_vectors
+            .into_iter()
+            .map(|v| v.try_into().unwrap())
+            .collect();
+        let m = gaussian_vectors.len();
+
+        // Insert each point in the bucket of its closest Gaussian vector
+        let mut hash_table: HashMap<usize, Vec<[f64; D]>> = HashMap::new();
+        for point in data {
+            let index = get_closest_gaussian(&point, &gaussian_vectors);
+            hash_table.entry(index).or_default().push(point);
+        }
+
+        Top1Fixed {
+            gaussian_vectors,
+            hash_table,
+            alpha,
+            beta,
+            threshold: get_threshold(alpha, m),
+            m,
+        }
+    }
+
+    /// Given a query `q`, return a point with dot product at least `beta` with the query among
+    /// the buckets of the Gaussian vectors that meet the threshold.
+    /// If the query is not normalized, an error is returned.
+    pub fn query(&self, q: &[f64; D]) -> Result<Option<&[f64; D]>, io::Error> {
+        if !is_normalized(q) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Query vector is not normalized",
+            ));
+        }
+        let close_vector = self
+            .gaussian_vectors
+            .iter()
+            .enumerate()
+            .filter(|(_, gaussian_vector)| dot_product(q, *gaussian_vector) >= self.threshold)
+            .filter_map(|(i, _)| self.hash_table.get(&i))
+            .flat_map(|vectors| vectors.iter())
+            .find(|vector| dot_product(q, *vector) >= self.beta);
+        Ok(close_vector)
+    }
+
+    /// Return the number of points stored in the hash table.
+    pub fn len(&self) -> usize {
+        self.hash_table.values().map(|bucket| bucket.len()).sum()
+    }
+
+    /// Return true if no point is stored in the hash table.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Like `check_input`, for arrays: the dimension is checked by the type.
+fn check_fixed_input<const D: usize>(
+    data: &[[f64; D]],
+    alpha: f64,
+    beta: f64,
+    theta: f64,
+) -> Result<(), String> {
+    check_parameters(alpha, beta, theta)?;
+    if data.is_empty() {
+        return Err("Data cannot be empty.".to_string());
+    }
+    if D == 0 {
+        return Err("Vectors cannot have zero dimensions.".to_string());
+    }
+    for (i, vector) in data.iter().enumerate() {
+        if let Some(j) = vector.iter().position(|x| !x.is_finite()) {
+            return Err(format!(
+                "Vector at index {} has a non finite coordinate at index {} (value = {}).",
+                i, j, vector[j]
+            ));
+        }
+        if !is_normalized(vector) {
+            return Err(format!("Vector at index {} is not normalized.", i));
+        }
+    }
+    Ok(())
+}
+
+/// Test function for Top1Fixed struct.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_data_structures::top1::Top1;
+    use crate::utils::generate_spherical_vectors;
+
+    // test that Top1Fixed answers queries as Top1 on the same data and Gaussian vectors
+    #[test]
+    fn test_query_parity() {
+        let data = generate_spherical_vectors(200, 3, 24).unwrap();
+        let fixed_data: Vec<[f64; 3]> =
+            data.iter().map(|v| v.clone().try_into().unwrap()).collect();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.5, 0.1, 25);
+        let top1_fixed = Top1Fixed::new_with_seed(fixed_data, 0.9, 0.5, 0.1, 25);
+        assert_eq!(top1_fixed.m, top1.m);
+        assert_eq!(top1_fixed.len(), 200);
+
+        let queries = generate_spherical_vectors(50, 3, 26).unwrap();
+        for q in queries {
+            let expected = top1.query(&q).unwrap();
+            let q: [f64; 3] = q.try_into().unwrap();
+            let result = top1_fixed.query(&q).unwrap().map(|v| v.to_vec());
+            assert_eq!(result, expected);
+        }
+        assert!(top1_fixed.query(&[2.0, 0.0, 0.0]).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use super::top1::{Top1, Top1Config};
+use crate::dedup::{Dedup, DedupStrategy};
+use crate::error::AnnError;
+use std::collections::HashSet;
+use std::io;
+
+/// Classic multi-table LSH: `L` independent Top1 structures built on the same data with
+/// different seeds. A query probes the buckets of every table and takes the union of their
+/// candidates, which raises the recall at the cost of `L` times the memory. This differs from
+/// TensorTop1, whose composite buckets are the intersection of the buckets of its structures.
+/// A point has the same id, its index in the data, in every table.
+#[derive(Clone)]
+pub struct MultiTableTop1 {
+    pub tables: Vec<Top1>,
+    pub beta: f64,
+}
+
+impl MultiTableTop1 {
+    /// Constructor building `l` tables, the i-th with seed `seed + i`.
+    /// An error is returned if `l` is 0.
+    pub fn new(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        l: usize,
+        seed: u64,
+    ) -> Result<Self, AnnError> {
+        let config = Top1Config {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        Self::with_config(data, alpha, beta, theta, l, config)
+    }
+
+    /// Constructor building `l` tables with the options of `config`, e.g. the metric or sorted
+    /// buckets. If `config.seed` is set, the i-th table is built with seed `seed + i`.
+    /// Each table stores its own copy of the data, moved into the last one.
+    /// An error is returned if `l` is 0.
+    pub fn with_config(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        l: usize,
+        config: Top1Config,
+    ) -> Result<Self, AnnError> {
+        if l == 0 {
+            return Err(AnnError::InvalidParameter("l must be positive".to_string()));
+        }
+        let table_config = |i: usize| Top1Config {
+            seed: config.seed.map(|seed| seed.wrapping_add(i as u64)),
+            ..config.clone()
+        };
+        let mut tables: Vec<Top1> = (0..l - 1)
+            .map(|i| Top1::with_config(data.clone(), alpha, beta, theta, table_config(i)))
+            .collect();
+        tables.push(Top1::with_config(
+            data,
+            alpha,
+            beta,
+            theta,
+            table_config(l - 1),
+        ));
+        Ok(MultiTableTop1 { tables, beta })
+    }
+
+    /// Given a query `q`, return the point with the highest score with the query (see
+    /// `Metric::score`) among the close points of the union of the candidates of the tables,
+    /// see `Top1::neighbors`. A point found in several tables is scored once.
+    pub fn query(&self, q: &[f64]) -> Result<Option<Vec<f64>>, io::Error> {
+        let Some(first) = self.tables.first() else {
+            return Ok(None);
+        };
+        // The tables share the dimension and the metric
+        let q = &*first.check_query(q)?;
+        let mut seen = HashSet::new();
+        let mut best: Option<(f64, &Vec<f64>)> = None;
+        for table in &self.tables {
+            for id in table.neighbors(q)? {
+                if !seen.insert(id) {
+                    continue;
+                }
+                let Some(vector) = table.point(id) else {
+                    continue;
+                };
+                let score = table.metric.score(q, vector);
+                if best.is_none_or(|(best_score, _)| score > best_score) {
+                    best = Some((score, vector));
+                }
+            }
+        }
+        Ok(best.map(|(_, vector)| vector.clone()))
+    }
+
+    /// Given a query `q`, count the close points (see `Top1::neighbors`) among the union of
+    /// the candidates of the tables, collapsing the points found in several tables according
+    /// to `strategy`.
+    pub fn count_neighbors(&self, q: &[f64], strategy: DedupStrategy) -> Result<usize, io::Error> {
+        let mut dedup = Dedup::new(strategy);
+        let mut count = 0;
+        for table in &self.tables {
+            for id in table.neighbors(q)? {
+                let Some(vector) = table.point(id) else {
+                    continue;
+                };
+                if dedup.insert(id, vector)? {
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Return the number of indexed points.
+    pub fn len(&self) -> usize {
+        self.tables.first().map_or(0, |table| table.len())
+    }
+
+    /// Return true if no point is indexed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Test function for MultiTableTop1 struct.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metric::Metric;
+    use crate::utils::{
+        dot_product, generate_normal_gaussian_vectors_seeded, generate_spherical_vectors,
+        normalize_vector,
+    };
+
+    // test that 4 tables find more close points than a single table
+    #[test]
+    fn test_multi_table_recall() {
+        let data = generate_spherical_vectors(300, 8, 37).unwrap();
+        // Queries close to a stored point
+        let noise = generate_normal_gaussian_vectors_seeded(300, 8, 38).unwrap();
+        let queries: Vec<Vec<f64>> = data
+            .iter()
+            .zip(&noise)
+            .map(|(v, e)| {
+                let mut q: Vec<f64> = v.iter().zip(e).map(|(x, y)| x + 0.15 * y).collect();
+                normalize_vector(&mut q);
+                q
+            })
+            .collect();
+
+        let single = Top1::new_with_seed(data.clone(), 0.9, 0.8, 0.1, 39);
+        let multi = MultiTableTop1::new(data.clone(), 0.9, 0.8, 0.1, 4, 39).unwrap();
+        assert_eq!(multi.len(), 300);
+        let recall = |found: Vec<Option<Vec<f64>>>| found.iter().filter(|p| p.is_some()).count();
+        let single_recall = recall(queries.iter().map(|q| single.query(q).unwrap()).collect());
+        let multi_recall = recall(queries.iter().map(|q| multi.query(q).unwrap()).collect());
+        assert!(multi_recall > single_recall);
+        for q in queries.iter().take(20) {
+            if let Some(point) = multi.query(q).unwrap() {
+                assert!(dot_product(q, &point) >= 0.8);
+            }
+        }
+        assert!(multi.query(&[2.0; 8]).is_err());
+
+        // Sorted buckets only skip points that are not close
+        let config = Top1Config {
+            seed: Some(39),
+            sorted_buckets: true,
+            ..Default::default()
+        };
+        let sorted = MultiTableTop1::with_config(data, 0.9, 0.8, 0.1, 4, config).unwrap();
+        for q in queries.iter().take(50) {
+            let count = multi.count_neighbors(q, DedupStrategy::ByValue).unwrap();
+            assert_eq!(
+                sorted.count_neighbors(q, DedupStrategy::ByValue).unwrap(),
+                count
+            );
+        }
+    }
+
+    // test that the candidates are ranked by the metric of the tables
+    #[test]
+    fn test_query_metric_score() {
+        // The second point has the largest dot product with the query, the first the largest
+        // cosine similarity
+        let data = vec![
+            vec![0.1, 0.0, 0.0],
+            vec![9.0, 10.0 * 0.19f64.sqrt(), 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let config = Top1Config { seed: Some(41), metric: Metric::CosineRaw, ..Default::default() };
+        let mut multi =
+            MultiTableTop1::with_config(data.clone(), 0.95, 0.8, 0.5, 2, config).unwrap();
+        for table in multi.tables.iter_mut() {
+            table.threshold = f64::NEG_INFINITY;
+        }
+        assert_eq!(multi.query(&[3.0, 0.0, 0.0]).unwrap(), Some(data[0].clone()));
+        assert_eq!(multi.query(&[0.0, 0.0, 2.0]).unwrap(), Some(data[2].clone()));
+    }
+
+    // test that at least one table is required
+    #[test]
+    fn test_no_table() {
+        let data = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        assert!(MultiTableTop1::new(data, 0.9, 0.5, 0.5, 0, 1).is_err());
+    }
+
+    // test the count of each dedup strategy when every point is reached in every table
+    #[test]
+    fn test_count_neighbors_dedup_strategies() {
+        // The first two points are equal
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![1.0, 0.0, 0.0],
+            vec![0.8, 0.6, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let mut multi = MultiTableTop1::new(data, 0.9, 0.5, 0.5, 3, 40).unwrap();
+        // Probe every bucket of every table
+        for table in multi.tables.iter_mut() {
+            table.threshold = f64::NEG_INFINITY;
+        }
+        let q = vec![1.0, 0.0, 0.0];
+        assert_eq!(multi.count_neighbors(&q, DedupStrategy::None).unwrap(), 9);
+        assert_eq!(
+            multi.count_neighbors(&q, DedupStrategy::ByIndex).unwrap(),
+            3
+        );
+        assert_eq!(
+            multi.count_neighbors(&q, DedupStrategy::ByValue).unwrap(),
+            2
+        );
+        let keys = [0, 1, 0, 1];
+        assert_eq!(
+            multi
+                .count_neighbors(&q, DedupStrategy::ByKey(&keys))
+                .unwrap(),
+            2
+        );
+        assert!(multi
+            .count_neighbors(&q, DedupStrategy::ByKey(&keys[..2]))
+            .is_err());
+        assert!(multi
+            .count_neighbors(&[2.0, 0.0, 0.0], DedupStrategy::None)
+            .is_err());
+    }
+}
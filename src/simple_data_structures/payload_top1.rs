@@ -0,0 +1,189 @@
+use super::top1::Top1;
+use std::io;
+
+/// Top1 structure that stores an opaque payload with each point, e.g. the id of the embedding
+/// in a database. Queries return the payload of the close point instead of the vector.
+#[derive(Clone)]
+pub struct PayloadTop1<P> {
+    pub top1: Top1,
+    // Payload of each point, indexed by the id of the point in `top1`
+    pub payloads: Vec<P>,
+}
+
+impl Top1 {
+    /// Constructor for a Top1 struct storing a payload with each point.
+    pub fn new_with_payloads<P>(
+        data: Vec<(Vec<f64>, P)>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+    ) -> PayloadTop1<P> {
+        let (vectors, payloads): (Vec<Vec<f64>>, Vec<P>) = data.into_iter().unzip();
+        PayloadTop1 {
+            top1: Top1::new(vectors, alpha, beta, theta),
+            payloads,
+        }
+    }
+
+    /// Constructor for a Top1 struct storing an external u64 id with each point, e.g. the row
+    /// index of the point in an upstream system. The ids need not be contiguous.
+    pub fn new_indexed(
+        data: impl IntoIterator<Item = (u64, Vec<f64>)>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+    ) -> PayloadTop1<u64> {
+        let data = data.into_iter().map(|(id, vector)| (vector, id)).collect();
+        Top1::new_with_payloads(data, alpha, beta, theta)
+    }
+}
+
+impl PayloadTop1<u64> {
+    /// Given a query `q`, return the external id of a close point and a copy of the point.
+    pub fn query_id(&self, q: &[f64]) -> Result<Option<(u64, Vec<f64>)>, io::Error> {
+        let result = self.top1.query_id_point(q)?;
+        Ok(result.map(|(id, _, point)| (self.payloads[id], point.clone())))
+    }
+}
+
+impl<P> PayloadTop1<P> {
+    /// Given a query `q`, return the dot product of a close point with the query and its
+    /// payload.
+    pub fn query_payload(&self, q: &[f64]) -> Result<Option<(f64, &P)>, io::Error> {
+        let result = self.top1.query_id(q)?;
+        Ok(result.map(|(id, dot_product)| (dot_product, &self.payloads[id])))
+    }
+
+    /// Insert a new point with its payload.
+    /// If the point is not normalized, an error is returned.
+    pub fn insert(&mut self, p: Vec<f64>, payload: P) -> Result<(), io::Error> {
+        self.top1.insert(p)?;
+        self.payloads.push(payload);
+        Ok(())
+    }
+
+    /// Drop every stored point for which `keep`, given the point and its payload, returns
+    /// false. The payloads of the dropped points are kept, so that the ids stay valid.
+    pub fn retain(&mut self, keep: impl Fn(&[f64], &P) -> bool) {
+        let payloads = &self.payloads;
+        self.top1
+            .retain_with_ids(|id, point| keep(point, &payloads[id]));
+    }
+
+    /// Return a close point to the stored point with payload `id`, other than the point itself
+    /// ("more like this"). The id of the point is found by a scan of the payloads, and the
+    /// point itself in constant time, see `Top1::point`.
+    /// An error is returned if no stored point has this payload.
+    pub fn query_by_id(&self, id: &P) -> Result<Option<Vec<f64>>, io::Error>
+    where
+        P: PartialEq,
+    {
+        let found = self
+            .payloads
+            .iter()
+            .enumerate()
+            .filter(|(_, payload)| *payload == id)
+            .find_map(|(point_id, _)| Some((point_id, self.top1.point(point_id)?)));
+        let (point_id, point) = found.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "No stored point has this payload")
+        })?;
+        let neighbor = self.top1.query_id_excluding(point, point_id)?;
+        Ok(neighbor.map(|(neighbor_id, _)| self.top1.point(neighbor_id).unwrap().clone()))
+    }
+
+    /// Return the number of indexed points.
+    pub fn len(&self) -> usize {
+        self.top1.len()
+    }
+
+    /// Return true if no point is indexed.
+    pub fn is_empty(&self) -> bool {
+        self.top1.is_empty()
+    }
+}
+
+/// Test function for PayloadTop1 struct.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test function to check that queries return the payload of the close point.
+    #[test]
+    fn test_query_payload() {
+        let data = vec![
+            (vec![1.0, 0.0, 0.0], String::from("x")),
+            (vec![0.0, 1.0, 0.0], String::from("y")),
+            (vec![0.0, 0.0, 1.0], String::from("z")),
+        ];
+        let mut index = Top1::new_with_payloads(data, 0.9, 0.8, 0.5);
+        // Probe every bucket so that the answer does not depend on the Gaussian vectors
+        index.top1.threshold = f64::NEG_INFINITY;
+        assert_eq!(index.len(), 3);
+
+        let q = vec![0.0, 1.0, 0.0];
+        let (dot_product, payload) = index.query_payload(&q).unwrap().unwrap();
+        assert_eq!(payload, "y");
+        assert_eq!(dot_product, 1.0);
+
+        // Inserted points keep their payload
+        let p = vec![(0.5f64).sqrt(), 0.0, (0.5f64).sqrt()];
+        index.insert(p.clone(), String::from("xz")).unwrap();
+        let (_, payload) = index.query_payload(&p).unwrap().unwrap();
+        assert_eq!(payload, "xz");
+
+        // Retain on the payloads
+        index.retain(|_, payload| payload != "y");
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.query_payload(&q).unwrap(), None);
+
+        // Bad query
+        assert!(index.query_payload(&[2.0, 0.0, 0.0]).is_err());
+    }
+
+    /// Test function to check that query_id returns the external id of the close point.
+    #[test]
+    fn test_query_id_indexed() {
+        let data = vec![
+            (1000, vec![1.0, 0.0, 0.0]),
+            (7, vec![0.0, 1.0, 0.0]),
+            (424242, vec![0.0, 0.0, 1.0]),
+        ];
+        let mut index = Top1::new_indexed(data, 0.9, 0.8, 0.5);
+        index.top1.threshold = f64::NEG_INFINITY;
+
+        assert_eq!(
+            index.query_id(&[0.0, 1.0, 0.0]).unwrap(),
+            Some((7, vec![0.0, 1.0, 0.0]))
+        );
+        assert_eq!(
+            index.query_id(&[0.0, 0.0, 1.0]).unwrap(),
+            Some((424242, vec![0.0, 0.0, 1.0]))
+        );
+        assert_eq!(index.query_id(&[1.0, 0.0, 0.0]).unwrap().unwrap().0, 1000);
+        let far = [(0.5f64).sqrt(), (0.5f64).sqrt(), 0.0];
+        assert_eq!(index.query_id(&far).unwrap(), None);
+        assert!(index.query_id(&[2.0, 0.0, 0.0]).is_err());
+    }
+
+    /// Test function to check that query_by_id finds a neighbor other than the point itself.
+    #[test]
+    fn test_query_by_id() {
+        let tilted = (0.99f64, (1.0 - 0.99f64.powi(2)).sqrt());
+        let data = vec![
+            (vec![1.0, 0.0, 0.0], 10),
+            (vec![tilted.0, tilted.1, 0.0], 11),
+            (vec![0.0, 0.0, 1.0], 12),
+        ];
+        let mut index = Top1::new_with_payloads(data, 0.9, 0.8, 0.5);
+        index.top1.threshold = f64::NEG_INFINITY;
+
+        assert_eq!(
+            index.query_by_id(&10).unwrap(),
+            Some(vec![tilted.0, tilted.1, 0.0])
+        );
+        assert_eq!(index.query_by_id(&11).unwrap(), Some(vec![1.0, 0.0, 0.0]));
+        // The only point in its direction has no neighbor but itself
+        assert_eq!(index.query_by_id(&12).unwrap(), None);
+        assert!(index.query_by_id(&13).is_err());
+    }
+}
@@ -0,0 +1,539 @@
+use super::top1::{locate_ids, DriftStats, Top1};
+use crate::metric::Metric;
+use rand::Rng;
+use savefile::prelude::*;
+use savefile_derive::Savefile;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Version of the snapshot format written by `Top1::save`.
+/// Bump it whenever the layout of `Top1Snapshot` changes, and annotate the new fields with
+/// the first version that stores them, so that older snapshots can still be loaded.
+const SNAPSHOT_VERSION: u32 = 7;
+
+/// Version of the projection file format written by `Top1::export_projections_bin`.
+const PROJECTIONS_VERSION: u32 = 0;
+
+/// Serializable copy of a Top1 struct.
+#[derive(Savefile)]
+struct Top1Snapshot {
+    gaussian_vectors: Vec<Vec<f64>>,
+    hash_table: HashMap<usize, Vec<Vec<f64>>>,
+    #[savefile_versions = "1.."]
+    id_table: HashMap<usize, Vec<usize>>,
+    alpha: f64,
+    beta: f64,
+    threshold: f64,
+    m: usize,
+    d: usize,
+    #[savefile_versions = "2.."]
+    metric: Metric,
+    #[savefile_versions = "1.."]
+    next_id: usize,
+    #[savefile_versions = "3.."]
+    sorted_buckets: bool,
+    #[savefile_versions = "4.."]
+    theta: Option<f64>,
+    #[savefile_versions = "4.."]
+    tuned_n: usize,
+    #[savefile_versions = "4.."]
+    max_m: Option<usize>,
+    #[savefile_versions = "4.."]
+    auto_rebuild_factor: Option<f64>,
+    #[savefile_versions = "4.."]
+    #[savefile_default_fn = "random_seed"]
+    rehash_seed: u64,
+    #[savefile_versions = "5.."]
+    bloom_filters: bool,
+    #[savefile_versions = "6.."]
+    threshold_override: Option<f64>,
+    #[savefile_versions = "7.."]
+    drift: Option<DriftStats>,
+}
+
+/// Seed of the next rebuild of a snapshot that does not store one.
+fn random_seed() -> u64 {
+    rand::thread_rng().gen()
+}
+
+impl Top1 {
+    /// Save a snapshot of the Top1 struct to `path`.
+    ///
+    /// The snapshot contains every inserted point, so the log next to the snapshot,
+    /// `<path>.wal`, is truncated once the snapshot is written. An attached log of another
+    /// snapshot is left untouched, so that snapshot still reopens with every point, and the
+    /// later inserts keep being appended to it.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let snapshot = Top1Snapshot {
+            gaussian_vectors: self.gaussian_vectors.clone(),
+            hash_table: self.hash_table.clone(),
+            id_table: self.id_table.clone(),
+            alpha: self.alpha,
+            beta: self.beta,
+            threshold: self.threshold,
+            m: self.m,
+            d: self.d,
+            metric: self.metric,
+            next_id: self.next_id,
+            sorted_buckets: self.sorted_buckets(),
+            theta: self.theta,
+            tuned_n: self.tuned_n,
+            max_m: self.max_m,
+            auto_rebuild_factor: self.auto_rebuild_factor,
+            rehash_seed: self.rehash_seed,
+            bloom_filters: self.bloom_filters(),
+            threshold_override: self.threshold_override,
+            drift: self.drift.clone(),
+        };
+        save_file(path, SNAPSHOT_VERSION, &snapshot)
+            .map_err(|e| io::Error::other(format!("Failed to save file: {}", e)))?;
+
+        // The log only holds points inserted after the previous snapshot
+        let wal_path = wal_path(path);
+        if wal_path.exists() {
+            File::create(&wal_path)?;
+        }
+        Ok(())
+    }
+
+    /// Load a snapshot saved with `save` from `path`, replay the write-ahead log `<path>.wal`
+    /// (if present) and attach the log, so that every subsequent `insert` is appended to it.
+    ///
+    /// Points inserted after the last `save` are therefore recovered even if the process
+    /// crashes before saving again. Replaying does not modify the log, so opening the same
+    /// snapshot several times always produces the same structure.
+    ///
+    /// Every replayed point is checked like an inserted one, so a log with points of the wrong
+    /// dimension (or not normalized, if the metric requires it) returns an error. The rebuilds
+    /// that `Top1Config::auto_rebuild_factor` triggered after the snapshot are run again at
+    /// the same points, so the reopened structure matches the one that wrote the log.
+    ///
+    /// Note that a clone of the returned structure appends to the same log.
+    pub fn open_with_wal<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut snapshot: Top1Snapshot = load_file(path, SNAPSHOT_VERSION).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Failed to load file: {}", e),
+            )
+        })?;
+        // Snapshots of version 0 have no ids: number the points bucket by bucket
+        if snapshot.id_table.is_empty() {
+            let mut indices: Vec<usize> = snapshot.hash_table.keys().copied().collect();
+            indices.sort_unstable();
+            for index in indices {
+                let len = snapshot.hash_table[&index].len();
+                let ids = (snapshot.next_id..snapshot.next_id + len).collect();
+                snapshot.id_table.insert(index, ids);
+                snapshot.next_id += len;
+            }
+        }
+        // Snapshots older than version 4 do not store the number of points `m` was tuned for
+        if snapshot.tuned_n == 0 {
+            snapshot.tuned_n = snapshot.id_table.values().map(|ids| ids.len()).sum();
+        }
+        let mut top1 = Top1 {
+            gaussian_vectors: snapshot.gaussian_vectors,
+            hash_table: snapshot.hash_table,
+            locations: locate_ids(&snapshot.id_table),
+            id_table: snapshot.id_table,
+            alpha: snapshot.alpha,
+            beta: snapshot.beta,
+            threshold: snapshot.threshold,
+            m: snapshot.m,
+            d: snapshot.d,
+            threshold_override: snapshot.threshold_override,
+            metric: snapshot.metric,
+            next_id: snapshot.next_id,
+            wal_path: None,
+            bucket_projections: None,
+            bloom_filters: None,
+            theta: snapshot.theta,
+            tuned_n: snapshot.tuned_n,
+            max_m: snapshot.max_m,
+            auto_rebuild_factor: snapshot.auto_rebuild_factor,
+            drift: snapshot.drift,
+            rehash_seed: snapshot.rehash_seed,
+        };
+        // The buckets are saved sorted, so this only recomputes the projections
+        if snapshot.sorted_buckets {
+            top1.sort_buckets();
+        }
+        if snapshot.bloom_filters {
+            top1.build_bloom_filters();
+        }
+
+        // Replay the log in insertion order
+        let wal_path = wal_path(path);
+        if wal_path.exists() {
+            for point in read_wal(&wal_path, top1.d)? {
+                top1.check_point(&point)?;
+                if let Some(drift) = top1.drift.as_mut() {
+                    drift.update(&point);
+                }
+                top1.insert_in_memory(point);
+                top1.auto_rebuild();
+            }
+        }
+
+        top1.wal_path = Some(wal_path);
+        Ok(top1)
+    }
+
+    /// Save the Gaussian vectors of the structure to `path`, to rebuild a structure with the
+    /// same random projections with `from_projections` and `load_projections`.
+    pub fn export_projections_bin<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        save_file(path.as_ref(), PROJECTIONS_VERSION, &self.gaussian_vectors)
+            .map_err(|e| io::Error::other(format!("Failed to save file: {}", e)))
+    }
+
+    /// Load Gaussian vectors saved with `export_projections_bin` from `path`.
+    pub fn load_projections<P: AsRef<Path>>(path: P) -> io::Result<Vec<Vec<f64>>> {
+        load_file(path.as_ref(), PROJECTIONS_VERSION).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Failed to load file: {}", e),
+            )
+        })
+    }
+
+    /// Append `point` to the write-ahead log, if one is attached.
+    pub(super) fn append_to_wal(&self, point: &[f64]) -> io::Result<()> {
+        let wal_path = match &self.wal_path {
+            None => return Ok(()),
+            Some(wal_path) => wal_path,
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(wal_path)?;
+        let mut writer = BufWriter::new(file);
+
+        // Each record is the dimension followed by the coordinates, in little endian
+        writer.write_all(&(point.len() as u64).to_le_bytes())?;
+        for x in point {
+            writer.write_all(&x.to_le_bytes())?;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_data()
+    }
+}
+
+/// Return the path of the write-ahead log associated to the snapshot at `path`.
+fn wal_path(path: &Path) -> PathBuf {
+    let mut wal_path: OsString = path.as_os_str().to_owned();
+    wal_path.push(".wal");
+    PathBuf::from(wal_path)
+}
+
+/// Read all the points stored in the write-ahead log at `path`.
+/// A truncated last record (e.g. a crash during the write) is ignored, while a record whose
+/// dimension is not `d` returns an error.
+fn read_wal(path: &Path, d: usize) -> io::Result<Vec<Vec<f64>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut points = Vec::new();
+    let mut buffer = [0u8; 8];
+    loop {
+        // Read the dimension of the record
+        match reader.read_exact(&mut buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        // Check the dimension before allocating, a corrupted record could claim any size
+        let found = u64::from_le_bytes(buffer);
+        if found != d as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Log record has dimension {} (expected {})", found, d),
+            ));
+        }
+
+        // Read the coordinates of the record
+        let mut point = Vec::with_capacity(d);
+        for _ in 0..d {
+            match reader.read_exact(&mut buffer) {
+                Ok(()) => point.push(f64::from_le_bytes(buffer)),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(points),
+                Err(e) => return Err(e),
+            }
+        }
+        points.push(point);
+    }
+    Ok(points)
+}
+
+/// Test function
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simple_data_structures::top1::Top1Config;
+    use std::fs::remove_file;
+
+    /// Return a path in the temporary directory that is unique to the test.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ann_rust_{}_{}.bin", name, std::process::id()))
+    }
+
+    // Test that exported projections reproduce the queries of the structure
+    #[test]
+    fn test_export_projections() {
+        let path = temp_path("projections");
+        let data = crate::utils::generate_spherical_vectors(200, 3, 34).unwrap();
+        let top1 = Top1::new(data.clone(), 0.9, 0.5, 0.1);
+        top1.export_projections_bin(&path).unwrap();
+        let projections = Top1::load_projections(&path).unwrap();
+        remove_file(&path).unwrap();
+        assert_eq!(projections, top1.projections());
+
+        let replay = Top1::from_projections(data.clone(), 0.9, 0.5, projections).unwrap();
+        assert_eq!(replay.hash_table, top1.hash_table);
+        for q in data.iter().take(20) {
+            assert_eq!(replay.query(q).unwrap(), top1.query(q).unwrap());
+        }
+        assert!(Top1::load_projections(temp_path("missing_projections")).is_err());
+    }
+
+    // Test that inserted points survive a crash through the write-ahead log
+    #[test]
+    fn test_wal_replay() {
+        let path = temp_path("wal_replay");
+        let data = vec![
+            vec![1.0, 0.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0, 0.0],
+            vec![0.0, 0.0, 1.0, 0.0],
+        ];
+        let mut top1 = Top1::new(data, 0.9, 0.8, 0.5);
+        // Probe every bucket so that the queries are deterministic
+        top1.threshold = f64::NEG_INFINITY;
+        top1.save(&path).unwrap();
+
+        // Insert a point and drop the structure without saving
+        let new_point = vec![0.0, 0.0, 0.0, 1.0];
+        let mut top1 = Top1::open_with_wal(&path).unwrap();
+        top1.insert(new_point.clone()).unwrap();
+        drop(top1);
+
+        // Reopen: the point is recovered from the log
+        let top1 = Top1::open_with_wal(&path).unwrap();
+        assert_eq!(top1.len(), 4);
+        assert_eq!(top1.query(&new_point).unwrap(), Some(new_point.clone()));
+
+        // Replaying is idempotent
+        let reopened = Top1::open_with_wal(&path).unwrap();
+        assert_eq!(reopened.hash_table, top1.hash_table);
+        assert_eq!(reopened.id_table, top1.id_table);
+
+        // Saving truncates the log, and the snapshot now holds the point
+        top1.save(&path).unwrap();
+        assert!(read_wal(&wal_path(&path), 4).unwrap().is_empty());
+        let reopened = Top1::open_with_wal(&path).unwrap();
+        assert_eq!(reopened.len(), 4);
+
+        remove_file(&path).unwrap();
+        remove_file(wal_path(&path)).unwrap();
+    }
+
+    // Test that the replay runs the rebuilds the inserts triggered after the snapshot
+    #[test]
+    fn test_wal_replay_auto_rebuild() {
+        let path = temp_path("wal_replay_auto_rebuild");
+        let data = crate::utils::generate_spherical_vectors(60, 3, 49).unwrap();
+        let config = Top1Config { seed: Some(50), auto_rebuild_factor: Some(1.5), ..Default::default() };
+        Top1::with_config(data[..20].to_vec(), 0.9, 0.5, 0.3, config).save(&path).unwrap();
+
+        // The inserts cross the growth factor twice, and the structure crashes without saving
+        let mut top1 = Top1::open_with_wal(&path).unwrap();
+        let built = top1.projections().to_vec();
+        for p in &data[20..] {
+            top1.insert(p.clone()).unwrap();
+        }
+        assert_ne!(top1.projections(), &built[..]);
+
+        let reopened = Top1::open_with_wal(&path).unwrap();
+        assert_eq!(reopened.m, top1.m);
+        assert_eq!(reopened.projections(), top1.projections());
+        assert_eq!(reopened.hash_table, top1.hash_table);
+        assert_eq!(reopened.id_table, top1.id_table);
+
+        remove_file(&path).unwrap();
+        remove_file(wal_path(&path)).unwrap();
+    }
+
+    // Test that a loaded snapshot keeps its options and rebuilds like the saved structure
+    #[test]
+    fn test_snapshot_rebuild_parameters() {
+        let path = temp_path("rebuild_parameters");
+        let data = crate::utils::generate_spherical_vectors(100, 3, 47).unwrap();
+        let config = Top1Config {
+            seed: Some(48),
+            max_m: Some(6),
+            auto_rebuild_factor: Some(3.0),
+            bloom_filters: true,
+            ..Default::default()
+        };
+        let mut top1 = Top1::with_config(data, 0.9, 0.5, 0.3, config);
+        top1.set_threshold_override(Some(0.4));
+        top1.save(&path).unwrap();
+        let mut loaded = Top1::open_with_wal(&path).unwrap();
+        assert_eq!(loaded.theta, Some(0.3));
+        assert_eq!(loaded.max_m, Some(6));
+        assert_eq!(loaded.auto_rebuild_factor, Some(3.0));
+        assert_eq!(loaded.tuned_n, 100);
+        assert!(loaded.bloom_filters());
+        assert_eq!(loaded.threshold_override(), Some(0.4));
+
+        top1.rebuild();
+        loaded.rebuild();
+        assert_eq!(loaded.m, top1.m);
+        assert_eq!(loaded.threshold, 0.4);
+        assert_eq!(loaded.gaussian_vectors, top1.gaussian_vectors);
+        remove_file(&path).unwrap();
+    }
+
+    // Test that the drift statistics are saved and follow the replayed points
+    #[test]
+    fn test_drift_persisted() {
+        let path = temp_path("drift");
+        let data = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        let config = Top1Config {
+            seed: Some(49),
+            track_drift: true,
+            ..Default::default()
+        };
+        let mut top1 = Top1::with_config(data, 0.9, 0.8, 0.5, config);
+        top1.insert(vec![1.0, 0.0]).unwrap();
+        top1.save(&path).unwrap();
+        let mut reopened = Top1::open_with_wal(&path).unwrap();
+        assert_eq!(reopened.drift_stats(), top1.drift_stats());
+
+        // A point in the log is tracked when replayed
+        reopened.insert(vec![0.0, 1.0]).unwrap();
+        let replayed = Top1::open_with_wal(&path).unwrap();
+        assert_eq!(replayed.drift_stats(), reopened.drift_stats());
+        assert_eq!(replayed.drift_stats().unwrap().points_since_build, 2);
+
+        remove_file(&path).unwrap();
+        remove_file(wal_path(&path)).unwrap();
+    }
+
+    // Test that a snapshot of an older version can still be loaded
+    #[test]
+    fn test_load_old_snapshot() {
+        let path = temp_path("old_snapshot");
+        let data = vec![vec![1.0, 0.0], vec![0.0, 1.0], vec![-1.0, 0.0]];
+        let top1 = Top1::new(data, 0.9, 0.8, 0.5);
+        let snapshot = Top1Snapshot {
+            gaussian_vectors: top1.gaussian_vectors.clone(),
+            hash_table: top1.hash_table.clone(),
+            id_table: top1.id_table.clone(),
+            alpha: top1.alpha,
+            beta: top1.beta,
+            threshold: top1.threshold,
+            m: top1.m,
+            d: top1.d,
+            metric: Metric::CosineRaw,
+            next_id: top1.next_id,
+            sorted_buckets: true,
+            theta: Some(0.5),
+            tuned_n: 3,
+            max_m: Some(7),
+            auto_rebuild_factor: Some(2.0),
+            rehash_seed: 1,
+            bloom_filters: true,
+            threshold_override: Some(0.5),
+            drift: None,
+        };
+        // Version 0 only stores the fields present before the ids were added
+        save_file(&path, 0, &snapshot).unwrap();
+
+        let loaded = Top1::open_with_wal(&path).unwrap();
+        assert_eq!(loaded.hash_table, top1.hash_table);
+        assert_eq!(loaded.metric, Metric::default());
+        assert!(!loaded.sorted_buckets());
+        assert!(!loaded.bloom_filters());
+        assert_eq!(loaded.threshold_override(), None);
+        let mut ids: Vec<usize> = loaded.id_table.values().flatten().copied().collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert_eq!(loaded.next_id, 3);
+        remove_file(&path).unwrap();
+    }
+
+    // Test that a truncated record at the end of the log is ignored
+    #[test]
+    fn test_read_truncated_wal() {
+        let path = temp_path("truncated_wal");
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&2u64.to_le_bytes());
+        bytes.extend_from_slice(&1.0f64.to_le_bytes());
+        bytes.extend_from_slice(&0.0f64.to_le_bytes());
+        bytes.extend_from_slice(&2u64.to_le_bytes());
+        bytes.extend_from_slice(&1.0f64.to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+
+        assert_eq!(read_wal(&path, 2).unwrap(), vec![vec![1.0, 0.0]]);
+        remove_file(&path).unwrap();
+    }
+
+    // Test that a corrupted log returns an error instead of being replayed
+    #[test]
+    fn test_corrupted_wal() {
+        let path = temp_path("corrupted_wal");
+        let data = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        Top1::new(data, 0.9, 0.8, 0.5).save(&path).unwrap();
+
+        // A record with a huge dimension is rejected before allocating it
+        std::fs::write(wal_path(&path), u64::MAX.to_le_bytes()).unwrap();
+        assert!(read_wal(&wal_path(&path), 2).is_err());
+        assert!(Top1::open_with_wal(&path).is_err());
+
+        // A record of the right dimension that is not normalized is rejected
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&2u64.to_le_bytes());
+        bytes.extend_from_slice(&2.0f64.to_le_bytes());
+        bytes.extend_from_slice(&0.0f64.to_le_bytes());
+        std::fs::write(wal_path(&path), bytes).unwrap();
+        assert!(Top1::open_with_wal(&path).is_err());
+
+        remove_file(&path).unwrap();
+        remove_file(wal_path(&path)).unwrap();
+    }
+
+    // Test that saving to a new path keeps the attached log, so both snapshots reopen whole
+    #[test]
+    fn test_save_keeps_attached_wal() {
+        let path = temp_path("attached_wal");
+        let copy = temp_path("attached_wal_copy");
+        let data = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        Top1::new(data, 0.9, 0.8, 0.5).save(&path).unwrap();
+        // A stale log of the copy is dropped by the save
+        let mut bytes = 2u64.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&0.0f64.to_le_bytes());
+        bytes.extend_from_slice(&(-1.0f64).to_le_bytes());
+        std::fs::write(wal_path(&copy), bytes).unwrap();
+        let mut top1 = Top1::open_with_wal(&path).unwrap();
+        top1.insert(vec![-1.0, 0.0]).unwrap();
+        assert_eq!(read_wal(&wal_path(&path), 2).unwrap().len(), 1);
+
+        // Saving elsewhere leaves the attached log alone, which keeps receiving the inserts
+        top1.save(&copy).unwrap();
+        top1.insert(vec![0.6, 0.8]).unwrap();
+        assert_eq!(read_wal(&wal_path(&path), 2).unwrap().len(), 2);
+        let reopened = Top1::open_with_wal(&path).unwrap();
+        assert_eq!(reopened.len(), 4);
+        assert!(reopened.contains(&[-1.0, 0.0]) && reopened.contains(&[0.6, 0.8]));
+        let reopened_copy = Top1::open_with_wal(&copy).unwrap();
+        assert_eq!(reopened_copy.len(), 3);
+        assert!(reopened_copy.contains(&[-1.0, 0.0]) && !reopened_copy.contains(&[0.0, -1.0]));
+
+        remove_file(&path).unwrap();
+        remove_file(wal_path(&path)).unwrap();
+        remove_file(&copy).unwrap();
+        remove_file(wal_path(&copy)).unwrap();
+    }
+}
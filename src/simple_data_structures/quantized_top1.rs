@@ -0,0 +1,150 @@
+use super::top1::Top1;
+use crate::utils::is_normalized;
+use std::io;
+
+/// Gaussian coordinates are clipped to [-GAUSSIAN_RANGE, GAUSSIAN_RANGE] before quantization.
+/// A standard normal coordinate falls outside this range with probability below 1e-4.
+const GAUSSIAN_RANGE: f64 = 4.0;
+
+/// Top1 structure whose `search` runs on `i8` quantized projections for speed.
+///
+/// The Gaussian vectors are quantized with scale `127 / GAUSSIAN_RANGE` and the query, whose
+/// coordinates lie in [-1, 1], with scale `127`. Their integer dot product divided by the
+/// product of the scales approximates the `f64` dot product with an absolute error of order
+/// `sqrt(d) / 127`, so Gaussian vectors whose projection is close to the threshold may be
+/// routed differently than with `Top1`. The points of the probed buckets are still scored
+/// with `f64` dot products against `beta`, so a returned point is always close.
+#[derive(Clone)]
+pub struct QuantizedTop1 {
+    pub top1: Top1,
+    // Quantized Gaussian vectors
+    pub quantized_gaussian_vectors: Vec<Vec<i8>>,
+    // Scale of the quantized Gaussian vectors
+    pub gaussian_scale: f64,
+}
+
+impl QuantizedTop1 {
+    /// Constructor for the QuantizedTop1 struct.
+    pub fn new(data: Vec<Vec<f64>>, alpha: f64, beta: f64, theta: f64) -> Self {
+        Self::from_top1(Top1::new(data, alpha, beta, theta))
+    }
+
+    /// Quantize the Gaussian vectors of an existing Top1 structure.
+    pub fn from_top1(top1: Top1) -> Self {
+        let gaussian_scale = i8::MAX as f64 / GAUSSIAN_RANGE;
+        let quantized_gaussian_vectors = top1
+            .gaussian_vectors
+            .iter()
+            .map(|gaussian_vector| quantize(gaussian_vector, gaussian_scale))
+            .collect();
+        QuantizedTop1 {
+            top1,
+            quantized_gaussian_vectors,
+            gaussian_scale,
+        }
+    }
+
+    /// Given a query `q`, return a close point according to dot product.
+    pub fn query(&self, q: &[f64]) -> Result<Option<Vec<f64>>, io::Error> {
+        // Check if the query vector is normalized
+        if !is_normalized(q) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Query vector is not normalized",
+            ));
+        }
+
+        // Search for a close vector in the buckets of the Gaussian vectors that meet the threshold
+        for i in self.search(q) {
+            if let Some(vectors) = self.top1.hash_table.get(&i) {
+                let close_vector = vectors
+                    .iter()
+                    .find(|v| self.top1.metric.accepts(q, v, self.top1.beta));
+                if let Some(close_vector) = close_vector {
+                    return Ok(Some(close_vector.clone()));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Return the indices of the Gaussian vectors whose integer dot product with the quantized
+    /// query meets the rescaled threshold.
+    pub fn search(&self, q: &[f64]) -> Vec<usize> {
+        let query_scale = i8::MAX as f64;
+        let quantized_query = quantize(q, query_scale);
+        let threshold = self.top1.threshold * self.gaussian_scale * query_scale;
+        self.quantized_gaussian_vectors
+            .iter()
+            .enumerate()
+            .filter(|(_, gaussian_vector)| {
+                integer_dot_product(&quantized_query, gaussian_vector) as f64 >= threshold
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+/// Quantize a vector to `i8` with the given scale, clipping the out of range coordinates.
+fn quantize(vector: &[f64], scale: f64) -> Vec<i8> {
+    vector
+        .iter()
+        .map(|x| (x * scale).round().clamp(i8::MIN as f64, i8::MAX as f64) as i8)
+        .collect()
+}
+
+/// Computes the dot product of two quantized vectors.
+fn integer_dot_product(vec1: &[i8], vec2: &[i8]) -> i32 {
+    vec1.iter()
+        .zip(vec2.iter())
+        .map(|(&a, &b)| a as i32 * b as i32)
+        .sum()
+}
+
+/// Test function for QuantizedTop1 struct.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::dot_product;
+
+    /// Test function to check the quantization helpers.
+    #[test]
+    fn test_quantize() {
+        assert_eq!(
+            quantize(&[1.0, -0.5, 0.0, 3.0], 127.),
+            vec![127, -64, 0, 127]
+        );
+        assert_eq!(integer_dot_product(&[1, -2, 3], &[4, 5, -6]), -24);
+    }
+
+    /// Test function to check that the quantized routing finds the same close points as the
+    /// f64 version on well separated data.
+    #[test]
+    fn test_quantized_query_matches_top1() {
+        // Well separated data: the axes of the space
+        let d = 16;
+        let data: Vec<Vec<f64>> = (0..d)
+            .map(|i| (0..d).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+            .collect();
+        let mut agreements = 0;
+        for seed in 0..20 {
+            let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.8, 0.3, seed);
+            let quantized_top1 = QuantizedTop1::from_top1(top1.clone());
+            for q in data.iter() {
+                let expected = top1.query(q).unwrap();
+                let result = quantized_top1.query(q).unwrap();
+                // Any returned point is beta-close
+                if let Some(point) = &result {
+                    assert!(dot_product(q, point) >= 0.8);
+                }
+                if result == expected {
+                    agreements += 1;
+                }
+            }
+        }
+        // Only Gaussian vectors within the quantization error of the threshold are routed
+        // differently
+        let agreement = agreements as f64 / (20 * d) as f64;
+        assert!(agreement >= 0.9, "agreement = {}", agreement);
+    }
+}
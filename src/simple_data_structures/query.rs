@@ -1,15 +1,489 @@
-use crate::utils::{dot_product, is_normalized, find_close_vector};
-use std::collections::HashMap;
+use crate::hll::HyperLogLog;
+use crate::metric::Metric;
+use crate::spans;
+use crate::utils;
+use crate::utils::{
+    collision_probability, dot_product, is_normalized, l2_distance_sq, normal_quantile,
+};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::io;
+use std::ops::Range;
+
+/// Buckets with at least this many points are scanned in parallel, smaller buckets are
+/// scanned sequentially to avoid the overhead of Rayon.
+pub const PARALLEL_SCAN_THRESHOLD: usize = 10_000;
+
+/// Given a query `q`, count the distinct ids of the points of the probed buckets, without
+/// checking their dot product with the query: bucket membership is the acceptance criterion.
+pub fn count_bucket_members(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    id_table: &HashMap<usize, Vec<usize>>,
+) -> Result<usize, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let members: HashSet<usize> = utils::search(gaussian_vectors, query, threshold)
+        .iter()
+        .filter_map(|i| id_table.get(i))
+        .flatten()
+        .copied()
+        .collect();
+    Ok(members.len())
+}
+
+/// Given a query `q`, return the total size of the probed buckets, i.e. the number of points
+/// a full scan of the probed buckets scores, without scoring them.
+pub fn expected_candidates(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+) -> Result<usize, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let candidates = utils::search(gaussian_vectors, query, threshold)
+        .iter()
+        .filter_map(|i| hash_table.get(i))
+        .map(|bucket| bucket.len())
+        .sum();
+    Ok(candidates)
+}
+
+/// Confidence attached to a query that found no close point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoneConfidence {
+    /// Some Gaussian vectors met the threshold, but none of their buckets had a close point.
+    Confident,
+    /// No Gaussian vector met the threshold, so no bucket was probed.
+    Uncertain,
+}
+
+/// Result of a query that reports how confident a negative answer is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfidentQuery {
+    /// A close point was found.
+    Found(Vec<f64>),
+    /// No close point was found.
+    NotFound(NoneConfidence),
+}
 
 /// Given a query `q`, return a close point according to dot product.
 pub fn query(
-    gaussian_vectors: &Vec<Vec<f64>>,
-    query: &Vec<f64>,
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+) -> Result<Option<Vec<f64>>, io::Error> {
+    match query_confident(gaussian_vectors, query, threshold, hash_table, beta, metric)? {
+        ConfidentQuery::Found(close_vector) => Ok(Some(close_vector)),
+        ConfidentQuery::NotFound(_) => Ok(None),
+    }
+}
+
+/// Like `query`, but the dot products of the query with the Gaussian vectors are given as
+/// `projection_scores`, e.g. cached by the caller, instead of being computed. The buckets whose
+/// score meets the `threshold` are probed, and scanned with the query `q`.
+pub fn query_with_projection(
+    query: &[f64],
+    projection_scores: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+) -> Result<Option<Vec<f64>>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let _span = spans::query_span();
+    let indices: Vec<usize> = projection_scores
+        .iter()
+        .enumerate()
+        .filter(|(_, score)| **score >= threshold)
+        .map(|(i, _)| i)
+        .collect();
+    let hit = probe_buckets(&indices, query, hash_table, beta, metric, None);
+    Ok(hit.ok().map(|hit| hit.vector.clone()))
+}
+
+/// Given a query `q`, return a close point according to dot product. If no close point is
+/// found, report whether the answer is `Confident` (some buckets were probed) or `Uncertain`
+/// (the hashing probed no bucket at all).
+pub fn query_confident(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+) -> Result<ConfidentQuery, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    match probe(
+        gaussian_vectors,
+        query,
+        threshold,
+        hash_table,
+        beta,
+        metric,
+        None,
+    ) {
+        Ok(hit) => Ok(ConfidentQuery::Found(hit.vector.clone())),
+        Err(confidence) => Ok(ConfidentQuery::NotFound(confidence)),
+    }
+}
+
+/// Given a query `q`, return a reference to a close point according to dot product.
+/// The reference points into `hash_table`, which avoids copying the vector.
+pub fn query_ref<'a>(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &'a HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+) -> Result<Option<&'a Vec<f64>>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let hit = probe(
+        gaussian_vectors,
+        query,
+        threshold,
+        hash_table,
+        beta,
+        metric,
+        None,
+    );
+    Ok(hit.ok().map(|hit| hit.vector))
+}
+
+/// Given a query `q`, return a close point according to dot product without checking that
+/// the query is normalized. This saves an O(d) pass per query.
+///
+/// The caller must guarantee that `q` is normalized: a non normalized query is not rejected
+/// and silently gives meaningless results.
+pub fn query_unchecked(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
     threshold: f64,
     hash_table: &HashMap<usize, Vec<Vec<f64>>>,
     beta: f64,
+    metric: Metric,
+) -> Option<Vec<f64>> {
+    let hit = probe(
+        gaussian_vectors,
+        query,
+        threshold,
+        hash_table,
+        beta,
+        metric,
+        None,
+    );
+    hit.ok().map(|hit| hit.vector.clone())
+}
+
+/// Given a query `q`, return a close point according to dot product, probing only the buckets
+/// of the Gaussian vectors in `allowed`. This allows partitioned search, e.g. when each shard
+/// owns a range of Gaussian vectors.
+pub fn query_restricted(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+    allowed: &HashSet<usize>,
 ) -> Result<Option<Vec<f64>>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let allowed = Some(allowed);
+    let hit = probe(
+        gaussian_vectors,
+        query,
+        threshold,
+        hash_table,
+        beta,
+        metric,
+        allowed,
+    );
+    Ok(hit.ok().map(|hit| hit.vector.clone()))
+}
+
+/// Given a query `q`, return a close point according to `metric`, probing only the buckets of
+/// the `p` Gaussian vectors with the highest dot product with the query, strongest first,
+/// regardless of the threshold. This bounds the number of probed buckets.
+pub fn query_top_p(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    p: usize,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+) -> Result<Option<Vec<f64>>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let _span = spans::query_span();
+    let indices: Vec<usize> = nearest_gaussians(gaussian_vectors, query, p)
+        .into_iter()
+        .map(|(i, _)| i)
+        .collect();
+    let hit = probe_buckets(&indices, query, hash_table, beta, metric, None);
+    Ok(hit.ok().map(|hit| hit.vector.clone()))
+}
+
+/// Return the indices and dot products of the `k` Gaussian vectors with the highest dot product
+/// with the query, regardless of the threshold, by decreasing dot product. Only the `k` best
+/// are sorted, after a partial selection over the `m` dot products.
+pub fn nearest_gaussians(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    k: usize,
+) -> Vec<(usize, f64)> {
+    let mut scores: Vec<(usize, f64)> = gaussian_vectors
+        .iter()
+        .enumerate()
+        .map(|(i, gaussian_vector)| (i, dot_product(query, gaussian_vector)))
+        .collect();
+    let k = k.min(scores.len());
+    if k == 0 {
+        return Vec::new();
+    }
+    let by_score = |a: &(usize, f64), b: &(usize, f64)| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0));
+    if k < scores.len() {
+        scores.select_nth_unstable_by(k - 1, by_score);
+        scores.truncate(k);
+    }
+    scores.sort_unstable_by(by_score);
+    scores
+}
+
+/// Given a query `q`, return a close point according to `metric`, scanning only the probed
+/// buckets with at least `min_bucket_size` points, e.g. to ignore tiny noisy buckets.
+pub fn query_min_bucket(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+    min_bucket_size: usize,
+) -> Result<Option<Vec<f64>>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let _span = spans::query_span();
+    let Some(indices) = search(gaussian_vectors, query, threshold) else {
+        spans::record_query(0, 0);
+        return Ok(None);
+    };
+    // Only the probed buckets are filtered by size
+    let large_buckets: Vec<usize> = indices
+        .into_iter()
+        .filter(|i| {
+            hash_table
+                .get(i)
+                .is_some_and(|vectors| vectors.len() >= min_bucket_size)
+        })
+        .collect();
+    let hit = probe_buckets(&large_buckets, query, hash_table, beta, metric, None);
+    Ok(hit.ok().map(|hit| hit.vector.clone()))
+}
+
+/// Given a query `q`, return the id of a close point according to dot product and its dot
+/// product with the query. `id_table` holds the ids of the points of each bucket, in the same
+/// order as `hash_table`.
+pub fn query_id(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    id_table: &HashMap<usize, Vec<usize>>,
+    beta: f64,
+    metric: Metric,
+) -> Result<Option<(usize, f64)>, io::Error> {
+    let hit = query_id_point(
+        gaussian_vectors,
+        query,
+        threshold,
+        hash_table,
+        id_table,
+        beta,
+        metric,
+    )?;
+    Ok(hit.map(|(id, dot_product, _)| (id, dot_product)))
+}
+
+/// Id of a close point, its dot product with the query and a reference to the point.
+pub type IdHit<'a> = (usize, f64, &'a Vec<f64>);
+
+/// Like `query_id`, but a reference to the close point is also returned.
+pub fn query_id_point<'a>(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &'a HashMap<usize, Vec<Vec<f64>>>,
+    id_table: &HashMap<usize, Vec<usize>>,
+    beta: f64,
+    metric: Metric,
+) -> Result<Option<IdHit<'a>>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let hit = match probe(
+        gaussian_vectors,
+        query,
+        threshold,
+        hash_table,
+        beta,
+        metric,
+        None,
+    ) {
+        Ok(hit) => hit,
+        Err(_) => return Ok(None),
+    };
+    let id = id_table[&hit.bucket][hit.position];
+    Ok(Some((id, metric.score(query, hit.vector), hit.vector)))
+}
+
+/// Like `query_id`, but the point with id `excluded` is never returned, e.g. to find the
+/// neighbors of a stored point.
+#[allow(clippy::too_many_arguments)]
+pub fn query_id_excluding(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    id_table: &HashMap<usize, Vec<usize>>,
+    beta: f64,
+    metric: Metric,
+    excluded: usize,
+) -> Result<Option<(usize, f64)>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let indices = match search(gaussian_vectors, query, threshold) {
+        None => return Ok(None), // No matching Gaussian vectors
+        Some(indices) => indices,
+    };
+    for i in indices {
+        let (Some(vectors), Some(ids)) = (hash_table.get(&i), id_table.get(&i)) else {
+            continue;
+        };
+        let hit = vectors
+            .iter()
+            .zip(ids)
+            .find(|(vector, id)| **id != excluded && metric.accepts(query, vector, beta));
+        if let Some((vector, id)) = hit {
+            return Ok(Some((*id, metric.score(query, vector))));
+        }
+    }
+    Ok(None)
+}
+
+/// Reusable scratch buffers for `query_with_ctx`, which are cleared rather than reallocated
+/// between queries. A context must not be shared between threads: create one per thread.
+#[derive(Debug, Clone, Default)]
+pub struct QueryContext {
+    // Indices of the Gaussian vectors that meet the threshold
+    gaussian_hits: Vec<usize>,
+}
+
+impl QueryContext {
+    /// Create an empty context.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Given a query `q`, return a reference to a close point according to dot product, like
+/// `query_ref`, using the scratch buffers of `ctx` to avoid allocating on each call.
+pub fn query_with_ctx<'a>(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &'a HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+    ctx: &mut QueryContext,
+) -> Result<Option<&'a Vec<f64>>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let _span = spans::query_span();
+    search_into(gaussian_vectors, query, threshold, &mut ctx.gaussian_hits);
+    let hit = probe_buckets(&ctx.gaussian_hits, query, hash_table, beta, metric, None);
+    Ok(hit.ok().map(|hit| hit.vector))
+}
+
+/// Given a query `q`, return a reference to a point with dot product at least `beta` with the
+/// query, in buckets sorted by decreasing dot product of their points with the normalized
+/// Gaussian vector. `bucket_projections` holds these sorted dot products.
+///
+/// If the query and a point have dot products `a` and `b` with the normalized Gaussian vector,
+/// their dot product is at most `cos(acos(a) - acos(b))`, so only the points with
+/// `b` in `[cos(acos(a) + acos(beta)), cos(acos(a) - acos(beta))]` can be close. This range is
+/// found by binary search, and the first close point is the same as with a full scan.
+pub fn query_sorted<'a>(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &'a HashMap<usize, Vec<Vec<f64>>>,
+    bucket_projections: &HashMap<usize, Vec<f64>>,
+    beta: f64,
+) -> Result<Option<&'a Vec<f64>>, io::Error> {
     // Check if the query vector is normalized
     if !is_normalized(query) {
         return Err(io::Error::new(
@@ -23,33 +497,562 @@ pub fn query(
         Some(indices) => indices,
     };
 
-    // Search for a close vector in the hash table
     for i in indices {
+        let (vectors, projections) = match (hash_table.get(&i), bucket_projections.get(&i)) {
+            (Some(vectors), Some(projections)) => (vectors, projections),
+            _ => continue,
+        };
+        let range = sorted_range(query, &gaussian_vectors[i], projections, beta);
+        if let Some(close_vector) = vectors[range]
+            .iter()
+            .find(|v| dot_product(query, v) >= beta)
+        {
+            return Ok(Some(close_vector));
+        }
+    }
+    Ok(None)
+}
+
+/// Return the positions of the points of a sorted bucket that can have dot product at least
+/// `beta` with the query, see `query_sorted`. `projections` holds the dot products of the
+/// points with the normalized `gaussian_vector`, in decreasing order.
+fn sorted_range(
+    query: &[f64],
+    gaussian_vector: &[f64],
+    projections: &[f64],
+    beta: f64,
+) -> Range<usize> {
+    // Slack on the range, against rounding errors
+    let epsilon = 1e-9;
+    let beta_angle = beta.clamp(-1., 1.).acos();
+    let norm = dot_product(gaussian_vector, gaussian_vector).sqrt();
+    let a = dot_product(query, gaussian_vector) / norm;
+    let query_angle = a.clamp(-1., 1.).acos();
+    let high = (query_angle - beta_angle).max(0.).cos() + epsilon;
+    let low = (query_angle + beta_angle).min(std::f64::consts::PI).cos() - epsilon;
+
+    // The projections are sorted in decreasing order
+    let start = projections.partition_point(|&b| b > high);
+    let end = projections.partition_point(|&b| b >= low);
+    start..end.max(start)
+}
+
+/// Close point found by `probe`.
+struct Hit<'a> {
+    /// Index of the Gaussian vector of the bucket
+    bucket: usize,
+    /// Position of the point in the bucket
+    position: usize,
+    /// The point
+    vector: &'a Vec<f64>,
+}
+
+/// Probe the buckets of the Gaussian vectors that meet the threshold for a point close to
+/// the query according to `metric` (`beta` is the dot product threshold of `Metric::Cosine`).
+/// If `allowed` is given, only the buckets of the Gaussian vectors it contains are probed.
+/// If no close point is found, return the confidence of the negative answer.
+fn probe<'a>(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &'a HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+    allowed: Option<&HashSet<usize>>,
+) -> Result<Hit<'a>, NoneConfidence> {
+    let _span = spans::query_span();
+    // Get indices of Gaussian vectors that meet the threshold
+    let indices = match search(gaussian_vectors, query, threshold) {
+        // No matching Gaussian vectors
+        None => {
+            spans::record_query(0, 0);
+            return Err(NoneConfidence::Uncertain);
+        }
+        Some(indices) => indices,
+    };
+    probe_buckets(&indices, query, hash_table, beta, metric, allowed)
+}
+
+/// Probe the buckets of the Gaussian vectors of `indices` for a point close to the query,
+/// see `probe`.
+fn probe_buckets<'a>(
+    indices: &[usize],
+    query: &[f64],
+    hash_table: &'a HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+    allowed: Option<&HashSet<usize>>,
+) -> Result<Hit<'a>, NoneConfidence> {
+    // Search for a close vector in the hash table
+    let mut candidates_scored = 0;
+    for &i in indices {
+        if allowed.is_some_and(|allowed| !allowed.contains(&i)) {
+            continue;
+        }
         if let Some(vectors) = hash_table.get(&i) {
-            if let Some(close_vector) = find_close_vector(query, vectors, beta) {
-                if cfg!(test) {println!("Found a close vector! .");}
-                return Ok(Some(close_vector));
+            if let Some(position) =
+                scan_bucket(query, vectors, beta, metric, PARALLEL_SCAN_THRESHOLD)
+            {
+                if cfg!(test) {
+                    println!("Found a close vector! .");
+                }
+                spans::record_query(indices.len(), candidates_scored + position + 1);
+                return Ok(Hit {
+                    bucket: i,
+                    position,
+                    vector: &vectors[position],
+                });
             }
+            candidates_scored += vectors.len();
         }
     }
     if cfg!(test) {println!("No close vector found.");}
+    spans::record_query(indices.len(), candidates_scored);
     // If no vector meets the `beta` threshold, return None
-    Ok(None)
+    Err(NoneConfidence::Confident)
 }
 
-/// Given a `query`, return all the indices of the Gaussian vectors with dot product
-/// greater than or equal to the `threshold`.
-fn search(
-    gaussian_vectors: &Vec<Vec<f64>>,
-    query: &Vec<f64>,
+/// Return the position of the first point of `bucket` accepted by `metric`. Buckets with at
+/// least `parallel_threshold` points are scanned with Rayon, which returns the same point as
+/// the sequential scan.
+fn scan_bucket(
+    query: &[f64],
+    bucket: &[Vec<f64>],
+    beta: f64,
+    metric: Metric,
+    parallel_threshold: usize,
+) -> Option<usize> {
+    if bucket.len() >= parallel_threshold {
+        bucket
+            .par_iter()
+            .position_first(|v| metric.accepts(query, v, beta))
+    } else {
+        bucket.iter().position(|v| metric.accepts(query, v, beta))
+    }
+}
+
+/// Given a query `q`, count the points in the probed buckets whose squared L2 distance from
+/// the query is at most `radius`^2. With `Metric::CosineRaw` the points are normalized first,
+/// so that the radius applies to the directions, like the rest of the metric.
+pub fn count_within_radius(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
     threshold: f64,
-) -> Option<Vec<usize>> {
-    let mut result = Vec::new();
-    for (i, gaussian_vector) in gaussian_vectors.iter().enumerate() {
-        if dot_product(query, gaussian_vector) >= threshold {
-            result.push(i);
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    metric: Metric,
+    radius: f64,
+) -> Result<usize, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    // Get indices of Gaussian vectors that meet the threshold
+    let indices = match search(gaussian_vectors, query, threshold) {
+        None => return Ok(0), // No matching Gaussian vectors
+        Some(indices) => indices,
+    };
+
+    // Count the vectors within the radius in the probed buckets
+    let radius_sq = radius.powi(2);
+    let count = indices
+        .iter()
+        .filter_map(|i| hash_table.get(i))
+        .flatten()
+        .filter(|vector| {
+            let distance_sq = if metric.requires_normalized() {
+                l2_distance_sq(query, vector)
+            } else {
+                // |q - v / |v||^2 = 2 - 2 cos(q, v) for a normalized q
+                2. - 2. * metric.score(query, vector)
+            };
+            distance_sq <= radius_sq
+        })
+        .count();
+    Ok(count)
+}
+
+/// Given a query `q`, return the sorted and deduplicated ids of the points in the probed
+/// buckets close to the query according to `metric` (`beta` is the dot product threshold of
+/// `Metric::Cosine`). With `Metric::Cosine` and sorted buckets, only the range of each bucket
+/// that can hold a close point is scanned, see `query_sorted`.
+#[allow(clippy::too_many_arguments)]
+pub fn neighbors(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    id_table: &HashMap<usize, Vec<usize>>,
+    bucket_projections: Option<&HashMap<usize, Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+) -> Result<Vec<usize>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let mut ids = Vec::new();
+    for i in search(gaussian_vectors, query, threshold).unwrap_or_default() {
+        if let (Some(vectors), Some(bucket_ids)) = (hash_table.get(&i), id_table.get(&i)) {
+            let range = match (bucket_projections.and_then(|b| b.get(&i)), metric) {
+                (Some(projections), Metric::Cosine) => {
+                    sorted_range(query, &gaussian_vectors[i], projections, beta)
+                }
+                _ => 0..vectors.len(),
+            };
+            for (vector, id) in vectors[range.clone()].iter().zip(&bucket_ids[range]) {
+                if metric.accepts(query, vector, beta) {
+                    ids.push(*id);
+                }
+            }
+        }
+    }
+    ids.sort_unstable();
+    ids.dedup();
+    Ok(ids)
+}
+
+/// Like `neighbors`, but the ids of the close points are fed into a HyperLogLog sketch,
+/// whose estimate of the distinct count is returned. The memory is bounded regardless of the
+/// number of close points, and the standard error is about 3.3%, see `HyperLogLog`.
+pub fn count_neighbors_hll(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    id_table: &HashMap<usize, Vec<usize>>,
+    beta: f64,
+    metric: Metric,
+) -> Result<f64, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let mut sketch = HyperLogLog::new();
+    for i in search(gaussian_vectors, query, threshold).unwrap_or_default() {
+        if let (Some(vectors), Some(ids)) = (hash_table.get(&i), id_table.get(&i)) {
+            for (vector, id) in vectors.iter().zip(ids) {
+                if metric.accepts(query, vector, beta) {
+                    sketch.insert(*id);
+                }
+            }
+        }
+    }
+    Ok(sketch.estimate())
+}
+
+/// Given a query `q`, estimate the number of points with dot product (cosine similarity with
+/// `Metric::CosineRaw`) at least `beta` with the query, and return the estimate with the margin of a confidence interval at level
+/// `confidence`.
+///
+/// Each close point found in a probed bucket is weighted by the inverse of its
+/// `collision_probability` (Horvitz-Thompson estimator), and the margin is derived from the
+/// variance of the estimator. Probing more Gaussian vectors increases the collision
+/// probabilities, which shrinks the margin. If no bucket is probed, the margin is infinite.
+pub fn count_neighbors_ci(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+    confidence: f64,
+) -> Result<(usize, f64), io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    if !(0.0 < confidence && confidence < 1.0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Confidence must be in the range (0, 1)",
+        ));
+    }
+    // Get indices of Gaussian vectors that meet the threshold
+    let indices = match search(gaussian_vectors, query, threshold) {
+        // No bucket was probed, so the count is unknown
+        None => return Ok((0, f64::INFINITY)),
+        Some(indices) => indices,
+    };
+
+    // Weight the close points of the probed buckets by their inverse collision probability
+    let mut estimate = 0.0;
+    let mut variance = 0.0;
+    for i in indices {
+        let vectors = match hash_table.get(&i) {
+            Some(vectors) => vectors,
+            None => continue,
+        };
+        let (mut weight, mut count) = (0.0, 0);
+        for vector in vectors {
+            let similarity = metric.score(query, vector);
+            if similarity < beta {
+                continue;
+            }
+            // The collision probability depends on the direction of the point only
+            let mut projection = dot_product(&gaussian_vectors[i], vector);
+            if !metric.requires_normalized() {
+                projection /= dot_product(vector, vector).sqrt();
+            }
+            // The point was found, so its collision probability is positive
+            let p = collision_probability(similarity, projection, threshold).max(f64::EPSILON);
+            weight += 1. / p;
+            count += 1;
+        }
+        if count == 0 {
+            continue;
+        }
+        // Close points of the same bucket collide together, so the bucket is sampled as a
+        // whole with the harmonic mean of their collision probabilities
+        let p = count as f64 / weight;
+        estimate += weight;
+        variance += (1. - p) * weight.powi(2);
+    }
+
+    // Margin of the two sided interval
+    let z = normal_quantile(0.5 + confidence / 2.);
+    Ok((estimate.round() as usize, z * variance.sqrt()))
+}
+
+/// Result of a count that stops at a cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountResult {
+    /// The count finished below the cap.
+    Exact(usize),
+    /// The count reached the cap and stopped, so there are at least this many close points.
+    AtLeast(usize),
+}
+
+/// Given a query `q`, count the points in the probed buckets close to the query according to
+/// `metric`, but stop scanning as soon as `cap` close points are found, and return
+/// `AtLeast(cap)` in that case, or `Exact(count)` if the count stays below `cap`.
+pub fn count_neighbors_capped(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+    cap: usize,
+) -> Result<CountResult, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    if cap == 0 {
+        return Ok(CountResult::AtLeast(0));
+    }
+    // Get indices of Gaussian vectors that meet the threshold
+    let indices = match search(gaussian_vectors, query, threshold) {
+        None => return Ok(CountResult::Exact(0)), // No matching Gaussian vectors
+        Some(indices) => indices,
+    };
+
+    let count = indices
+        .iter()
+        .filter_map(|i| hash_table.get(i))
+        .flatten()
+        .filter(|vector| metric.accepts(query, vector, beta))
+        .take(cap)
+        .count();
+    if count == cap {
+        Ok(CountResult::AtLeast(cap))
+    } else {
+        Ok(CountResult::Exact(count))
+    }
+}
+
+/// Given a query `q`, return the ids of at most `k` points with the highest dot product with
+/// the query among the probed buckets, sorted by decreasing dot product. With
+/// `Metric::CosineRaw` the points are ranked by cosine similarity, see `Metric::score`.
+/// `id_table` holds the ids of the points of each bucket, in the same order as `hash_table`.
+pub fn query_k(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    id_table: &HashMap<usize, Vec<usize>>,
+    metric: Metric,
+    k: usize,
+) -> Result<Vec<usize>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    // Get indices of Gaussian vectors that meet the threshold
+    let indices = match search(gaussian_vectors, query, threshold) {
+        None => return Ok(Vec::new()), // No matching Gaussian vectors
+        Some(indices) => indices,
+    };
+
+    // Score every point of the probed buckets
+    let mut candidates: Vec<(usize, f64)> = Vec::new();
+    for i in indices {
+        if let (Some(vectors), Some(ids)) = (hash_table.get(&i), id_table.get(&i)) {
+            for (vector, id) in vectors.iter().zip(ids.iter()) {
+                candidates.push((*id, metric.score(query, vector)));
+            }
+        }
+    }
+
+    // Keep the k best candidates
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    Ok(candidates.into_iter().take(k).map(|(id, _)| id).collect())
+}
+
+/// Given a query `q`, return the scores (see `Metric::score`) and copies of all the points of
+/// the probed buckets close to the query according to `metric`, deduplicated by id and sorted
+/// by decreasing score. This is `query_k` with an unbounded `k`, restricted to close points.
+pub fn query_all(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    id_table: &HashMap<usize, Vec<usize>>,
+    beta: f64,
+    metric: Metric,
+) -> Result<Vec<(f64, Vec<f64>)>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let indices = match search(gaussian_vectors, query, threshold) {
+        None => return Ok(Vec::new()), // No matching Gaussian vectors
+        Some(indices) => indices,
+    };
+
+    // Keep every close point once
+    let mut seen = HashSet::new();
+    let mut neighbors: Vec<(f64, Vec<f64>)> = Vec::new();
+    for i in indices {
+        if let (Some(vectors), Some(ids)) = (hash_table.get(&i), id_table.get(&i)) {
+            for (vector, id) in vectors.iter().zip(ids.iter()) {
+                let score = metric.score(query, vector);
+                if metric.accepts(query, vector, beta) && seen.insert(*id) {
+                    neighbors.push((score, vector.clone()));
+                }
+            }
+        }
+    }
+    neighbors.sort_by(|a, b| b.0.total_cmp(&a.0));
+    Ok(neighbors)
+}
+
+/// Close points of a query and their dot products, grouped by the index of their bucket.
+pub type GroupedCandidates = HashMap<usize, Vec<(f64, Vec<f64>)>>;
+
+/// Given a query `q`, return the scores (see `Metric::score`) and copies of the points close
+/// to the query according to `metric`, grouped by the index of the probed bucket holding them.
+/// Each group is sorted by decreasing score, and probed buckets without a close point are
+/// left out.
+pub fn query_grouped(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    beta: f64,
+    metric: Metric,
+) -> Result<GroupedCandidates, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    let mut groups = HashMap::new();
+    for i in search(gaussian_vectors, query, threshold).unwrap_or_default() {
+        let Some(vectors) = hash_table.get(&i) else {
+            continue;
+        };
+        let mut candidates: Vec<(f64, Vec<f64>)> = vectors
+            .iter()
+            .filter(|vector| metric.accepts(query, vector, beta))
+            .map(|vector| (metric.score(query, vector), vector))
+            .map(|(score, vector)| (score, vector.clone()))
+            .collect();
+        if !candidates.is_empty() {
+            candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+            groups.insert(i, candidates);
+        }
+    }
+    Ok(groups)
+}
+
+/// Given a query `q`, return the ids and scores (see `Metric::score`) of the points of the
+/// probed buckets close to the query according to `metric`, by decreasing score, up to the
+/// first drop of more than `gap` between consecutive scores. The result size thus adapts to
+/// the score distribution instead of a fixed `k`.
+#[allow(clippy::too_many_arguments)]
+pub fn query_until_gap(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    hash_table: &HashMap<usize, Vec<Vec<f64>>>,
+    id_table: &HashMap<usize, Vec<usize>>,
+    beta: f64,
+    metric: Metric,
+    gap: f64,
+) -> Result<Vec<(usize, f64)>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(query) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    // Get indices of Gaussian vectors that meet the threshold
+    let indices = match search(gaussian_vectors, query, threshold) {
+        None => return Ok(Vec::new()), // No matching Gaussian vectors
+        Some(indices) => indices,
+    };
+
+    // Collect the close points of the probed buckets
+    let mut candidates: Vec<(usize, f64)> = Vec::new();
+    for i in indices {
+        if let (Some(vectors), Some(ids)) = (hash_table.get(&i), id_table.get(&i)) {
+            for (vector, id) in vectors.iter().zip(ids.iter()) {
+                if metric.accepts(query, vector, beta) {
+                    candidates.push((*id, metric.score(query, vector)));
+                }
+            }
         }
     }
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    // Cut before the first drop larger than the gap
+    let cutoff = candidates
+        .windows(2)
+        .position(|pair| pair[0].1 - pair[1].1 > gap)
+        .map_or(candidates.len(), |position| position + 1);
+    candidates.truncate(cutoff);
+    Ok(candidates)
+}
+
+/// Given a `query`, return all the indices of the Gaussian vectors with dot product
+/// greater than or equal to the `threshold`, or None when no Gaussian vector meets it.
+fn search(gaussian_vectors: &[Vec<f64>], query: &[f64], threshold: f64) -> Option<Vec<usize>> {
+    let result = utils::search(gaussian_vectors, query, threshold);
     // If vector is empty, return None
     if result.is_empty() {
         if cfg!(test) {println!("Search returned a empty vector .");}
@@ -60,6 +1063,22 @@ fn search(
     }
 }
 
+/// Like `search`, but write the indices into `result`, which is cleared first, instead of
+/// allocating a new vector.
+fn search_into(
+    gaussian_vectors: &[Vec<f64>],
+    query: &[f64],
+    threshold: f64,
+    result: &mut Vec<usize>,
+) {
+    result.clear();
+    for (i, gaussian_vector) in gaussian_vectors.iter().enumerate() {
+        if dot_product(query, gaussian_vector) >= threshold {
+            result.push(i);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::utils::get_threshold;
@@ -88,4 +1107,213 @@ mod tests {
         // Ensure that the indices returned by `search` match the expected indices
         assert_eq!(indices, Some(matched_gaussian_indices));
     }
+
+    /// Test function to check that the unified `utils::search` matches the simple and tensor
+    /// searches: the simple one maps an empty result to None, the tensor one formats "i#".
+    #[test]
+    fn test_unified_search() {
+        let gaussian_vectors = vec![vec![1.0, 0., 0.], vec![0., 1.0, 0.], vec![0.6, 0.8, 0.]];
+        let query = vec![1.0, 0.5, 0.];
+        for threshold in [0.4, 0.9, 2.0] {
+            let unified = utils::search(&gaussian_vectors, &query, threshold);
+            let simple = search(&gaussian_vectors, &query, threshold);
+            if unified.is_empty() {
+                assert_eq!(simple, None);
+            } else {
+                assert_eq!(simple, Some(unified.clone()));
+            }
+            let tensor = crate::tensor_data_structures::top1::Top1 {
+                gaussian_vectors: gaussian_vectors.clone(),
+                match_list: vec![],
+                threshold,
+            }
+            .search(&query);
+            let formatted: Vec<String> = unified.iter().map(|i| format!("{}#", i)).collect();
+            assert_eq!(tensor, formatted);
+        }
+        assert_eq!(utils::search(&gaussian_vectors, &query, 0.9), vec![0, 2]);
+        assert!(utils::search(&gaussian_vectors, &query, 2.0).is_empty());
+    }
+
+    /// Test function to check count_within_radius against a brute-force count.
+    #[test]
+    fn test_count_within_radius() {
+        let gaussian_vectors = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]];
+        let data = [
+            vec![1.0, 0.0, 0.0],
+            vec![0.8, 0.6, 0.0],
+            vec![0.6, 0.8, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let mut hash_table: HashMap<usize, Vec<Vec<f64>>> = HashMap::new();
+        hash_table.insert(0, data[..2].to_vec());
+        hash_table.insert(1, data[2..].to_vec());
+
+        let q = vec![1.0, 0.0, 0.0];
+        for radius in [0.1, 0.7, 1.0, 1.5, 2.0] {
+            let expected = data
+                .iter()
+                .filter(|v| l2_distance_sq(&q, v) <= radius * radius)
+                .count();
+            // Probing every bucket matches the brute-force count
+            let count = count_within_radius(
+                &gaussian_vectors,
+                &q,
+                f64::NEG_INFINITY,
+                &hash_table,
+                Metric::Cosine,
+                radius,
+            );
+            assert_eq!(count.unwrap(), expected);
+        }
+
+        // Probing only the first bucket counts only its points
+        let count =
+            count_within_radius(&gaussian_vectors, &q, 0.5, &hash_table, Metric::Cosine, 2.0);
+        assert_eq!(count.unwrap(), 2);
+
+        // With CosineRaw the distances are those of the normalized points
+        let scale = |bucket: &Vec<Vec<f64>>| -> Vec<Vec<f64>> {
+            bucket.iter().map(|v| v.iter().map(|x| 5. * x).collect()).collect()
+        };
+        let scaled: HashMap<usize, Vec<Vec<f64>>> =
+            hash_table.iter().map(|(i, bucket)| (*i, scale(bucket))).collect();
+        for radius in [0.1, 0.7, 1.0, 1.5, 2.0] {
+            let count = count_within_radius(
+                &gaussian_vectors,
+                &q,
+                f64::NEG_INFINITY,
+                &scaled,
+                Metric::CosineRaw,
+                radius,
+            );
+            let expected =
+                data.iter().filter(|v| l2_distance_sq(&q, v) <= radius * radius + 1e-12).count();
+            assert_eq!(count.unwrap(), expected);
+        }
+    }
+
+    /// Test function to check that query_unchecked matches query on normalized queries.
+    #[test]
+    fn test_query_unchecked() {
+        let gaussian_vectors = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]];
+        let mut hash_table = HashMap::new();
+        hash_table.insert(0, vec![vec![1.0, 0.0, 0.0]]);
+        hash_table.insert(1, vec![vec![0.0, 1.0, 0.0]]);
+
+        for q in [
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ] {
+            let expected =
+                query(&gaussian_vectors, &q, 0.5, &hash_table, 0.8, Metric::Cosine).unwrap();
+            let result =
+                query_unchecked(&gaussian_vectors, &q, 0.5, &hash_table, 0.8, Metric::Cosine);
+            assert_eq!(result, expected);
+        }
+
+        // A non normalized query is not rejected
+        let q = vec![2.0, 0.0, 0.0];
+        assert!(query(&gaussian_vectors, &q, 0.5, &hash_table, 0.8, Metric::Cosine).is_err());
+        assert!(
+            query_unchecked(&gaussian_vectors, &q, 0.5, &hash_table, 0.8, Metric::Cosine).is_some()
+        );
+    }
+
+    /// Test function to check that query_ref points into the hash table.
+    #[test]
+    fn test_query_ref() {
+        let gaussian_vectors = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]];
+        let mut hash_table = HashMap::new();
+        hash_table.insert(0, vec![vec![1.0, 0.0, 0.0]]);
+        hash_table.insert(1, vec![vec![0.0, 1.0, 0.0]]);
+
+        let q = vec![0.0, 1.0, 0.0];
+        let result = query_ref(&gaussian_vectors, &q, 0.5, &hash_table, 0.8, Metric::Cosine)
+            .unwrap()
+            .unwrap();
+        assert!(std::ptr::eq(result, &hash_table[&1][0]));
+        let expected = query(&gaussian_vectors, &q, 0.5, &hash_table, 0.8, Metric::Cosine).unwrap();
+        assert_eq!(Some(result.clone()), expected);
+
+        let q = vec![0.0, 0.0, 1.0];
+        let result = query_ref(&gaussian_vectors, &q, 0.5, &hash_table, 0.8, Metric::Cosine);
+        assert_eq!(result.unwrap(), None);
+        let q = vec![2.0, 0.0, 0.0];
+        let result = query_ref(&gaussian_vectors, &q, 0.5, &hash_table, 0.8, Metric::Cosine);
+        assert!(result.is_err());
+    }
+
+    /// Test function to check the confidence reported by query_confident.
+    #[test]
+    fn test_query_confident() {
+        let gaussian_vectors = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]];
+        let mut hash_table = HashMap::new();
+        hash_table.insert(0, vec![vec![1.0, 0.0, 0.0]]);
+        hash_table.insert(1, vec![vec![0.0, 1.0, 0.0]]);
+        let beta = 0.8;
+
+        // The query hits Gaussian 0, whose bucket has a close point
+        let q = vec![1.0, 0.0, 0.0];
+        let result = query_confident(
+            &gaussian_vectors,
+            &q,
+            0.5,
+            &hash_table,
+            beta,
+            Metric::Cosine,
+        )
+        .unwrap();
+        assert_eq!(result, ConfidentQuery::Found(vec![1.0, 0.0, 0.0]));
+
+        // The query hits both Gaussians, but no bucket has a close point
+        let q = vec![(0.5f64).sqrt(), (0.5f64).sqrt(), 0.0];
+        let result = query_confident(
+            &gaussian_vectors,
+            &q,
+            0.5,
+            &hash_table,
+            beta,
+            Metric::Cosine,
+        )
+        .unwrap();
+        assert_eq!(result, ConfidentQuery::NotFound(NoneConfidence::Confident));
+
+        // The query hits no Gaussian
+        let q = vec![0.0, 0.0, 1.0];
+        let result = query_confident(
+            &gaussian_vectors,
+            &q,
+            0.5,
+            &hash_table,
+            beta,
+            Metric::Cosine,
+        )
+        .unwrap();
+        assert_eq!(result, ConfidentQuery::NotFound(NoneConfidence::Uncertain));
+    }
+
+    /// Test function to check that the parallel scan of a huge bucket returns the same
+    /// candidate as the sequential scan.
+    #[test]
+    fn test_scan_bucket_parallel() {
+        let q = vec![1.0, 0.0, 0.0];
+        let beta = 0.8;
+        // A huge bucket of far points with a few close points in the middle
+        let mut bucket = vec![vec![0.0, 1.0, 0.0]; 50_000];
+        bucket[31_000] = vec![0.9, (0.19f64).sqrt(), 0.0];
+        bucket[42_000] = vec![1.0, 0.0, 0.0];
+
+        let sequential = scan_bucket(&q, &bucket, beta, Metric::Cosine, usize::MAX);
+        let parallel = scan_bucket(&q, &bucket, beta, Metric::Cosine, 0);
+        assert_eq!(sequential, Some(31_000));
+        assert_eq!(parallel, Some(31_000));
+
+        // No close point in the bucket
+        bucket[31_000] = vec![0.0, 1.0, 0.0];
+        bucket[42_000] = vec![0.0, 0.0, 1.0];
+        assert_eq!(scan_bucket(&q, &bucket, beta, Metric::Cosine, 0), None);
+    }
 }
@@ -1,24 +1,283 @@
-use crate::utils::{generate_normal_gaussian_vectors, dot_product, get_threshold};
-use crate::checks::check_input;
-use super::query::query;
-use rand_distr::num_traits::Pow;
-use std::collections::HashMap;
-use std::io;
+use super::query::{
+    count_bucket_members, count_neighbors_capped, count_neighbors_ci, count_neighbors_hll,
+    count_within_radius, expected_candidates, nearest_gaussians, neighbors, query, query_all,
+    query_confident, query_grouped, query_id, query_id_excluding, query_id_point, query_k,
+    query_min_bucket, query_ref, query_restricted, query_sorted, query_top_p, query_unchecked,
+    query_until_gap, query_with_ctx, query_with_projection, ConfidentQuery, CountResult,
+    GroupedCandidates, IdHit, NoneConfidence, QueryContext,
+};
+use crate::ann_index::AnnIndex;
+use crate::bloom::BloomFilter;
+use crate::checks::{check_dimension, check_input_with};
+use crate::error::AnnError;
+use crate::metric::Metric;
+use crate::privacy::{private_count_with, NoiseMechanism};
+use crate::spans;
+use crate::utils::{
+    collision_probability, compute_m, dot_product, generate_normal_gaussian_vectors,
+    generate_normal_gaussian_vectors_seeded, get_threshold, is_normalized, normal_cdf,
+    normalize_vector, BuildReport,
+};
+#[cfg(feature = "ndarray")]
+use ndarray::{ArrayView1, ArrayView2};
+use rand::rngs::StdRng;
+use rand::seq::{index, SliceRandom};
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use savefile_derive::Savefile;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
+/// Buckets of data vectors indexed by Gaussian vector.
+type HashTable = HashMap<usize, Vec<Vec<f64>>>;
+/// Ids of the data vectors of each bucket.
+type IdTable = HashMap<usize, Vec<usize>>;
+
+/// Return the bucket and the position in the bucket of each id of `id_table`.
+pub(super) fn locate_ids(id_table: &IdTable) -> HashMap<usize, (usize, usize)> {
+    id_table
+        .iter()
+        .flat_map(|(i, ids)| {
+            ids.iter()
+                .enumerate()
+                .map(move |(position, id)| (*id, (*i, position)))
+        })
+        .collect()
+}
+
+/// A warning is emitted at build time if the largest bucket exceeds this many times the
+/// expected size `n / m` of a bucket under a uniform assignment.
+const MAX_BUCKET_SKEW: f64 = 10.0;
+
+/// Tolerance on the parameters compared by `Top1::query_checked`.
+const PARAMETER_TOLERANCE: f64 = 1e-9;
+
+/// Tolerance on the cosine of the angle of `Top1::query_angle`.
+const ANGLE_TOLERANCE: f64 = 1e-9;
+
+/// Differences between two Top1 structures, see `Top1::diff`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StructureDiff {
+    /// True if the Gaussian vectors differ
+    pub projections: bool,
+    /// Names of the parameters that differ
+    pub parameters: Vec<&'static str>,
+    /// Indices of the buckets whose points differ, in increasing order
+    pub buckets: Vec<usize>,
+}
+
+impl StructureDiff {
+    /// Return true if the structures do not differ.
+    pub fn is_empty(&self) -> bool {
+        !self.projections && self.parameters.is_empty() && self.buckets.is_empty()
+    }
+}
+
+/// Options of the construction of a Top1 struct, see `Top1::with_config`.
+#[derive(Debug, Clone, Default)]
+pub struct Top1Config {
+    /// Seed of the Gaussian vectors, drawn from the thread generator if `None`
+    pub seed: Option<u64>,
+    /// Sort each bucket by decreasing dot product of its points with the normalized Gaussian
+    /// vector, so that `query` only scans the part of a bucket that can hold a close point.
+    /// This costs an extra sort per bucket at construction.
+    pub sorted_buckets: bool,
+    /// Seed of a shuffle of the data before bucketing, so that the order of the points in the
+    /// buckets does not follow the input order (e.g. pre-sorted or clustered data). The ids of
+    /// the points are still their indices in the data.
+    pub shuffle_seed: Option<u64>,
+    /// Keep a Bloom filter of the points of each bucket, so that `contains` skips the scan of
+    /// a bucket that definitely does not hold the point, and `query` returns a stored copy of
+    /// the query without scanning the probed buckets. The filters have no false negatives
+    /// and a false positive rate of about 0.8%, see `BloomFilter`.
+    pub bloom_filters: bool,
+    /// Upper bound on the number of Gaussian vectors, to bound their memory (m * d floats) for
+    /// aggressive parameters. A larger computed `m` is clamped, which may degrade the recall.
+    pub max_m: Option<usize>,
+    /// Rebuild the structure in `insert` and `merge` once the number of points exceeds this factor times
+    /// the number of points `m` was computed for, so that the recall does not decay under
+    /// streaming inserts, see `Top1::rebuild`.
+    pub auto_rebuild_factor: Option<f64>,
+    /// Track the running mean of the points inserted since the build, see `Top1::drift_stats`.
+    pub track_drift: bool,
+    /// Acceptance rule of the queries. With `Metric::CosineRaw` the data need not be
+    /// normalized.
+    pub metric: Metric,
+    /// Sample this many pairs of points at build time and warn if none is `beta`-close, see
+    /// `Top1::max_pairwise_similarity_sample`. Skipped if `None`.
+    pub beta_check_sample: Option<usize>,
+}
+
+/// Statistics of the points inserted since the last build, to detect a drift of their
+/// distribution away from the data the structure was tuned for. Inserted and merged points
+/// are tracked.
+#[derive(Debug, Clone, PartialEq, Savefile)]
+pub struct DriftStats {
+    /// Running mean of the inserted points
+    pub mean: Vec<f64>,
+    /// Norm of `mean`: close to 1 if the inserted points concentrate in one direction, close
+    /// to 0 if they are spread over the sphere
+    pub mean_norm: f64,
+    /// Running mean of the norms of the inserted points: 1 for normalized points, the scale of
+    /// the points with `Metric::CosineRaw`
+    pub mean_point_norm: f64,
+    /// Number of points inserted since the last build
+    pub points_since_build: usize,
+}
+
+impl DriftStats {
+    fn new(d: usize) -> Self {
+        DriftStats {
+            mean: vec![0.0; d],
+            mean_norm: 0.0,
+            mean_point_norm: 0.0,
+            points_since_build: 0,
+        }
+    }
+
+    pub(super) fn update(&mut self, p: &[f64]) {
+        self.points_since_build += 1;
+        let k = self.points_since_build as f64;
+        for (mean, x) in self.mean.iter_mut().zip(p) {
+            *mean += (x - *mean) / k;
+        }
+        self.mean_norm = dot_product(&self.mean, &self.mean).sqrt();
+        self.mean_point_norm += (dot_product(p, p).sqrt() - self.mean_point_norm) / k;
+    }
+}
+
+#[derive(Clone)]
 pub struct Top1 {
     pub gaussian_vectors: Vec<Vec<f64>>,
     pub hash_table: HashMap<usize, Vec<Vec<f64>>>,
+    // Ids of the points in each bucket, in the same order as `hash_table`
+    pub id_table: HashMap<usize, Vec<usize>>,
+    // Bucket and position of each id in `id_table`, see `point`
+    pub(super) locations: HashMap<usize, (usize, usize)>,
     pub alpha: f64,
     pub beta: f64,
     pub threshold: f64,
     pub m: usize,
+    pub d: usize,
+    // Threshold used instead of `get_threshold(alpha, m)`, see `set_threshold_override`
+    pub(super) threshold_override: Option<f64>,
+    // Criterion used to accept the candidates of the probed buckets
+    pub metric: Metric,
+    // Id assigned to the next inserted point
+    pub(super) next_id: usize,
+    // Write-ahead log where inserted points are appended, see `open_with_wal`
+    pub(super) wal_path: Option<PathBuf>,
+    // Dot products of the points of each bucket with the normalized Gaussian vector, in the
+    // same order as `hash_table`, if the buckets are sorted
+    pub(super) bucket_projections: Option<HashMap<usize, Vec<f64>>>,
+    // Bloom filter of the points of each bucket, if enabled
+    pub(super) bloom_filters: Option<HashMap<usize, BloomFilter>>,
+    // Exponent `m` was computed with, if known
+    pub(super) theta: Option<f64>,
+    // Number of points `m` was computed for
+    pub(super) tuned_n: usize,
+    // Upper bound on `m`, see `Top1Config::max_m`
+    pub(super) max_m: Option<usize>,
+    // See `Top1Config::auto_rebuild_factor`
+    pub(super) auto_rebuild_factor: Option<f64>,
+    // Statistics of the inserted points, if tracked
+    pub(super) drift: Option<DriftStats>,
+    // Seed of the Gaussian vectors of the next `rebuild`, derived from the build seed and
+    // advanced at each rebuild, so that seeded structures rebuild reproducibly
+    pub(super) rehash_seed: u64,
 }
 
 impl Top1 {
     /// Constructor for the Top1 struct.
     pub fn new(data: Vec<Vec<f64>>, alpha: f64, beta: f64, theta: f64) -> Self {
+        Self::new_timed(data, alpha, beta, theta).0
+    }
+
+    /// Constructor for the Top1 struct with Gaussian vectors drawn from a seeded generator.
+    /// Structures built with the same seed on data of the same size share their Gaussian vectors.
+    pub fn new_with_seed(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        seed: u64,
+    ) -> Self {
+        let config = Top1Config {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        Self::build(data, alpha, beta, theta, &config).0
+    }
+
+    /// Constructor for the Top1 struct that also reports the time spent in each phase.
+    pub fn new_timed(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+    ) -> (Self, BuildReport) {
+        Self::build(data, alpha, beta, theta, &Top1Config::default())
+    }
+
+    /// Constructor for the Top1 struct with the options of `config`.
+    pub fn with_config(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        config: Top1Config,
+    ) -> Self {
+        Self::build(data, alpha, beta, theta, &config).0
+    }
+
+    /// Like `with_config`, but invalid input is returned as an error instead of being reported
+    /// on stderr: `AnnError::DimensionMismatch` if the points do not all have the dimension of
+    /// the first one, `AnnError::InvalidParameter` for the other checks of `check_input_with`.
+    pub fn try_with_config(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        config: Top1Config,
+    ) -> Result<Self, AnnError> {
+        if let Some(d) = data.first().map(|point| point.len()) {
+            for point in &data {
+                check_dimension(d, point.len())?;
+            }
+        }
+        check_input_with(
+            &data,
+            alpha,
+            beta,
+            theta,
+            config.metric.requires_normalized(),
+        )
+        .map_err(AnnError::InvalidParameter)?;
+        Ok(Self::with_config(data, alpha, beta, theta, config))
+    }
+
+    fn build(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        config: &Top1Config,
+    ) -> (Self, BuildReport) {
+        let start = Instant::now();
+
         // Check inputs
-        match check_input(&data, alpha, beta, theta) {
+        match check_input_with(
+            &data,
+            alpha,
+            beta,
+            theta,
+            config.metric.requires_normalized(),
+        ) {
             Ok(_) => {}
             Err(err) => eprintln!("Input validation failed: {}", err),
         }
@@ -28,138 +287,3540 @@ impl Top1 {
         // Number of vectors in the data
         let n = data.len();
         // Number of Gaussian vectors
-        let m = (n as f64).pow(theta / (1. - alpha.powf(2.))).ceil() as usize;
+        let mut m = compute_m(n, alpha, theta);
+        if let Some(max_m) = config.max_m {
+            if m > max_m {
+                log::warn!(
+                    "Computed number of Gaussian vectors {} exceeds max_m, clamped to {}: recall may degrade.",
+                    m, max_m
+                );
+                m = max_m;
+            }
+        }
+        let _span = spans::build_span(n, d, m);
 
         // Generate Gaussian vectors
         println!("Generating {} Gaussian vectors...", m);
-        let gaussian_vectors = generate_normal_gaussian_vectors(m, d).unwrap();
+        let gaussian_start = Instant::now();
+        let gaussian_vectors = match config.seed {
+            Some(seed) => generate_normal_gaussian_vectors_seeded(m, d, seed).unwrap(),
+            None => generate_normal_gaussian_vectors(m, d).unwrap(),
+        };
+        let gaussian_gen = gaussian_start.elapsed();
 
         // Create hash table
         println!("Creating hash table...");
-        let hash_table = get_hash_table(&data, &gaussian_vectors);
+        let hashing_start = Instant::now();
+        let (hash_table, id_table) = match config.shuffle_seed {
+            None => get_hash_table(&data, &gaussian_vectors),
+            Some(seed) => {
+                let mut order: Vec<usize> = (0..n).collect();
+                order.shuffle(&mut StdRng::seed_from_u64(seed));
+                get_hash_table_in_order(&data, &gaussian_vectors, order)
+            }
+        };
+        let hashing = hashing_start.elapsed();
 
         // Create Top1 struct
-        Top1 {
+        let mut top1 = Top1 {
             gaussian_vectors,
             hash_table,
+            locations: locate_ids(&id_table),
+            id_table,
             alpha,
             beta,
             m,
+            d,
+            threshold: get_threshold(alpha, m),
+            threshold_override: None,
+            metric: config.metric,
+            next_id: n,
+            wal_path: None,
+            bucket_projections: None,
+            bloom_filters: None,
+            theta: Some(theta),
+            tuned_n: n,
+            max_m: config.max_m,
+            auto_rebuild_factor: config.auto_rebuild_factor,
+            drift: config.track_drift.then(|| DriftStats::new(d)),
+            rehash_seed: match config.seed {
+                Some(seed) => StdRng::seed_from_u64(seed).gen(),
+                None => rand::thread_rng().gen(),
+            },
+        };
+        if config.sorted_buckets {
+            top1.sort_buckets();
+        }
+        if config.bloom_filters {
+            top1.build_bloom_filters();
+        }
+
+        // Warn if a single Gaussian vector captures too many points
+        let skew = top1.max_bucket_skew();
+        if skew > MAX_BUCKET_SKEW {
+            log::warn!(
+                "Largest bucket is {:.1} times the expected uniform size: queries may be slow, check the parameters.",
+                skew
+            );
+        }
+
+        // Warn if no sampled pair of points is beta-close
+        if let Some(sample) = config.beta_check_sample {
+            let max_similarity = top1.max_pairwise_similarity_sample(sample);
+            if n > 1 && max_similarity < beta {
+                log::warn!(
+                    "Sampled max pairwise similarity {} is below beta {}: queries may never find a close point.",
+                    max_similarity, beta
+                );
+            }
+        }
+
+        let report = BuildReport {
+            gaussian_gen,
+            hashing,
+            total: start.elapsed(),
+        };
+        (top1, report)
+    }
+
+    /// Predict, without building the structure, the probability that a query finds a point at
+    /// dot product `alpha` with it in the structure built on `n` points with these parameters.
+    ///
+    /// The projection `h` of the point on its closest Gaussian vector is the maximum of `m`
+    /// standard normals, with density `m phi(h) Phi(h)^(m - 1)`, and the recall is the
+    /// `collision_probability` of the point averaged over this density.
+    pub fn predicted_recall(n: usize, alpha: f64, beta: f64, theta: f64) -> f64 {
+        let m = compute_m(n, alpha, theta);
+        let threshold = get_threshold(alpha, m);
+        if threshold.is_nan() || alpha <= beta {
+            return 0.0;
+        }
+
+        // Trapezoidal integration of the collision probability over the density of h
+        let (low, high, steps) = (-8.0, 12.0, 4000);
+        let step = (high - low) / steps as f64;
+        let mut recall = 0.0;
+        for k in 0..=steps {
+            let h: f64 = low + k as f64 * step;
+            let phi = (-h * h / 2.).exp() / (2. * std::f64::consts::PI).sqrt();
+            let log_cdf = normal_cdf(h).max(f64::MIN_POSITIVE).ln();
+            let density = m as f64 * phi * ((m - 1) as f64 * log_cdf).exp();
+            let weight = if k == 0 || k == steps { 0.5 } else { 1.0 };
+            recall += weight * density * collision_probability(alpha, h, threshold) * step;
+        }
+        recall.clamp(0.0, 1.0)
+    }
+
+    /// Assemble a Top1 struct from Gaussian vectors and buckets computed elsewhere, e.g. by a
+    /// TensorTop1 structure. The ids of the points follow the order of `id_table`.
+    pub(crate) fn from_buckets(
+        gaussian_vectors: Vec<Vec<f64>>,
+        hash_table: HashMap<usize, Vec<Vec<f64>>>,
+        id_table: HashMap<usize, Vec<usize>>,
+        alpha: f64,
+        beta: f64,
+        d: usize,
+    ) -> Self {
+        let m = gaussian_vectors.len();
+        let next_id = id_table.values().flatten().max().map_or(0, |id| id + 1);
+        let tuned_n = id_table.values().map(|ids| ids.len()).sum();
+        Top1 {
+            gaussian_vectors,
+            hash_table,
+            locations: locate_ids(&id_table),
+            id_table,
+            alpha,
+            beta,
             threshold: get_threshold(alpha, m),
+            m,
+            d,
+            threshold_override: None,
+            metric: Metric::Cosine,
+            next_id,
+            wal_path: None,
+            bucket_projections: None,
+            bloom_filters: None,
+            theta: None,
+            tuned_n,
+            max_m: None,
+            auto_rebuild_factor: None,
+            drift: None,
+            rehash_seed: rand::thread_rng().gen(),
+        }
+    }
+
+    /// Constructor for a Top1 struct using the given Gaussian vectors, e.g. exported from
+    /// another structure with `export_projections_bin`, instead of drawing new ones.
+    /// An error is returned if the data is empty, if a point or a Gaussian vector does not have
+    /// the dimension of the first point, or if a Gaussian vector is zero, which never meets the
+    /// threshold, or has a non-finite entry.
+    pub fn from_projections(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        gaussian_vectors: Vec<Vec<f64>>,
+    ) -> Result<Self, AnnError> {
+        let Some(d) = data.first().map(|point| point.len()) else {
+            return Err(AnnError::InvalidParameter("the data is empty".to_string()));
+        };
+        for vector in data.iter().chain(&gaussian_vectors) {
+            check_dimension(d, vector.len())?;
+        }
+        for (i, gaussian_vector) in gaussian_vectors.iter().enumerate() {
+            if gaussian_vector.iter().any(|x| !x.is_finite()) {
+                return Err(AnnError::InvalidParameter(format!(
+                    "the Gaussian vector {} has a non-finite entry",
+                    i
+                )));
+            }
+            if gaussian_vector.iter().all(|x| *x == 0.0) {
+                return Err(AnnError::InvalidParameter(format!(
+                    "the Gaussian vector {} is zero",
+                    i
+                )));
+            }
+        }
+        let (hash_table, id_table) = get_hash_table(&data, &gaussian_vectors);
+        Ok(Top1::from_buckets(
+            gaussian_vectors,
+            hash_table,
+            id_table,
+            alpha,
+            beta,
+            d,
+        ))
+    }
+
+    /// Constructor for a Top1 struct using arbitrary projections, e.g. a learned projection
+    /// matrix, probed with an explicit `threshold`: `get_threshold` assumes Gaussian marginals,
+    /// which learned projections need not have. The threshold is set as the threshold override
+    /// (see `set_threshold_override`), so it is kept by `rehash` and `rebuild`.
+    /// An error is returned if there is no projection, if a projection is rejected by
+    /// `from_projections`, if the threshold is NaN, or unless `0 < beta < alpha < 1`.
+    pub fn from_projections_with_threshold(
+        data: Vec<Vec<f64>>,
+        projections: Vec<Vec<f64>>,
+        threshold: f64,
+        alpha: f64,
+        beta: f64,
+    ) -> Result<Self, AnnError> {
+        if projections.is_empty() {
+            return Err(AnnError::InvalidParameter(
+                "no projection given".to_string(),
+            ));
+        }
+        if threshold.is_nan() {
+            return Err(AnnError::InvalidParameter("threshold is NaN".to_string()));
+        }
+        if !(0.0 < beta && beta < alpha && alpha < 1.0) {
+            return Err(AnnError::InvalidParameter(format!(
+                "expected 0 < beta < alpha < 1, got alpha = {} and beta = {}",
+                alpha, beta
+            )));
+        }
+        let mut top1 = Top1::from_projections(data, alpha, beta, projections)?;
+        top1.set_threshold_override(Some(threshold));
+        Ok(top1)
+    }
+
+    /// Return the Gaussian vectors used as random projections.
+    pub fn projections(&self) -> &[Vec<f64>] {
+        &self.gaussian_vectors
+    }
+
+    /// Check that `q` has the dimension `d` of the structure, see `check_dimension`, and return
+    /// it as `metric_query` does.
+    pub(super) fn check_query<'q>(&self, q: &'q [f64]) -> Result<Cow<'q, [f64]>, io::Error> {
+        check_dimension(self.d, q.len())?;
+        Ok(self.metric_query(q))
+    }
+
+    /// Return the query used for the bucketing: `q` normalized if the metric is
+    /// `Metric::CosineRaw` and `q` is not zero, `q` itself otherwise.
+    fn metric_query<'q>(&self, q: &'q [f64]) -> Cow<'q, [f64]> {
+        if self.metric.requires_normalized() || dot_product(q, q) == 0.0 {
+            return Cow::Borrowed(q);
+        }
+        let mut unit = q.to_vec();
+        normalize_vector(&mut unit);
+        Cow::Owned(unit)
+    }
+
+    /// Given a query `q`, return a close point according to `metric` (dot product by default).
+    /// If the buckets are sorted and the metric is `Metric::Cosine`, only the part of each
+    /// probed bucket that can hold a close point is scanned.
+    /// Queries only read the structure and take no lock, so a structure shared in an `Arc`
+    /// serves concurrent reader threads without contention.
+    pub fn query(&self, q: &[f64]) -> Result<Option<Vec<f64>>, io::Error> {
+        self.query_with_beta(q, self.beta)
+    }
+
+    /// Like `query`, but if `q` probes no bucket, scan `sample_size` distinct stored points
+    /// drawn with `rng` (all of them if fewer are stored) and return the one with the highest
+    /// score (see `Metric::score`) among those accepted by `metric`, if any. This bounds the
+    /// extra work spent on the queries the hashing gives up on. The probed buckets are scanned
+    /// in insertion order even if they are sorted.
+    pub fn query_sampled_fallback<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        q: &[f64],
+        sample_size: usize,
+    ) -> Result<Option<Vec<f64>>, io::Error> {
+        let q = &*self.check_query(q)?;
+        match self.query_confident(q)? {
+            ConfidentQuery::Found(close_vector) => return Ok(Some(close_vector)),
+            ConfidentQuery::NotFound(NoneConfidence::Confident) => return Ok(None),
+            ConfidentQuery::NotFound(NoneConfidence::Uncertain) => {}
+        }
+        // Sample positions among the stored points, numbered bucket by bucket in the order of
+        // the Gaussian vectors, then walk the buckets once to find them
+        let n = self.len();
+        let mut positions = index::sample(rng, n, sample_size.min(n)).into_vec();
+        positions.sort_unstable();
+        let mut positions = positions.into_iter().peekable();
+        let mut best: Option<(f64, &Vec<f64>)> = None;
+        let mut offset = 0;
+        for i in 0..self.gaussian_vectors.len() {
+            let Some(bucket) = self.hash_table.get(&i) else {
+                continue;
+            };
+            let end = offset + bucket.len();
+            while let Some(position) = positions.next_if(|position| *position < end) {
+                let point = &bucket[position - offset];
+                if !self.metric.accepts(q, point, self.beta) {
+                    continue;
+                }
+                let score = self.metric.score(q, point);
+                if best.is_none_or(|(best_score, _)| score > best_score) {
+                    best = Some((score, point));
+                }
+            }
+            offset = end;
+        }
+        Ok(best.map(|(_, point)| point.clone()))
+    }
+
+    /// Like `query`, but an error is returned if `expected_alpha` or `expected_beta` differ
+    /// from the parameters the structure was built with (up to rounding), e.g. when querying a
+    /// structure tuned for another regime than the caller assumes.
+    pub fn query_checked(
+        &self,
+        q: &[f64],
+        expected_alpha: f64,
+        expected_beta: f64,
+    ) -> Result<Option<Vec<f64>>, io::Error> {
+        let parameters = [
+            ("alpha", self.alpha, expected_alpha),
+            ("beta", self.beta, expected_beta),
+        ];
+        for (name, actual, expected) in parameters {
+            // A NaN expectation never matches
+            let matches = (actual - expected).abs() <= PARAMETER_TOLERANCE;
+            if !matches {
+                return Err(AnnError::IncompatibleParameters(format!(
+                    "{} (expected {}, the structure has {})",
+                    name, expected, actual
+                ))
+                .into());
+            }
         }
+        self.query(q)
     }
 
-    /// Given a query `q`, return a close point according to dot product.
-    pub fn query(&self, q: &Vec<f64>) -> Result<Option<Vec<f64>>, io::Error> {
+    /// Like `query`, but a candidate is accepted if its dot product with `q` is at least `beta`
+    /// instead of the `beta` of the structure. The buckets probed do not depend on `beta`.
+    /// An error is returned if `beta` is not in [-1, alpha): the structure is only tuned to find
+    /// the points at dot product at least `alpha`, so a larger `beta` has no guarantee.
+    pub fn query_beta(&self, q: &[f64], beta: f64) -> Result<Option<Vec<f64>>, io::Error> {
+        if !(-1.0..self.alpha).contains(&beta) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("beta must be in [-1, alpha = {}), got {}", self.alpha, beta),
+            ));
+        }
+        self.query_with_beta(q, beta)
+    }
+
+    /// Like `query_beta`, without checking `beta`.
+    fn query_with_beta(&self, q: &[f64], beta: f64) -> Result<Option<Vec<f64>>, io::Error> {
+        let q = &*self.check_query(q)?;
+        // Fast path for an empty structure, whose Gaussian vectors may still meet the threshold
+        if self.is_empty() {
+            if !is_normalized(q) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Query vector is not normalized",
+                ));
+            }
+            return Ok(None);
+        }
+        if let Some(copy) = self.probed_copy(q, beta) {
+            return Ok(Some(copy.clone()));
+        }
+        if let (Some(bucket_projections), Metric::Cosine) = (&self.bucket_projections, self.metric)
+        {
+            let close_vector = query_sorted(
+                &self.gaussian_vectors,
+                q,
+                self.threshold,
+                &self.hash_table,
+                bucket_projections,
+                beta,
+            )?;
+            return Ok(close_vector.cloned());
+        }
         query(
             &self.gaussian_vectors,
             q,
             self.threshold,
             &self.hash_table,
+            beta,
+            self.metric,
+        )
+    }
+
+    /// Like `query`, but skip the dot products of `q` with the Gaussian vectors, taking them from
+    /// `projection_scores`, where the i-th score is `q · gaussian_vectors[i]`. Callers issuing
+    /// related queries can compute or cache the projections themselves. The buckets are scanned
+    /// in insertion order even if they are sorted. With `Metric::CosineRaw` the query is
+    /// normalized, and the scores must be those of the normalized query.
+    /// An error is returned if `q` does not have dimension `d`, if there are not `m` scores or
+    /// if `q` is not normalized and the metric requires it.
+    pub fn query_with_projection(
+        &self,
+        q: &[f64],
+        projection_scores: &[f64],
+    ) -> Result<Option<Vec<f64>>, io::Error> {
+        let q = &*self.check_query(q)?;
+        if projection_scores.len() != self.m {
+            return Err(AnnError::DimensionMismatch {
+                expected: self.m,
+                found: projection_scores.len(),
+            }
+            .into());
+        }
+        query_with_projection(
+            q,
+            projection_scores,
+            self.threshold,
+            &self.hash_table,
             self.beta,
+            self.metric,
         )
     }
-}
 
-/// For each vector in `data`, find the Gaussian vector with the highest dot product.
-/// Store the result in a `HashMap` where the key is the index of the Gaussian vector and
-/// the value is the list of data vectors that are closest to it.
-fn get_hash_table(
-    data: &Vec<Vec<f64>>,
-    gaussian_vectors: &Vec<Vec<f64>>,
-) -> HashMap<usize, Vec<Vec<f64>>> {
-    let mut closest_gaussian_vectors: HashMap<usize, Vec<Vec<f64>>> = HashMap::new();
+    /// Given a query `q`, return a point at angle at most `max_angle_deg` degrees from `q`, i.e.
+    /// query with `beta = cos(max_angle_deg)`. A tolerance of `ANGLE_TOLERANCE` on the cosine
+    /// absorbs rounding, so that an angle of 0 accepts the points in the direction of `q`.
+    /// Unlike `query_beta`, a cosine of at least `alpha` is allowed: the probed buckets are
+    /// still checked exactly, but the recall of such small angles is not guaranteed.
+    /// An error is returned if the angle is not in [0, 180].
+    pub fn query_angle(
+        &self,
+        q: &[f64],
+        max_angle_deg: f64,
+    ) -> Result<Option<Vec<f64>>, io::Error> {
+        if !(0.0..=180.0).contains(&max_angle_deg) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("max_angle_deg must be in [0, 180], got {}", max_angle_deg),
+            ));
+        }
+        let beta = (max_angle_deg.to_radians().cos() - ANGLE_TOLERANCE).max(-1.0);
+        self.query_with_beta(q, beta)
+    }
 
-    // Iterate over each data vector
-    for data_vector in data.iter() {
-        let mut max_dot_product = f64::MIN;
-        let mut max_dot_product_index = 0;
+    /// Given a query `q`, return a reference to a close point according to dot product,
+    /// avoiding the copy of the vector made by `query`.
+    pub fn query_ref(&self, q: &[f64]) -> Result<Option<&Vec<f64>>, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_ref(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.beta,
+            self.metric,
+        )
+    }
 
-        // Iterate over each Gaussian vector
-        for (j, gaussian_vector) in gaussian_vectors.iter().enumerate() {
-            // Compute dot product between the data vector and this Gaussian vector
-            let dot_product_value = dot_product(data_vector, gaussian_vector);
+    /// Return the indices and dot products of the `k` Gaussian vectors with the highest dot
+    /// product with `q`, regardless of the threshold, by decreasing dot product. This shows
+    /// e.g. how far below the threshold the best Gaussian vector of a query that probes no
+    /// bucket is. With `Metric::CosineRaw` the dot products are those of the normalized query.
+    /// An error is returned if `q` does not have dimension `d`.
+    pub fn nearest_gaussians(&self, q: &[f64], k: usize) -> Result<Vec<(usize, f64)>, io::Error> {
+        let q = &*self.check_query(q)?;
+        Ok(nearest_gaussians(&self.gaussian_vectors, q, k))
+    }
 
-            if dot_product_value > max_dot_product {
-                max_dot_product = dot_product_value;
-                max_dot_product_index = j;
-            }
-        }
+    /// Like `query_ref`, but the close point is returned as a `Cow::Borrowed` into the hash
+    /// table, which the caller can turn into an owned vector with `into_owned` to keep it
+    /// beyond the lifetime of the structure.
+    pub fn query_cow(&self, q: &[f64]) -> Result<Option<Cow<'_, [f64]>>, io::Error> {
+        Ok(self
+            .query_ref(q)?
+            .map(|point| Cow::Borrowed(point.as_slice())))
+    }
 
-        // Insert or update the list of data vectors for the closest Gaussian vector
-        closest_gaussian_vectors
-            .entry(max_dot_product_index)
-            .or_insert_with(Vec::new)
-            .push(data_vector.clone());
+    /// Given a query `q`, return a close point according to dot product without checking
+    /// that `q` is normalized. The caller must guarantee it: garbage in, garbage out.
+    /// The saving is measured for d = 10000 in `tests/query_unchecked_cost.rs`.
+    pub fn query_unchecked(&self, q: &[f64]) -> Option<Vec<f64>> {
+        let q = &*self.metric_query(q);
+        query_unchecked(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.beta,
+            self.metric,
+        )
     }
 
-    closest_gaussian_vectors
-}
+    /// Given a query `q`, return a close point according to dot product, or the confidence
+    /// of the negative answer if no close point is found.
+    pub fn query_confident(&self, q: &[f64]) -> Result<ConfidentQuery, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_confident(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.beta,
+            self.metric,
+        )
+    }
 
-/// Test function for Top1 struct.
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Given a query `q`, return a reference to a close point according to dot product,
+    /// reusing the scratch buffers of `ctx` to avoid allocating on each call in hot loops.
+    /// The context is per thread.
+    pub fn query_with_ctx(
+        &self,
+        q: &[f64],
+        ctx: &mut QueryContext,
+    ) -> Result<Option<&Vec<f64>>, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_with_ctx(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.beta,
+            self.metric,
+            ctx,
+        )
+    }
 
-    /// Test function to check if the Top1 struct works.
-    #[test]
-    fn test_top1_query() {
-        // Create a sample data
-        let data = vec![
-            vec![1.0, 0.0, 0.0],
-            vec![0.0, 1.0, 0.0],
-            vec![0.0, 0.0, 1.0],
-        ];
-        let alpha = 0.9;
-        let beta = 0.8;
-        let theta = 0.5;
-        let top1 = Top1::new(data, alpha, beta, theta);
+    /// Given a query `q`, return the id of a close point according to `metric` and its score
+    /// (see `Metric::score`): the cosine similarity with `Metric::CosineRaw`, the dot product
+    /// otherwise. Ids follow the order of insertion, starting from the data.
+    pub fn query_id(&self, q: &[f64]) -> Result<Option<(usize, f64)>, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_id(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            &self.id_table,
+            self.beta,
+            self.metric,
+        )
+    }
 
-        // Good query
-        let query = vec![1.0, 0.0, 0.0];
-        let result = top1.query(&query);
-        // if threshold is lower than all the dot products, the result should be None
-        let mut flag: bool = true;
-        for vector in top1.gaussian_vectors.iter() {
-            let dot_product = dot_product(&query, vector);
-            // A vector has a dot product greater than the threshold, so the result should not be None
-            if dot_product >= top1.threshold {
-                println!("Dot product: {}", dot_product);
-                flag = false;
-                break;
-            }
-        }
-        if flag {
-            // Result should be None
-            assert_eq!(result.unwrap(), None);
-        } else {
-            // Result should be close to the query
-            let dot_product = dot_product(&query, &result.unwrap().unwrap());
-            assert!(dot_product >= beta);
-        }
+    /// Like `query_id`, but a reference to the close point is also returned.
+    pub fn query_id_point(&self, q: &[f64]) -> Result<Option<IdHit<'_>>, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_id_point(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            &self.id_table,
+            self.beta,
+            self.metric,
+        )
+    }
 
-        // Bad query
-        let query = vec![2.0, 0.0, 0.0];
-        let result = top1.query(&query);
-        // Result should be an Error
-        assert!(result.is_err());
+    /// Like `query_id`, but the point with id `excluded` is never returned.
+    pub fn query_id_excluding(
+        &self,
+        q: &[f64],
+        excluded: usize,
+    ) -> Result<Option<(usize, f64)>, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_id_excluding(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            &self.id_table,
+            self.beta,
+            self.metric,
+            excluded,
+        )
     }
 
-    /// Test function to check if the get_hash_table function works.
-    #[test]
-    fn test_get_hash_table() {
-        let data = vec![
-            vec![1.0, 0.0, 0.0],
-            vec![1.0, 0.0, 0.0],
-            vec![0.0, 1.0, 0.0],
-            vec![0.0, 0.0, 1.0],
-        ];
-        let gaussian_vectors = vec![
-            vec![1.0, 0.0, 0.0],
-            vec![0.0, 1.0, 0.0],
-            vec![0.0, 0.0, 1.0],
-        ];
-        let hash_table = get_hash_table(&data, &gaussian_vectors);
+    /// Return the stored point with id `id`, if any, in constant time.
+    pub fn point(&self, id: usize) -> Option<&Vec<f64>> {
+        let &(i, position) = self.locations.get(&id)?;
+        self.hash_table.get(&i)?.get(position)
+    }
+
+    /// Given a query `q`, return a close point according to dot product, probing only the
+    /// buckets of the Gaussian vectors whose index is in `allowed`.
+    pub fn query_restricted(
+        &self,
+        q: &[f64],
+        allowed: &HashSet<usize>,
+    ) -> Result<Option<Vec<f64>>, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_restricted(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.beta,
+            self.metric,
+            allowed,
+        )
+    }
+
+    /// Given a query `q`, count the points in the probed buckets within Euclidean distance
+    /// `radius` from the query. With `Metric::CosineRaw` the query and the points are
+    /// normalized, so the radius bounds the distance between their directions.
+    pub fn count_within_radius(&self, q: &[f64], radius: f64) -> Result<usize, io::Error> {
+        let q = &*self.check_query(q)?;
+        count_within_radius(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.metric,
+            radius,
+        )
+    }
+
+    /// Given a query `q`, return a close point according to `metric`, probing only the buckets
+    /// of the `p` Gaussian vectors closest to the query, regardless of the threshold.
+    pub fn query_top_p(&self, q: &[f64], p: usize) -> Result<Option<Vec<f64>>, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_top_p(
+            &self.gaussian_vectors,
+            q,
+            p,
+            &self.hash_table,
+            self.beta,
+            self.metric,
+        )
+    }
+
+    /// Given a query `q`, return a close point according to `metric`, scanning only the probed
+    /// buckets with at least `min_bucket_size` points.
+    pub fn query_min_bucket(
+        &self,
+        q: &[f64],
+        min_bucket_size: usize,
+    ) -> Result<Option<Vec<f64>>, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_min_bucket(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.beta,
+            self.metric,
+            min_bucket_size,
+        )
+    }
+
+    /// Given a query `q`, return the ids and dot products of the close points of the probed
+    /// buckets by decreasing dot product, up to the first drop of more than `gap`.
+    pub fn query_until_gap(&self, q: &[f64], gap: f64) -> Result<Vec<(usize, f64)>, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_until_gap(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            &self.id_table,
+            self.beta,
+            self.metric,
+            gap,
+        )
+    }
+
+    /// Given a query `q`, return the scores (see `Metric::score`) and copies of all the distinct
+    /// points of the probed buckets close to the query according to `metric`, by decreasing
+    /// score.
+    pub fn query_all(&self, q: &[f64]) -> Result<Vec<(f64, Vec<f64>)>, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_all(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            &self.id_table,
+            self.beta,
+            self.metric,
+        )
+    }
+
+    /// Given a query `q`, return the dot products and copies of the points with dot product at
+    /// least `beta` with the query, grouped by the index of the Gaussian vector whose bucket
+    /// holds them, to see how the candidates spread over the probed buckets.
+    pub fn query_grouped(&self, q: &[f64]) -> Result<GroupedCandidates, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_grouped(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.beta,
+            self.metric,
+        )
+    }
+
+    /// Given a query `q`, count the distinct points in the probed buckets close to the query
+    /// according to `metric`, i.e. the length of `neighbors`.
+    pub fn count_neighbors(&self, q: &[f64]) -> Result<usize, io::Error> {
+        self.neighbors(q).map(|ids| ids.len())
+    }
+
+    /// Given a query `q`, return the sorted ids of the distinct points in the probed buckets
+    /// close to the query according to `metric`, e.g. to audit the exact count before noise
+    /// is added to it. Sorted buckets are only scanned in the range that can hold a close point.
+    pub fn neighbors(&self, q: &[f64]) -> Result<Vec<usize>, io::Error> {
+        let q = &*self.check_query(q)?;
+        neighbors(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            &self.id_table,
+            self.bucket_projections.as_ref(),
+            self.beta,
+            self.metric,
+        )
+    }
+
+    /// Given a query `q`, return the mean of `count_neighbors` over `repeats` rehashes of a
+    /// copy of the structure, seeded with `seed`, `seed + 1`, ... The structure itself is not
+    /// modified. Averaging reduces the variance of the count due to the random projections.
+    /// An error is returned if `repeats` is 0.
+    pub fn count_neighbors_robust(
+        &self,
+        q: &[f64],
+        repeats: usize,
+        seed: u64,
+    ) -> Result<f64, io::Error> {
+        if repeats == 0 {
+            return Err(AnnError::InvalidParameter("repeats must be positive".to_string()).into());
+        }
+        let mut copy = self.clone();
+        let mut total = 0;
+        for r in 0..repeats {
+            copy.rehash(seed.wrapping_add(r as u64));
+            total += copy.count_neighbors(q)?;
+        }
+        Ok(total as f64 / repeats as f64)
+    }
+
+    /// Given a query `q`, count the distinct points of the probed buckets without computing
+    /// their dot product with the query. This coarse estimate is cheaper than
+    /// `count_neighbors`, but over-counts it: every point counted there is counted here.
+    pub fn count_bucket_members(&self, q: &[f64]) -> Result<usize, io::Error> {
+        let q = &*self.check_query(q)?;
+        count_bucket_members(&self.gaussian_vectors, q, self.threshold, &self.id_table)
+    }
+
+    /// Given a query `q`, return the total size of the buckets it probes, without scoring their
+    /// points, to predict the cost of the query. `count_neighbors` scores exactly this many
+    /// points; `query` scores at most this many, since it stops at the first close point.
+    pub fn expected_candidates(&self, q: &[f64]) -> Result<usize, io::Error> {
+        let q = &*self.check_query(q)?;
+        expected_candidates(&self.gaussian_vectors, q, self.threshold, &self.hash_table)
+    }
+
+    /// Like `count_neighbors`, but the scan stops at `cap` close points, returning
+    /// `CountResult::AtLeast(cap)`, so that the work on huge buckets is bounded.
+    pub fn count_neighbors_capped(&self, q: &[f64], cap: usize) -> Result<CountResult, io::Error> {
+        let q = &*self.check_query(q)?;
+        count_neighbors_capped(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.beta,
+            self.metric,
+            cap,
+        )
+    }
+
+    /// Like `count_neighbors`, but the distinct ids of the close points are estimated in
+    /// bounded memory by a HyperLogLog sketch, with a standard error of about 3.3%.
+    pub fn count_neighbors_hll(&self, q: &[f64]) -> Result<f64, io::Error> {
+        let q = &*self.check_query(q)?;
+        count_neighbors_hll(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            &self.id_table,
+            self.beta,
+            self.metric,
+        )
+    }
+
+    /// Run `count_neighbors` on each query in parallel. An invalid query only fails its own
+    /// count.
+    pub fn count_batch(&self, queries: &[Vec<f64>]) -> Vec<Result<usize, io::Error>> {
+        queries
+            .par_iter()
+            .map(|q| self.count_neighbors(q))
+            .collect()
+    }
+
+    /// Like `count_batch`, but each count is released with epsilon-differential privacy by the
+    /// Laplace mechanism, see `privacy::private_count`.
+    pub fn count_batch_private(
+        &self,
+        queries: &[Vec<f64>],
+        epsilon: f64,
+    ) -> Vec<Result<f64, io::Error>> {
+        self.count_batch_private_with(queries, NoiseMechanism::Laplace { epsilon })
+    }
+
+    /// Like `count_batch_private`, with the noise of `mechanism`.
+    pub fn count_batch_private_with(
+        &self,
+        queries: &[Vec<f64>],
+        mechanism: NoiseMechanism,
+    ) -> Vec<Result<f64, io::Error>> {
+        if let Err(err) = mechanism.check() {
+            return queries
+                .iter()
+                .map(|_| Err(io::Error::new(err.kind(), err.to_string())))
+                .collect();
+        }
+        queries
+            .par_iter()
+            .map(|q| {
+                private_count_with(&mut rand::thread_rng(), self.count_neighbors(q)?, mechanism)
+            })
+            .collect()
+    }
+
+    /// Given a query `q`, estimate the number of points with dot product at least `beta` with
+    /// the query. Return the estimate and the margin of its confidence interval at level
+    /// `confidence`, based on the collision probability of the close points found.
+    pub fn count_neighbors_ci(
+        &self,
+        q: &[f64],
+        confidence: f64,
+    ) -> Result<(usize, f64), io::Error> {
+        let q = &*self.check_query(q)?;
+        count_neighbors_ci(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            self.beta,
+            self.metric,
+            confidence,
+        )
+    }
+
+    /// Insert a new point in the bucket of its closest Gaussian vector.
+    /// If the point does not have dimension `d` or is not normalized, an error is returned.
+    /// If a write-ahead log is attached, the point is appended to it before being inserted.
+    pub fn insert(&mut self, p: Vec<f64>) -> Result<(), io::Error> {
+        self.check_point(&p)?;
+        self.append_to_wal(&p)?;
+        if let Some(drift) = self.drift.as_mut() {
+            drift.update(&p);
+        }
+        self.insert_in_memory(p);
+        self.auto_rebuild();
+        Ok(())
+    }
+
+    /// Return true if the points exceed `Top1Config::auto_rebuild_factor` times the number
+    /// of points `m` was tuned for.
+    fn rebuild_pending(&self) -> bool {
+        self.auto_rebuild_factor
+            .is_some_and(|factor| self.len() as f64 > factor * self.tuned_n as f64)
+    }
+
+    /// Rebuild the structure if a rebuild is pending, see `Top1Config::auto_rebuild_factor`.
+    pub(super) fn auto_rebuild(&mut self) {
+        if let Some(factor) = self.auto_rebuild_factor.filter(|_| self.rebuild_pending()) {
+            let n = self.len();
+            log::info!(
+                "{} points exceed {} times the tuned {}: rebuilding.",
+                n,
+                factor,
+                self.tuned_n
+            );
+            self.rebuild();
+        }
+    }
+
+    /// Check that `p` can be inserted: it must have dimension `d` and, if the metric requires
+    /// it, be normalized.
+    pub(super) fn check_point(&self, p: &[f64]) -> Result<(), io::Error> {
+        check_dimension(self.d, p.len())?;
+        if self.metric.requires_normalized() && !is_normalized(p) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Inserted vector is not normalized",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Recompute `m` for the current number of points, with the exponent `theta` the
+    /// structure was built with, and rebuild the buckets with new Gaussian vectors (see
+    /// `rehash`). If `theta` is unknown (e.g. a structure assembled from projections) the
+    /// exponent is inferred from `m` and the number of points it was computed for. The seed of
+    /// the new Gaussian vectors is derived from the build seed, so seeded structures rebuild
+    /// reproducibly.
+    pub fn rebuild(&mut self) {
+        let n = self.len();
+        let mut m = match self.theta {
+            Some(theta) => compute_m(n, self.alpha, theta),
+            // m = n^exponent for the tuned number of points
+            None if self.tuned_n > 1 && self.m > 1 => {
+                let exponent = (self.m as f64).ln() / (self.tuned_n as f64).ln();
+                (n as f64).powf(exponent).ceil() as usize
+            }
+            None => self.m,
+        };
+        if let Some(max_m) = self.max_m {
+            m = m.min(max_m);
+        }
+        // An empty structure still needs a Gaussian vector to insert into, as in `set_m`
+        self.m = m.max(1);
+        let seed = self.next_rehash_seed();
+        self.rehash(seed);
+        self.tuned_n = n;
+        if self.drift.is_some() {
+            self.drift = Some(DriftStats::new(self.d));
+        }
+    }
+
+    /// Return the seed of the next rebuild and advance the stored one.
+    pub(super) fn next_rehash_seed(&mut self) -> u64 {
+        let seed = self.rehash_seed;
+        self.rehash_seed = StdRng::seed_from_u64(seed).gen();
+        seed
+    }
+
+    /// Draw `new_m` new Gaussian vectors, recompute the threshold for `new_m` and rebuild the
+    /// buckets from the stored points (see `rehash`). A smaller `m` trades recall for memory:
+    /// fewer Gaussian vectors are stored and the buckets are coarser. `new_m` is clamped to at
+    /// least 1, and `Top1Config::max_m` is not applied. As with `rebuild`, the seed of the new
+    /// Gaussian vectors is derived from the build seed and `m` is now tuned for the current
+    /// number of points.
+    pub fn rebuild_with_m(&mut self, new_m: usize) {
+        self.set_m(new_m);
+        self.tuned_n = self.len();
+    }
+
+    /// Return the statistics of the points inserted since the build or the last `rebuild`, if
+    /// tracked (see `Top1Config::track_drift`), so that operators can decide when to rebuild.
+    pub fn drift_stats(&self) -> Option<&DriftStats> {
+        self.drift.as_ref()
+    }
+
+    /// Insert a point in the hash table without touching the write-ahead log.
+    pub(super) fn insert_in_memory(&mut self, p: Vec<f64>) {
+        let index = get_closest_gaussian(&p, &self.gaussian_vectors);
+        if let Some(bloom_filters) = &mut self.bloom_filters {
+            let filter = bloom_filters
+                .entry(index)
+                .or_insert_with(|| BloomFilter::new(1));
+            if filter.len() < filter.capacity() {
+                filter.insert(&p);
+            } else {
+                // Resize a full filter to keep its false positive rate
+                let bucket = self.hash_table.get(&index).map_or(&[][..], |b| &b[..]);
+                *filter = bucket_bloom_filter(bucket, 2 * filter.capacity());
+                filter.insert(&p);
+            }
+        }
+        match &mut self.bucket_projections {
+            None => {
+                let ids = self.id_table.entry(index).or_default();
+                self.locations.insert(self.next_id, (index, ids.len()));
+                self.hash_table.entry(index).or_default().push(p);
+                ids.push(self.next_id);
+            }
+            // Keep the bucket sorted by decreasing projection
+            Some(bucket_projections) => {
+                let projection = normalized_projection(&p, &self.gaussian_vectors[index]);
+                let projections = bucket_projections.entry(index).or_default();
+                let position = projections.partition_point(|&x| x >= projection);
+                projections.insert(position, projection);
+                self.hash_table
+                    .entry(index)
+                    .or_default()
+                    .insert(position, p);
+                let ids = self.id_table.entry(index).or_default();
+                ids.insert(position, self.next_id);
+                // The points after the new one move by one position
+                for (k, id) in ids.iter().enumerate().skip(position) {
+                    self.locations.insert(*id, (index, k));
+                }
+            }
+        }
+        self.next_id += 1;
+    }
+
+    /// Drop every stored point for which `keep` returns false, and the buckets left empty.
+    /// Removals are not recorded in the write-ahead log: save a snapshot to persist them.
+    pub fn retain(&mut self, keep: impl Fn(&[f64]) -> bool) {
+        self.retain_with_ids(|_, point| keep(point));
+    }
+
+    /// Like `retain`, but `keep` also receives the id of the point.
+    pub(super) fn retain_with_ids(&mut self, keep: impl Fn(usize, &[f64]) -> bool) {
+        for (i, vectors) in self.hash_table.iter_mut() {
+            let ids = self.id_table.entry(*i).or_default();
+            let mut projections = self
+                .bucket_projections
+                .as_mut()
+                .and_then(|bucket_projections| bucket_projections.get_mut(i));
+            // Compact the bucket in place, keeping the three lists aligned
+            let mut kept = 0;
+            for k in 0..vectors.len() {
+                if keep(ids[k], &vectors[k]) {
+                    vectors.swap(kept, k);
+                    ids.swap(kept, k);
+                    if let Some(projections) = projections.as_mut() {
+                        projections.swap(kept, k);
+                    }
+                    kept += 1;
+                }
+            }
+            vectors.truncate(kept);
+            ids.truncate(kept);
+            if let Some(projections) = projections {
+                projections.truncate(kept);
+            }
+        }
+
+        // Prune the emptied buckets
+        self.hash_table.retain(|_, vectors| !vectors.is_empty());
+        let hash_table = &self.hash_table;
+        self.id_table.retain(|i, _| hash_table.contains_key(i));
+        if let Some(bucket_projections) = self.bucket_projections.as_mut() {
+            bucket_projections.retain(|i, _| hash_table.contains_key(i));
+        }
+        self.locations = locate_ids(&self.id_table);
+        // Points cannot be removed from a Bloom filter
+        if self.bloom_filters.is_some() {
+            self.build_bloom_filters();
+        }
+    }
+
+    /// Return true if the point `p` is stored. The bucket of `p` is only scanned if its Bloom
+    /// filter, when enabled, reports that it may hold `p`.
+    pub fn contains(&self, p: &[f64]) -> bool {
+        let index = get_closest_gaussian(p, &self.gaussian_vectors);
+        if let Some(bloom_filters) = &self.bloom_filters {
+            if !bloom_filters
+                .get(&index)
+                .is_some_and(|filter| filter.contains(p))
+            {
+                return false;
+            }
+        }
+        self.hash_table
+            .get(&index)
+            .is_some_and(|bucket| bucket.iter().any(|v| v[..] == *p))
+    }
+
+    /// With Bloom filters, return the stored copy of `q` if its bucket is probed, which is the
+    /// closest possible answer. The bucket is only scanned if its filter reports that it may
+    /// hold `q`, so a query that is not stored costs one lookup in the filter.
+    fn probed_copy(&self, q: &[f64], beta: f64) -> Option<&Vec<f64>> {
+        let bloom_filters = self.bloom_filters.as_ref()?;
+        if self.metric.requires_normalized() && !is_normalized(q) {
+            return None;
+        }
+        let index = get_closest_gaussian(q, &self.gaussian_vectors);
+        if dot_product(q, &self.gaussian_vectors[index]) < self.threshold
+            || !bloom_filters
+                .get(&index)
+                .is_some_and(|filter| filter.contains(q))
+        {
+            return None;
+        }
+        self.hash_table
+            .get(&index)?
+            .iter()
+            .find(|v| v[..] == *q && self.metric.accepts(q, v, beta))
+    }
+
+    /// Return true if the buckets have Bloom filters, see `Top1Config::bloom_filters`.
+    pub fn bloom_filters(&self) -> bool {
+        self.bloom_filters.is_some()
+    }
+
+    /// Build the Bloom filter of each bucket from its points.
+    pub(super) fn build_bloom_filters(&mut self) {
+        let bloom_filters = self
+            .hash_table
+            .iter()
+            .map(|(i, vectors)| (*i, bucket_bloom_filter(vectors, vectors.len())))
+            .collect();
+        self.bloom_filters = Some(bloom_filters);
+    }
+
+    /// Return true if the buckets are sorted, see `Top1Config::sorted_buckets`.
+    pub fn sorted_buckets(&self) -> bool {
+        self.bucket_projections.is_some()
+    }
+
+    /// Sort each bucket by decreasing dot product of its points with the normalized Gaussian
+    /// vector, and store these dot products.
+    pub(super) fn sort_buckets(&mut self) {
+        let mut bucket_projections = HashMap::new();
+        for (i, vectors) in self.hash_table.iter_mut() {
+            let ids = self.id_table.entry(*i).or_default();
+            let gaussian_vector = &self.gaussian_vectors[*i];
+            let mut entries: Vec<(f64, Vec<f64>, usize)> = std::mem::take(vectors)
+                .into_iter()
+                .zip(std::mem::take(ids))
+                .map(|(v, id)| (normalized_projection(&v, gaussian_vector), v, id))
+                .collect();
+            entries.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+            let mut projections = Vec::with_capacity(entries.len());
+            for (projection, v, id) in entries {
+                projections.push(projection);
+                vectors.push(v);
+                ids.push(id);
+            }
+            bucket_projections.insert(*i, projections);
+        }
+        self.bucket_projections = Some(bucket_projections);
+        self.locations = locate_ids(&self.id_table);
+    }
+
+    /// Merge the points of `other` into this structure, bucket by bucket.
+    ///
+    /// Both structures must share the same Gaussian vectors (e.g. built with the same seed on
+    /// shards of the same size), parameters and metric, otherwise their buckets are incompatible
+    /// and an error is returned. The merged points are not appended to the write-ahead log, and
+    /// their ids are shifted after the ids of this structure. The structure is rebuilt if the
+    /// merged points exceed `Top1Config::auto_rebuild_factor`.
+    pub fn merge(&mut self, other: Top1) -> Result<(), AnnError> {
+        check_dimension(self.d, other.d)?;
+        if self.gaussian_vectors != other.gaussian_vectors {
+            return Err(AnnError::IncompatibleProjections);
+        }
+        if self.alpha != other.alpha {
+            return Err(AnnError::IncompatibleParameters("alpha".to_string()));
+        }
+        if self.beta != other.beta {
+            return Err(AnnError::IncompatibleParameters("beta".to_string()));
+        }
+        if self.threshold != other.threshold {
+            return Err(AnnError::IncompatibleParameters("threshold".to_string()));
+        }
+        // The metrics do not agree on whether the points are normalized
+        if self.metric != other.metric {
+            return Err(AnnError::IncompatibleParameters("metric".to_string()));
+        }
+
+        // Track the merged points in id order, as if they were inserted
+        if let Some(drift) = self.drift.as_mut() {
+            let mut ids: Vec<usize> = other.locations.keys().copied().collect();
+            ids.sort_unstable();
+            for id in ids {
+                if let Some(p) = other.point(id) {
+                    drift.update(p);
+                }
+            }
+        }
+        for (i, vectors) in other.hash_table {
+            self.hash_table.entry(i).or_default().extend(vectors);
+        }
+        for (i, ids) in other.id_table {
+            let offset = self.next_id;
+            self.id_table
+                .entry(i)
+                .or_default()
+                .extend(ids.into_iter().map(|id| id + offset));
+        }
+        self.locations = locate_ids(&self.id_table);
+        self.next_id += other.next_id;
+        if self.sorted_buckets() {
+            self.sort_buckets();
+        }
+        if self.bloom_filters() {
+            self.build_bloom_filters();
+        }
+        self.auto_rebuild();
+        Ok(())
+    }
+
+    /// Regenerate the Gaussian vectors from `seed` and rebuild the buckets from the stored
+    /// points, without the caller re-supplying the data. The ids of the points are preserved.
+    /// Averaging counts over rehashes with different seeds reduces the LSH variance.
+    pub fn rehash(&mut self, seed: u64) {
+        let gaussian_vectors =
+            generate_normal_gaussian_vectors_seeded(self.m, self.d, seed).unwrap();
+
+        // Move the stored points into the new buckets, visiting the old buckets in index order
+        // so that the order of the points does not depend on the iteration order of the map
+        let mut hash_table: HashTable = HashMap::new();
+        let mut id_table: IdTable = HashMap::new();
+        let mut buckets: Vec<_> = std::mem::take(&mut self.hash_table).into_iter().collect();
+        buckets.sort_unstable_by_key(|(i, _)| *i);
+        let mut ids = std::mem::take(&mut self.id_table);
+        for (i, vectors) in buckets {
+            let bucket_ids = ids.remove(&i).unwrap_or_default();
+            for (point, id) in vectors.into_iter().zip(bucket_ids) {
+                let closest = get_closest_gaussian(&point, &gaussian_vectors);
+                hash_table.entry(closest).or_default().push(point);
+                id_table.entry(closest).or_default().push(id);
+            }
+        }
+
+        self.gaussian_vectors = gaussian_vectors;
+        self.hash_table = hash_table;
+        self.locations = locate_ids(&id_table);
+        self.id_table = id_table;
+        self.refresh_threshold();
+        if self.sorted_buckets() {
+            self.sort_buckets();
+        }
+        if self.bloom_filters() {
+            self.build_bloom_filters();
+        }
+    }
+
+    /// Set the number of Gaussian vectors to `m` (clamped to at least 1), draw `m` new
+    /// Gaussian vectors and rebuild the buckets with the threshold for `m` (see `rehash`), so
+    /// that `m`, the Gaussian vectors and the threshold never disagree.
+    pub fn set_m(&mut self, m: usize) {
+        self.m = m.max(1);
+        let seed = self.next_rehash_seed();
+        self.rehash(seed);
+    }
+
+    /// Recompute the threshold from `alpha` and `m`, keeping the invariant
+    /// `threshold == get_threshold(alpha, m)` after `m` changed. If the threshold is
+    /// overridden (see `set_threshold_override`), the override is kept instead.
+    pub fn refresh_threshold(&mut self) {
+        self.threshold = self
+            .threshold_override
+            .unwrap_or_else(|| get_threshold(self.alpha, self.m));
+    }
+
+    /// Probe the Gaussian vectors with `threshold` instead of `get_threshold(alpha, m)`, also
+    /// after `set_m`, `rehash`, `rebuild` and `rebuild_with_m`. `None` restores the theoretical
+    /// threshold.
+    pub fn set_threshold_override(&mut self, threshold: Option<f64>) {
+        self.threshold_override = threshold;
+        self.refresh_threshold();
+    }
+
+    /// Return the threshold set with `set_threshold_override` or `calibrate_threshold`, if any.
+    pub fn threshold_override(&self) -> Option<f64> {
+        self.threshold_override
+    }
+
+    /// Write the Gaussian vector each point is assigned to as CSV rows
+    /// `point_index,gaussian_index`, after a header line and sorted by point index.
+    pub fn export_assignments_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut assignments: Vec<(usize, usize)> = self
+            .id_table
+            .iter()
+            .flat_map(|(i, ids)| ids.iter().map(move |id| (*id, *i)))
+            .collect();
+        assignments.sort_unstable();
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "point_index,gaussian_index")?;
+        for (id, i) in assignments {
+            writeln!(writer, "{},{}", id, i)?;
+        }
+        writer.flush()
+    }
+
+    /// Return true if `other` has the same Gaussian vectors, parameters and buckets, the points
+    /// of a bucket being compared as a set of (id, point) pairs regardless of their order.
+    pub fn structural_eq(&self, other: &Top1) -> bool {
+        self.diff(other).is_empty()
+    }
+
+    /// Report which Gaussian vectors, parameters and buckets differ between the structure and
+    /// `other`, e.g. to check that a refactoring of the construction did not change the index.
+    /// The floats are compared bitwise, so that NaN parameters are equal.
+    pub fn diff(&self, other: &Top1) -> StructureDiff {
+        let bits = |x: f64| x.to_bits();
+        let parameters = [
+            ("alpha", bits(self.alpha) == bits(other.alpha)),
+            ("beta", bits(self.beta) == bits(other.beta)),
+            ("threshold", bits(self.threshold) == bits(other.threshold)),
+            ("m", self.m == other.m),
+            ("d", self.d == other.d),
+            ("metric", self.metric == other.metric),
+        ];
+
+        // Sorted (id, point) pairs of a bucket
+        let contents = |top1: &Top1, i: usize| -> Vec<(usize, Vec<u64>)> {
+            let (Some(vectors), Some(ids)) = (top1.hash_table.get(&i), top1.id_table.get(&i))
+            else {
+                return Vec::new();
+            };
+            let mut contents: Vec<(usize, Vec<u64>)> = ids
+                .iter()
+                .zip(vectors)
+                .map(|(id, v)| (*id, v.iter().map(|x| x.to_bits()).collect()))
+                .collect();
+            contents.sort_unstable();
+            contents
+        };
+        let indices: HashSet<usize> = self
+            .hash_table
+            .keys()
+            .chain(other.hash_table.keys())
+            .copied()
+            .collect();
+        let mut buckets: Vec<usize> = indices
+            .into_iter()
+            .filter(|i| contents(self, *i) != contents(other, *i))
+            .collect();
+        buckets.sort_unstable();
+
+        StructureDiff {
+            projections: self.gaussian_vectors != other.gaussian_vectors,
+            parameters: parameters
+                .iter()
+                .filter(|(_, equal)| !equal)
+                .map(|(name, _)| *name)
+                .collect(),
+            buckets,
+        }
+    }
+
+    /// Check the internal invariants of the structure and return a description of the first
+    /// violation: `m` is the number of Gaussian vectors, the threshold is the override (see
+    /// `set_threshold_override`) or else `get_threshold(alpha, m)`, the stored points have dimension `d`, are normalized (unless
+    /// the metric is `Metric::CosineRaw`) and are
+    /// in the bucket of their closest Gaussian vector, every bucket has one id per point, the
+    /// projections of sorted buckets and the Bloom filters (if enabled) match the points, every
+    /// id is located at its point, and no rebuild is pending (see
+    /// `Top1Config::auto_rebuild_factor`). This costs O(n m) and is meant for debugging and tests.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.m != self.gaussian_vectors.len() {
+            return Err(format!(
+                "m is {} but there are {} Gaussian vectors",
+                self.m,
+                self.gaussian_vectors.len()
+            ));
+        }
+        let (threshold, expected) = match self.threshold_override {
+            Some(threshold) => (threshold, "the override"),
+            None => (get_threshold(self.alpha, self.m), "get_threshold(alpha, m)"),
+        };
+        if self.threshold != threshold && !(self.threshold.is_nan() && threshold.is_nan()) {
+            return Err(format!(
+                "threshold is {} but {} is {}",
+                self.threshold, expected, threshold
+            ));
+        }
+        for (i, vectors) in self.hash_table.iter() {
+            let ids = self.id_table.get(i).map_or(0, |ids| ids.len());
+            if ids != vectors.len() {
+                return Err(format!(
+                    "bucket {} has {} points but {} ids",
+                    i,
+                    vectors.len(),
+                    ids
+                ));
+            }
+            for (k, vector) in vectors.iter().enumerate() {
+                if vector.len() != self.d {
+                    return Err(format!(
+                        "point {} of bucket {} has dimension {} instead of {}",
+                        k,
+                        i,
+                        vector.len(),
+                        self.d
+                    ));
+                }
+                if self.metric.requires_normalized() && !is_normalized(vector) {
+                    return Err(format!("point {} of bucket {} is not normalized", k, i));
+                }
+                let closest = get_closest_gaussian(vector, &self.gaussian_vectors);
+                if closest != *i {
+                    return Err(format!(
+                        "point {} of bucket {} belongs to bucket {}",
+                        k, i, closest
+                    ));
+                }
+            }
+            if let Some(bucket_projections) = &self.bucket_projections {
+                let projections = bucket_projections.get(i).map_or(&[][..], |p| &p[..]);
+                if projections.len() != vectors.len() {
+                    return Err(format!(
+                        "bucket {} has {} points but {} projections",
+                        i,
+                        vectors.len(),
+                        projections.len()
+                    ));
+                }
+                for (k, (vector, projection)) in vectors.iter().zip(projections).enumerate() {
+                    let expected = normalized_projection(vector, &self.gaussian_vectors[*i]);
+                    if (projection - expected).abs() > 1e-9 {
+                        return Err(format!(
+                            "point {} of bucket {} has projection {} instead of {}",
+                            k, i, projection, expected
+                        ));
+                    }
+                }
+                if projections.windows(2).any(|w| w[0] < w[1]) {
+                    return Err(format!("bucket {} is not sorted by projection", i));
+                }
+            }
+            if let Some(bloom_filters) = &self.bloom_filters {
+                let filter = bloom_filters.get(i);
+                if let Some(k) = vectors
+                    .iter()
+                    .position(|v| !filter.is_some_and(|f| f.contains(v)))
+                {
+                    return Err(format!(
+                        "point {} of bucket {} is missing from its Bloom filter",
+                        k, i
+                    ));
+                }
+            }
+        }
+
+        // Every id is located at its position in `id_table`
+        let ids: usize = self.id_table.values().map(|ids| ids.len()).sum();
+        if self.locations.len() != ids {
+            return Err(format!(
+                "{} ids are located but {} are stored",
+                self.locations.len(),
+                ids
+            ));
+        }
+        for (i, ids) in self.id_table.iter() {
+            for (k, id) in ids.iter().enumerate() {
+                if self.locations.get(id) != Some(&(*i, k)) {
+                    return Err(format!(
+                        "id {} is not located at point {} of bucket {}",
+                        id, k, i
+                    ));
+                }
+            }
+        }
+
+        // `insert` rebuilds once the points exceed `auto_rebuild_factor` times `tuned_n`
+        if self.rebuild_pending() {
+            return Err(format!(
+                "{} points exceed the auto rebuild factor times the tuned {}: a rebuild is pending",
+                ids, self.tuned_n
+            ));
+        }
+        Ok(())
+    }
+
+    /// Set the threshold so that the queries of `sample_queries` probe on average about
+    /// `target_hits` Gaussian vectors, by a binary search on the threshold. The result is set
+    /// as the threshold override (see `set_threshold_override`), so that the query cost no
+    /// longer follows the theoretical `get_threshold(alpha, m)`, also after a rebuild; note
+    /// that a threshold calibrated for one `m` need not suit another.
+    /// The threshold is unchanged if `sample_queries` is empty.
+    pub fn calibrate_threshold(&mut self, sample_queries: &[Vec<f64>], target_hits: usize) {
+        if sample_queries.is_empty() {
+            return;
+        }
+        // Dot products of the queries with the Gaussian vectors
+        let scores: Vec<Vec<f64>> = sample_queries
+            .iter()
+            .map(|q| {
+                self.gaussian_vectors
+                    .iter()
+                    .map(|g| dot_product(q, g))
+                    .collect()
+            })
+            .collect();
+        let average_hits = |threshold: f64| {
+            let hits: usize = scores
+                .iter()
+                .map(|row| row.iter().filter(|&&score| score >= threshold).count())
+                .sum();
+            hits as f64 / scores.len() as f64
+        };
+
+        // Largest threshold with at least `target_hits` hits on average
+        let flat = scores.iter().flatten();
+        let mut low = flat.clone().copied().fold(f64::INFINITY, f64::min);
+        let mut high = flat.copied().fold(f64::NEG_INFINITY, f64::max);
+        for _ in 0..100 {
+            let middle = (low + high) / 2.;
+            if average_hits(middle) >= target_hits as f64 {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        self.set_threshold_override(Some(low));
+    }
+
+    /// Return an iterator over the stored points, in no particular order.
+    pub fn iter_points(&self) -> impl Iterator<Item = &Vec<f64>> {
+        self.hash_table.values().flatten()
+    }
+
+    /// Return the number of points stored in the hash table.
+    pub fn len(&self) -> usize {
+        self.hash_table.values().map(|bucket| bucket.len()).sum()
+    }
+
+    /// Return true if no point is stored in the hash table.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return an estimate of the heap memory used by the structure in bytes: the Gaussian
+    /// vectors, the entries of the buckets, the stored points, their ids and locations, the
+    /// projections of sorted buckets and the Bloom filters. The spare capacity of the hash maps
+    /// is not counted.
+    pub fn memory_footprint(&self) -> usize {
+        fn vectors_bytes(vectors: &[Vec<f64>]) -> usize {
+            vectors
+                .iter()
+                .map(|v| size_of::<Vec<f64>>() + v.capacity() * size_of::<f64>())
+                .sum()
+        }
+        let gaussians = vectors_bytes(&self.gaussian_vectors);
+        let entries = self.hash_table.len() * size_of::<(usize, Vec<Vec<f64>>)>()
+            + self.id_table.len() * size_of::<(usize, Vec<usize>)>();
+        let points: usize = self
+            .hash_table
+            .values()
+            .map(|bucket| vectors_bytes(bucket))
+            .sum();
+        let ids: usize = self
+            .id_table
+            .values()
+            .map(|ids| ids.capacity() * size_of::<usize>())
+            .sum();
+        let locations = self.locations.len() * size_of::<(usize, (usize, usize))>();
+        let projections: usize = self
+            .bucket_projections
+            .iter()
+            .flat_map(|projections| projections.values())
+            .map(|p| size_of::<(usize, Vec<f64>)>() + p.capacity() * size_of::<f64>())
+            .sum();
+        let filters: usize = self
+            .bloom_filters
+            .iter()
+            .flat_map(|filters| filters.values())
+            .map(|filter| filter.memory_footprint())
+            .sum();
+        gaussians + entries + points + ids + locations + projections + filters
+    }
+
+    /// Return the largest `m` such that `n` points of dimension `d` indexed with `m` Gaussian
+    /// vectors fit in `budget_bytes`, as counted by `memory_footprint` without Bloom filters
+    /// or sorted buckets, see `rebuild_with_m` to apply it. The points, their ids and locations
+    /// take a fixed part of the budget, the ids with the spare capacity of their vectors. Each
+    /// Gaussian vector costs its `d` floats and its vector header, plus one entry in the hash
+    /// table and one in the id table. None is returned if not even one Gaussian vector fits.
+    pub fn max_m_for_budget(budget_bytes: usize, n: usize, d: usize) -> Option<usize> {
+        let vector_bytes = size_of::<Vec<f64>>() + d * size_of::<f64>();
+        // A vector of ids holds at most twice its length, and at least 4 ids once allocated
+        let points =
+            n * (vector_bytes + 2 * size_of::<usize>() + size_of::<(usize, (usize, usize))>());
+        let per_gaussian = vector_bytes
+            + size_of::<(usize, Vec<Vec<f64>>)>()
+            + size_of::<(usize, Vec<usize>)>()
+            + 4 * size_of::<usize>();
+        let m = budget_bytes.checked_sub(points)? / per_gaussian;
+        (m > 0).then_some(m)
+    }
+
+    /// Return the dimension of the indexed vectors.
+    pub fn dim(&self) -> usize {
+        self.d
+    }
+
+    /// Return the size of the largest bucket relative to the expected size `n / m` of a bucket
+    /// under a uniform assignment of the points. Returns 0 if no point is stored.
+    pub fn max_bucket_skew(&self) -> f64 {
+        let n = self.len();
+        if n == 0 {
+            return 0.0;
+        }
+        let max_bucket = self
+            .hash_table
+            .values()
+            .map(|bucket| bucket.len())
+            .max()
+            .unwrap_or(0);
+        max_bucket as f64 * self.m as f64 / n as f64
+    }
+
+    /// Estimate the maximum dot product between two distinct stored points from `sample`
+    /// random pairs. It is a cheap diagnostic to check whether `beta` is achievable on the data.
+    ///
+    /// Returns `f64::NEG_INFINITY` if fewer than two points are stored.
+    pub fn max_pairwise_similarity_sample(&self, sample: usize) -> f64 {
+        let points: Vec<&Vec<f64>> = self.iter_points().collect();
+        let n = points.len();
+        if n < 2 {
+            return f64::NEG_INFINITY;
+        }
+
+        let mut rng = rand::thread_rng();
+        let mut max_similarity = f64::NEG_INFINITY;
+        for _ in 0..sample {
+            // Sample two distinct indices
+            let i = rng.gen_range(0..n);
+            let mut j = rng.gen_range(0..n - 1);
+            if j >= i {
+                j += 1;
+            }
+            max_similarity = max_similarity.max(dot_product(points[i], points[j]));
+        }
+        max_similarity
+    }
+}
+
+#[cfg(feature = "ndarray")]
+impl Top1 {
+    /// Constructor for the Top1 struct with the options of `config`, reading each row of the
+    /// n x d array `data` as a point.
+    pub fn from_array2(
+        data: ArrayView2<f64>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        config: Top1Config,
+    ) -> Self {
+        let points = data.rows().into_iter().map(|row| row.to_vec()).collect();
+        Self::with_config(points, alpha, beta, theta, config)
+    }
+
+    /// Like `query`, with the query given as an array view. A contiguous view is read in
+    /// place, any other view is copied first.
+    pub fn query_array(&self, q: ArrayView1<f64>) -> Result<Option<Vec<f64>>, io::Error> {
+        match q.as_slice() {
+            Some(q) => self.query(q),
+            None => self.query(&q.to_vec()),
+        }
+    }
+}
+
+/// Summarize the parameters of the structure instead of dumping its vectors.
+impl fmt::Debug for Top1 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Top1")
+            .field("n", &self.len())
+            .field("d", &self.d)
+            .field("m", &self.m)
+            .field("alpha", &self.alpha)
+            .field("beta", &self.beta)
+            .field("threshold", &self.threshold)
+            .field("num_buckets", &self.hash_table.len())
+            .finish()
+    }
+}
+
+/// For each vector in `data`, find the Gaussian vector with the highest dot product.
+/// Store the result in a `HashMap` where the key is the index of the Gaussian vector and
+/// the value is the list of data vectors that are closest to it. A second `HashMap` stores
+/// the indices in `data` of the vectors of each bucket, in the same order.
+fn get_hash_table(data: &[Vec<f64>], gaussian_vectors: &[Vec<f64>]) -> (HashTable, IdTable) {
+    get_hash_table_in_order(data, gaussian_vectors, 0..data.len())
+}
+
+/// Like `get_hash_table`, but insert the vectors of `data` in the buckets following the
+/// indices of `order`.
+fn get_hash_table_in_order(
+    data: &[Vec<f64>],
+    gaussian_vectors: &[Vec<f64>],
+    order: impl IntoIterator<Item = usize>,
+) -> (HashTable, IdTable) {
+    // There is at most one bucket per Gaussian vector and per point
+    let capacity = gaussian_vectors.len().min(data.len());
+    let mut closest_gaussian_vectors: HashTable = HashMap::with_capacity(capacity);
+    let mut closest_ids: IdTable = HashMap::with_capacity(capacity);
+
+    // Iterate over each data vector
+    for id in order {
+        let data_vector = &data[id];
+        let max_dot_product_index = get_closest_gaussian(data_vector, gaussian_vectors);
+
+        // Insert or update the list of data vectors for the closest Gaussian vector
+        closest_gaussian_vectors
+            .entry(max_dot_product_index)
+            .or_default()
+            .push(data_vector.clone());
+        closest_ids
+            .entry(max_dot_product_index)
+            .or_default()
+            .push(id);
+    }
+
+    (closest_gaussian_vectors, closest_ids)
+}
+
+impl AnnIndex for Top1 {
+    /// Return the ids of the `k` points with the highest dot product with `q` among the
+    /// buckets probed by the query. The points are not filtered by `beta`.
+    fn query_k(&self, q: &[f64], k: usize) -> Result<Vec<usize>, io::Error> {
+        let q = &*self.check_query(q)?;
+        query_k(
+            &self.gaussian_vectors,
+            q,
+            self.threshold,
+            &self.hash_table,
+            &self.id_table,
+            self.metric,
+            k,
+        )
+    }
+}
+
+/// Build a Bloom filter sized for `capacity` points holding the points of `bucket`.
+fn bucket_bloom_filter(bucket: &[Vec<f64>], capacity: usize) -> BloomFilter {
+    let mut filter = BloomFilter::new(capacity);
+    for v in bucket {
+        filter.insert(v);
+    }
+    filter
+}
+
+/// Return the dot product of `point` with the normalized `gaussian_vector`.
+fn normalized_projection(point: &[f64], gaussian_vector: &[f64]) -> f64 {
+    dot_product(point, gaussian_vector) / dot_product(gaussian_vector, gaussian_vector).sqrt()
+}
+
+/// Return the index of the Gaussian vector with the highest dot product with `point`.
+pub(crate) fn get_closest_gaussian<G: AsRef<[f64]>>(
+    point: &[f64],
+    gaussian_vectors: &[G],
+) -> usize {
+    let mut max_dot_product = f64::MIN;
+    let mut max_dot_product_index = 0;
+
+    // Iterate over each Gaussian vector
+    for (j, gaussian_vector) in gaussian_vectors.iter().enumerate() {
+        // Compute dot product between the point and this Gaussian vector
+        let dot_product_value = dot_product(point, gaussian_vector.as_ref());
+
+        if dot_product_value > max_dot_product {
+            max_dot_product = dot_product_value;
+            max_dot_product_index = j;
+        }
+    }
+
+    max_dot_product_index
+}
+
+/// Test function for Top1 struct.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{generate_spherical_vectors, search};
+
+    /// Test function to check if the Top1 struct works.
+    #[test]
+    fn test_top1_query() {
+        // Create a sample data
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let alpha = 0.9;
+        let beta = 0.8;
+        let theta = 0.5;
+        let top1 = Top1::new(data, alpha, beta, theta);
+
+        // Good query
+        let query = vec![1.0, 0.0, 0.0];
+        let result = top1.query(&query);
+        // if threshold is lower than all the dot products, the result should be None
+        let mut flag: bool = true;
+        for vector in top1.gaussian_vectors.iter() {
+            let dot_product = dot_product(&query, vector);
+            // A vector has a dot product greater than the threshold, so the result should not be None
+            if dot_product >= top1.threshold {
+                println!("Dot product: {}", dot_product);
+                flag = false;
+                break;
+            }
+        }
+        if flag {
+            // Result should be None
+            assert_eq!(result.unwrap(), None);
+        } else {
+            // Result should be close to the query
+            let dot_product = dot_product(&query, &result.unwrap().unwrap());
+            assert!(dot_product >= beta);
+        }
+
+        // Bad query
+        let query = vec![2.0, 0.0, 0.0];
+        let result = top1.query(&query);
+        // Result should be an Error
+        assert!(result.is_err());
+    }
+
+    /// Test function to check that queries accept slices without constructing a Vec.
+    #[test]
+    fn test_query_slice() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let mut top1 = Top1::new(data, 0.9, 0.8, 0.5);
+        // Probe every bucket so that the answer does not depend on the Gaussian vectors
+        top1.threshold = f64::NEG_INFINITY;
+        let q: &[f64] = &[1.0, 0.0, 0.0][..];
+        assert_eq!(top1.query(q).unwrap(), Some(vec![1.0, 0.0, 0.0]));
+        assert_eq!(top1.query_ref(q).unwrap(), Some(&vec![1.0, 0.0, 0.0]));
+        assert!(top1.query(&[2.0, 0.0, 0.0][..]).is_err());
+    }
+
+    /// Test function to check that the predicted recall is a probability that increases with
+    /// theta.
+    #[test]
+    fn test_predicted_recall() {
+        let (n, alpha, beta) = (10_000, 0.9, 0.5);
+        let recalls: Vec<f64> = [0.1, 0.2, 0.3, 0.5]
+            .iter()
+            .map(|&theta| Top1::predicted_recall(n, alpha, beta, theta))
+            .collect();
+        for recall in recalls.iter() {
+            assert!((0.0..=1.0).contains(recall));
+        }
+        assert!(recalls.windows(2).all(|w| w[0] < w[1]), "{:?}", recalls);
+    }
+
+    /// Test function to check that retain drops the points rejected by the predicate.
+    #[test]
+    fn test_retain() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![-1.0, 0.0, 0.0],
+            vec![(0.5f64).sqrt(), (0.5f64).sqrt(), 0.0],
+            vec![0.0, 0.0, 1.0],
+            vec![-(0.5f64).sqrt(), 0.0, (0.5f64).sqrt()],
+        ];
+        let mut top1 = Top1::new(data.clone(), 0.9, 0.8, 0.5);
+        // Probe every bucket so that the answer does not depend on the Gaussian vectors
+        top1.threshold = f64::NEG_INFINITY;
+        top1.retain(|point| point[0] > 0.0);
+
+        assert_eq!(top1.len(), 2);
+        assert!(top1.hash_table.values().all(|vectors| !vectors.is_empty()));
+        for (id, point) in data.iter().enumerate() {
+            let result = top1.query_id(point).unwrap();
+            if point[0] > 0.0 {
+                assert_eq!(result.map(|(id, _)| id), Some(id));
+            } else {
+                assert_eq!(result, None);
+            }
+        }
+    }
+
+    /// Test function to check the len, is_empty and dim accessors.
+    #[test]
+    fn test_len_and_dim() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+            vec![1.0, 0.0, 0.0],
+        ];
+        let top1 = Top1::new(data, 0.9, 0.8, 0.5);
+        assert_eq!(top1.len(), 4);
+        assert!(!top1.is_empty());
+        assert_eq!(top1.dim(), 3);
+    }
+
+    /// Test function to check the sampled max pairwise similarity on orthogonal data.
+    #[test]
+    fn test_max_pairwise_similarity_sample() {
+        let d = 5;
+        let data: Vec<Vec<f64>> = (0..d)
+            .map(|i| (0..d).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+            .collect();
+        let top1 = Top1::new(data, 0.9, 0.8, 0.5);
+        let max_similarity = top1.max_pairwise_similarity_sample(100);
+        assert!(max_similarity.abs() <= 1e-6);
+
+        // A single point has no pairs
+        let top1 = Top1::new(vec![vec![1.0, 0.0, 0.0]], 0.9, 0.8, 0.5);
+        assert_eq!(top1.max_pairwise_similarity_sample(100), f64::NEG_INFINITY);
+    }
+
+    /// Test function to check that a cloned Top1 struct is independent of the original.
+    #[test]
+    fn test_clone_and_insert() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0, 0.0],
+            vec![0.0, 0.0, 1.0, 0.0],
+        ];
+        let mut top1 = Top1::new(data, 0.9, 0.8, 0.5);
+        // Probe every bucket so that the queries are deterministic
+        top1.threshold = f64::NEG_INFINITY;
+
+        let mut cloned = top1.clone();
+        let new_point = vec![0.0, 0.0, 0.0, 1.0];
+        cloned.insert(new_point.clone()).unwrap();
+
+        // The original is unaffected
+        assert_eq!(top1.len(), 3);
+        assert_eq!(top1.query(&new_point).unwrap(), None);
+        // The clone contains the new point
+        assert_eq!(cloned.len(), 4);
+        assert_eq!(cloned.query(&new_point).unwrap(), Some(new_point));
+        // Both answer queries on the original data
+        let query = vec![1.0, 0.0, 0.0, 0.0];
+        assert_eq!(top1.query(&query).unwrap(), Some(query.clone()));
+        assert_eq!(cloned.query(&query).unwrap(), Some(query.clone()));
+
+        // Inserting a non normalized point is an error
+        assert!(cloned.insert(vec![2.0, 0.0, 0.0, 0.0]).is_err());
+    }
+
+    /// Test function to check the construction time report.
+    #[test]
+    fn test_new_timed() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let (top1, report) = Top1::new_timed(data, 0.9, 0.8, 0.5);
+        assert_eq!(top1.len(), 3);
+        assert!(report.total >= report.gaussian_gen + report.hashing);
+    }
+
+    /// Test function to check the L1 metric against a brute-force L1 search.
+    #[test]
+    fn test_query_l1() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+            vec![0.6, 0.8, 0.0],
+        ];
+        let mut top1 = Top1::new(data.clone(), 0.9, 0.8, 0.5);
+        // Probe every bucket so that the result matches a brute-force search
+        top1.threshold = f64::NEG_INFINITY;
+
+        let query = vec![0.8, 0.6, 0.0];
+        for radius in [0.1, 0.5, 1.0, 2.0] {
+            top1.metric = Metric::L1 { radius };
+            let brute_force: Vec<&Vec<f64>> = data
+                .iter()
+                .filter(|v| crate::utils::l1_distance(&query, v) <= radius)
+                .collect();
+            match top1.query(&query).unwrap() {
+                None => assert!(brute_force.is_empty()),
+                Some(result) => assert!(brute_force.contains(&&result)),
+            }
+        }
+    }
+
+    /// Test function to check the merge of two structures built on disjoint shards.
+    #[test]
+    fn test_merge() {
+        let shard_a = vec![vec![1.0, 0.0, 0.0, 0.0], vec![0.0, 1.0, 0.0, 0.0]];
+        let shard_b = vec![vec![0.0, 0.0, 1.0, 0.0], vec![0.0, 0.0, 0.0, 1.0]];
+        let mut top1 = Top1::new_with_seed(shard_a.clone(), 0.9, 0.8, 0.5, 42);
+        let other = Top1::new_with_seed(shard_b.clone(), 0.9, 0.8, 0.5, 42);
+        top1.merge(other).unwrap();
+        assert_eq!(top1.len(), 4);
+        // The ids of the merged points follow the ids of the first shard
+        let mut ids: Vec<usize> = top1.id_table.values().flatten().copied().collect();
+        ids.sort();
+        assert_eq!(ids, vec![0, 1, 2, 3]);
+
+        // Probe every bucket so that the queries are deterministic
+        top1.threshold = f64::NEG_INFINITY;
+        for q in shard_a.iter().chain(shard_b.iter()) {
+            assert_eq!(top1.query(q).unwrap(), Some(q.clone()));
+        }
+
+        // Different Gaussian vectors are incompatible
+        let mut top1 = Top1::new_with_seed(shard_a.clone(), 0.9, 0.8, 0.5, 42);
+        let other = Top1::new_with_seed(shard_b.clone(), 0.9, 0.8, 0.5, 43);
+        assert_eq!(top1.merge(other), Err(AnnError::IncompatibleProjections));
+        assert_eq!(top1.len(), 2);
+
+        // Different parameters are incompatible
+        let other = Top1::new_with_seed(shard_b.clone(), 0.9, 0.7, 0.5, 42);
+        assert_eq!(
+            top1.merge(other),
+            Err(AnnError::IncompatibleParameters("beta".to_string()))
+        );
+        let config = Top1Config { seed: Some(42), metric: Metric::CosineRaw, ..Default::default() };
+        let other = Top1::with_config(shard_b, 0.9, 0.8, 0.5, config);
+        assert_eq!(
+            top1.merge(other),
+            Err(AnnError::IncompatibleParameters("metric".to_string()))
+        );
+        assert_eq!(top1.len(), 2);
+    }
+
+    /// Test function to check that the brute force count falls within the interval returned
+    /// by count_neighbors_ci at the stated confidence.
+    #[test]
+    fn test_count_neighbors_ci() {
+        let (n, d, beta, confidence) = (300, 4, 0.7, 0.9);
+        let data = generate_spherical_vectors(n, d, 1).unwrap();
+        let q = data[0].clone();
+        let true_count = data.iter().filter(|v| dot_product(&q, v) >= beta).count();
+
+        // Seeds of the Gaussian vectors, distinct from the seed of the data
+        let trials = 200;
+        let mut covered = 0;
+        for seed in 0..trials {
+            let top1 = Top1::new_with_seed(data.clone(), 0.9, beta, 0.1, seed + 1000);
+            let (count, margin) = top1.count_neighbors_ci(&q, confidence).unwrap();
+            if (count as f64 - true_count as f64).abs() <= margin + 0.5 {
+                covered += 1;
+            }
+        }
+        // The seeds are fixed, so the test is deterministic. For other seeds, a coverage of
+        // `confidence` over 200 trials falls 0.1 below it with probability about 1e-5.
+        let coverage = covered as f64 / trials as f64;
+        assert!(coverage >= confidence - 0.1, "coverage = {}", coverage);
+
+        // Invalid confidence
+        let top1 = Top1::new_with_seed(data, 0.9, beta, 0.1, 0);
+        assert!(top1.count_neighbors_ci(&q, 1.0).is_err());
+    }
+
+    /// Test function to check that sorted buckets are stored in decreasing order and that the
+    /// sorted query path returns the same point as a full scan.
+    #[test]
+    fn test_sorted_buckets() {
+        let data = generate_spherical_vectors(300, 3, 5).unwrap();
+        let config = Top1Config {
+            seed: Some(6),
+            sorted_buckets: true,
+            ..Default::default()
+        };
+        let mut top1 = Top1::with_config(data.clone(), 0.9, 0.5, 0.1, config);
+        assert!(top1.sorted_buckets());
+        top1.insert(vec![0.0, 0.0, 1.0]).unwrap();
+
+        // The stored order is descending
+        for (i, vectors) in top1.hash_table.iter() {
+            let projections: Vec<f64> = vectors
+                .iter()
+                .map(|v| normalized_projection(v, &top1.gaussian_vectors[*i]))
+                .collect();
+            assert!(projections.windows(2).all(|w| w[0] >= w[1]));
+            assert_eq!(top1.bucket_projections.as_ref().unwrap()[i], projections);
+        }
+
+        // The sorted query path matches the full scan of query_ref
+        let mut found = 0;
+        for q in data.iter().take(100) {
+            let expected = top1.query_ref(q).unwrap().cloned();
+            if expected.is_some() {
+                found += 1;
+            }
+            assert_eq!(top1.query(q).unwrap(), expected);
+        }
+        assert!(found > 0);
+    }
+
+    /// Test function to check that max_bucket_skew detects many identical points.
+    #[test]
+    fn test_max_bucket_skew() {
+        // Identical points all fall in the same bucket
+        let mut data = vec![vec![1.0, 0.0, 0.0]; 200];
+        data.push(vec![0.0, 1.0, 0.0]);
+        let top1 = Top1::new_with_seed(data, 0.9, 0.8, 0.2, 1);
+        assert!(top1.m > 20);
+        assert!(top1.max_bucket_skew() > MAX_BUCKET_SKEW);
+        assert!(top1.max_bucket_skew() <= top1.m as f64);
+    }
+
+    /// Test function to check that repeated query_with_ctx calls match query.
+    #[test]
+    fn test_query_with_ctx() {
+        let data = generate_spherical_vectors(100, 5, 3).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.5, 0.1, 4);
+
+        let mut ctx = QueryContext::new();
+        for q in data.iter().take(20) {
+            let expected = top1.query(q).unwrap();
+            assert_eq!(top1.query_with_ctx(q, &mut ctx).unwrap().cloned(), expected);
+        }
+        assert!(top1.query_with_ctx(&[2.0; 5], &mut ctx).is_err());
+    }
+
+    /// Test function to check that rehash changes the Gaussian vectors and keeps every point
+    /// queryable with its id.
+    #[test]
+    fn test_rehash() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let mut top1 = Top1::new_with_seed(data.clone(), 0.9, 0.8, 0.5, 1);
+        let gaussian_vectors = top1.gaussian_vectors.clone();
+        top1.rehash(2);
+        assert_ne!(top1.gaussian_vectors, gaussian_vectors);
+        assert_eq!(top1.gaussian_vectors.len(), top1.m);
+        assert_eq!(top1.len(), 3);
+
+        // Probe every bucket so that recall does not depend on the Gaussian vectors
+        top1.threshold = f64::NEG_INFINITY;
+        for (id, point) in data.iter().enumerate() {
+            assert_eq!(top1.query_id(point).unwrap(), Some((id, 1.0)));
+        }
+    }
+
+    /// Test function to check that the threshold and the Gaussian vectors follow m.
+    #[test]
+    fn test_set_m() {
+        let data = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        let mut top1 = Top1::new_with_seed(data, 0.9, 0.8, 0.5, 1);
+        top1.set_m(50);
+        assert_eq!(top1.m, 50);
+        assert_eq!(top1.gaussian_vectors.len(), 50);
+        assert_eq!(top1.threshold, get_threshold(0.9, 50));
+        assert_eq!(top1.len(), 2);
+
+        // m is clamped to at least 1
+        top1.set_m(0);
+        assert_eq!(top1.m, 1);
+        assert_eq!(top1.gaussian_vectors.len(), 1);
+        assert_eq!(top1.threshold, get_threshold(0.9, 1));
+
+        top1.m = 80;
+        top1.refresh_threshold();
+        assert_eq!(top1.threshold, get_threshold(0.9, 80));
+    }
+
+    /// Test function to check that query_restricted only probes the allowed buckets.
+    #[test]
+    fn test_query_restricted() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let mut top1 = Top1::new(data.clone(), 0.9, 0.8, 0.5);
+        // Use the axes as Gaussian vectors so that each point has its own bucket
+        top1.gaussian_vectors = data.clone();
+        (top1.hash_table, top1.id_table) = get_hash_table(&data, &top1.gaussian_vectors);
+        top1.threshold = 0.5;
+
+        let q = vec![1.0, 0.0, 0.0];
+        // Restricting to the wrong Gaussian vectors finds nothing
+        let allowed = HashSet::from([1, 2]);
+        assert_eq!(top1.query_restricted(&q, &allowed).unwrap(), None);
+        // Restricting to the correct Gaussian vector finds the point
+        let allowed = HashSet::from([0]);
+        assert_eq!(
+            top1.query_restricted(&q, &allowed).unwrap(),
+            Some(vec![1.0, 0.0, 0.0])
+        );
+    }
+
+    /// Test function to check that differently ordered copies of the same data, shuffled with
+    /// the same seed, produce the same buckets, and that the seed reorders each bucket
+    /// deterministically.
+    #[test]
+    fn test_shuffle_seed() {
+        let data = generate_spherical_vectors(50, 4, 8).unwrap();
+        let reversed: Vec<Vec<f64>> = data.iter().rev().cloned().collect();
+        let config = Top1Config {
+            seed: Some(9),
+            shuffle_seed: Some(10),
+            ..Default::default()
+        };
+        let top1 = Top1::with_config(data.clone(), 0.9, 0.5, 0.3, config.clone());
+        let top1_reversed = Top1::with_config(reversed.clone(), 0.9, 0.5, 0.3, config);
+
+        // Sort each bucket to compare them as sets
+        let sorted_buckets = |top1: &Top1| -> HashMap<usize, Vec<Vec<f64>>> {
+            let mut hash_table = top1.hash_table.clone();
+            for vectors in hash_table.values_mut() {
+                vectors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            }
+            hash_table
+        };
+        assert_eq!(sorted_buckets(&top1), sorted_buckets(&top1_reversed));
+
+        // The ids are still the indices in the data
+        for (top1, data) in [(&top1, &data), (&top1_reversed, &reversed)] {
+            for (i, vectors) in top1.hash_table.iter() {
+                for (vector, id) in vectors.iter().zip(top1.id_table[i].iter()) {
+                    assert_eq!(vector, &data[*id]);
+                }
+            }
+        }
+
+        // The shuffle changes the order of the points within a bucket, deterministically
+        let config = Top1Config {
+            seed: Some(9),
+            shuffle_seed: Some(10),
+            ..Default::default()
+        };
+        let shuffled = Top1::with_config(data.clone(), 0.9, 0.5, 0.1, config.clone());
+        let again = Top1::with_config(data.clone(), 0.9, 0.5, 0.1, config);
+        assert_eq!(shuffled.hash_table, again.hash_table);
+        assert_eq!(shuffled.id_table, again.id_table);
+        let config = Top1Config {
+            seed: Some(9),
+            ..Default::default()
+        };
+        let plain = Top1::with_config(data, 0.9, 0.5, 0.1, config);
+        assert_ne!(shuffled.id_table, plain.id_table);
+        for (i, ids) in plain.id_table.iter() {
+            // Without the shuffle the ids of a bucket are in input order
+            assert!(ids.windows(2).all(|w| w[0] < w[1]));
+            let mut shuffled_ids = shuffled.id_table[i].clone();
+            shuffled_ids.sort_unstable();
+            assert_eq!(&shuffled_ids, ids);
+        }
+    }
+
+    // test that contains with Bloom filters never misses a stored point
+    #[test]
+    fn test_contains_bloom_filters() {
+        let data = generate_spherical_vectors(300, 4, 11).unwrap();
+        let (stored, absent) = data.split_at(200);
+        let config = Top1Config {
+            seed: Some(12),
+            bloom_filters: true,
+            ..Default::default()
+        };
+        let mut top1 = Top1::with_config(stored[..150].to_vec(), 0.9, 0.5, 0.1, config);
+        assert!(top1.bloom_filters());
+        // Inserts grow the filters past their initial capacity
+        for p in &stored[150..] {
+            top1.insert(p.clone()).unwrap();
+        }
+        assert!(stored.iter().all(|p| top1.contains(p)));
+        assert!(absent.iter().all(|p| !top1.contains(p)));
+
+        // A stored query in a probed bucket is returned itself, other queries are unaffected
+        let plain_config = Top1Config {
+            seed: Some(12),
+            ..Default::default()
+        };
+        let mut plain = Top1::with_config(stored[..150].to_vec(), 0.9, 0.5, 0.1, plain_config);
+        for p in &stored[150..] {
+            plain.insert(p.clone()).unwrap();
+        }
+        for p in stored {
+            let index = get_closest_gaussian(p, &top1.gaussian_vectors);
+            if dot_product(p, &top1.gaussian_vectors[index]) >= top1.threshold {
+                assert_eq!(top1.query(p).unwrap().as_ref(), Some(p));
+            }
+        }
+        for p in absent {
+            assert_eq!(top1.query(p).unwrap(), plain.query(p).unwrap());
+        }
+
+        top1.retain(|p| p[0] > 0.0);
+        for p in stored {
+            assert_eq!(top1.contains(p), p[0] > 0.0);
+        }
+    }
+
+    // test that a null angle only accepts the direction of the query and a larger angle more
+    #[test]
+    fn test_query_angle() {
+        let q = vec![1.0, 0.0];
+        let tilted = |deg: f64| vec![deg.to_radians().cos(), deg.to_radians().sin()];
+        let mut hash_table: HashTable = HashMap::new();
+        hash_table.insert(0, vec![tilted(20.0)]);
+        hash_table.insert(1, vec![tilted(-10.0)]);
+        let id_table: IdTable = [(0, vec![0]), (1, vec![1])].into_iter().collect();
+        let gaussian_vectors = vec![vec![10.0, 0.0], vec![10.0, 0.0], vec![0.0, -10.0]];
+        // Small angles have a cosine above alpha, and are still answered from the probed buckets
+        let mut top1 = Top1::from_buckets(gaussian_vectors, hash_table, id_table, 0.9, 0.5, 2);
+        top1.threshold = 1.0;
+
+        assert_eq!(top1.query_angle(&q, 0.0).unwrap(), None);
+        assert_eq!(top1.query_angle(&q, 15.0).unwrap(), Some(tilted(-10.0)));
+        assert!(top1.query_angle(&q, 30.0).unwrap().is_some());
+        top1.insert_in_memory(q.clone());
+        assert_eq!(top1.query_angle(&q, 0.0).unwrap(), Some(q.clone()));
+        assert!(top1.query_angle(&q, 200.0).is_err());
+        assert!(top1.query_beta(&q, 1.5).is_err());
+
+        // query_beta still requires beta below alpha
+        assert!(top1.query_beta(&q, 0.9).is_err());
+        assert!(top1.query_beta(&q, 0.89).is_ok());
+        assert_eq!(top1.query_angle(&q, 5.0).unwrap(), Some(q.clone()));
+    }
+
+    // test count_batch against sequential counts and the mean of the private counts
+    #[test]
+    fn test_count_batch() {
+        let data = generate_spherical_vectors(200, 3, 13).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.5, 0.1, 14);
+        let mut queries = data[..20].to_vec();
+        queries.push(vec![1.0, 1.0, 0.0]);
+
+        let counts = top1.count_batch(&queries);
+        assert_eq!(counts.len(), queries.len());
+        for (q, count) in queries.iter().zip(&counts).take(20) {
+            assert_eq!(*count.as_ref().unwrap(), top1.count_neighbors(q).unwrap());
+        }
+        assert!(counts[20].is_err());
+
+        let repeated = vec![queries[0].clone(); 4000];
+        let private = top1.count_batch_private(&repeated, 1.0);
+        let mean = private.iter().map(|c| c.as_ref().unwrap()).sum::<f64>() / repeated.len() as f64;
+        let expected = *counts[0].as_ref().unwrap() as f64;
+        assert!(
+            (mean - expected).abs() < 0.15,
+            "mean = {}, expected = {}",
+            mean,
+            expected
+        );
+        assert!(top1
+            .count_batch_private(&queries, -1.0)
+            .iter()
+            .all(|c| c.is_err()));
+        assert!(top1.count_batch_private(&queries, 1.0)[20].is_err());
+    }
+
+    // test that a huge number of Gaussian vectors is clamped to max_m
+    #[test]
+    fn test_max_m() {
+        let data = generate_spherical_vectors(100, 3, 15).unwrap();
+        assert!(compute_m(100, 0.9, 0.9) > 1_000_000_000);
+        let config = Top1Config {
+            seed: Some(16),
+            max_m: Some(50),
+            ..Default::default()
+        };
+        let top1 = Top1::with_config(data.clone(), 0.9, 0.5, 0.9, config);
+        assert_eq!(top1.m, 50);
+        assert_eq!(top1.gaussian_vectors.len(), 50);
+        assert_eq!(top1.threshold, get_threshold(0.9, 50));
+        assert_eq!(top1.len(), 100);
+        assert!(top1.query_id(&data[0]).unwrap().is_some());
+    }
+
+    // test that inserting or merging a wrong dimension errors
+    #[test]
+    fn test_dimension_mismatch() {
+        let data = generate_spherical_vectors(20, 3, 17).unwrap();
+        let mut top1 = Top1::new_with_seed(data, 0.9, 0.5, 0.1, 18);
+        let err = top1.insert(vec![1.0, 0.0]).unwrap_err();
+        let expected = AnnError::DimensionMismatch {
+            expected: 3,
+            found: 2,
+        };
+        assert_eq!(
+            err.get_ref().unwrap().downcast_ref::<AnnError>(),
+            Some(&expected)
+        );
+        assert_eq!(top1.len(), 20);
+
+        let other = Top1::new_with_seed(vec![vec![1.0, 0.0]; 20], 0.9, 0.5, 0.1, 18);
+        assert_eq!(top1.merge(other), Err(expected));
+        assert_eq!(top1.len(), 20);
+    }
+
+    // test that construction, insert, merge and queries all reject a wrong dimension alike
+    #[test]
+    fn test_dimension_guard() {
+        let data = generate_spherical_vectors(20, 3, 96).unwrap();
+        let expected = AnnError::DimensionMismatch {
+            expected: 3,
+            found: 2,
+        };
+        let is_expected = |err: io::Error| {
+            err.get_ref().and_then(|e| e.downcast_ref::<AnnError>()) == Some(&expected)
+        };
+
+        // Construction from projections, with a wrong point or a wrong projection
+        let projections = generate_normal_gaussian_vectors_seeded(5, 3, 97).unwrap();
+        let mut wrong_data = data.clone();
+        wrong_data[7] = vec![1.0, 0.0];
+        assert_eq!(
+            Top1::from_projections(wrong_data, 0.9, 0.5, projections.clone()).unwrap_err(),
+            expected
+        );
+        let mut wrong_projections = projections.clone();
+        wrong_projections[2] = vec![1.0, 0.0];
+        assert_eq!(
+            Top1::from_projections(data.clone(), 0.9, 0.5, wrong_projections).unwrap_err(),
+            expected
+        );
+        assert!(matches!(
+            Top1::from_projections(Vec::new(), 0.9, 0.5, projections),
+            Err(AnnError::InvalidParameter(_))
+        ));
+
+        // Checked construction with a ragged point or invalid parameters
+        let config = Top1Config {
+            seed: Some(98),
+            ..Default::default()
+        };
+        let mut ragged = data.clone();
+        ragged[7] = vec![1.0, 0.0];
+        assert_eq!(
+            Top1::try_with_config(ragged, 0.9, 0.5, 0.1, config.clone()).unwrap_err(),
+            expected
+        );
+        assert!(matches!(
+            Top1::try_with_config(data.clone(), 0.5, 0.9, 0.1, config.clone()),
+            Err(AnnError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            Top1::try_with_config(Vec::new(), 0.9, 0.5, 0.1, config.clone()),
+            Err(AnnError::InvalidParameter(_))
+        ));
+        assert_eq!(
+            Top1::try_with_config(data.clone(), 0.9, 0.5, 0.1, config)
+                .unwrap()
+                .len(),
+            20
+        );
+
+        let mut top1 = Top1::new_with_seed(data.clone(), 0.9, 0.5, 0.1, 98);
+        assert_eq!(top1.dim(), 3);
+        // [1, 0] is normalized, so only the dimension check rejects it
+        let q = [1.0, 0.0];
+        assert!(is_expected(top1.insert(q.to_vec()).unwrap_err()));
+        let other = Top1::new_with_seed(vec![q.to_vec(); 20], 0.9, 0.5, 0.1, 98);
+        assert_eq!(top1.merge(other), Err(expected.clone()));
+        assert!(is_expected(top1.query(&q).unwrap_err()));
+        assert!(is_expected(top1.query_ref(&q).unwrap_err()));
+        assert!(is_expected(top1.query_id(&q).unwrap_err()));
+        assert!(is_expected(top1.query_all(&q).unwrap_err()));
+        assert!(is_expected(top1.query_grouped(&q).unwrap_err()));
+        assert!(is_expected(top1.neighbors(&q).unwrap_err()));
+        assert!(is_expected(top1.count_neighbors(&q).unwrap_err()));
+        assert!(is_expected(top1.count_within_radius(&q, 0.5).unwrap_err()));
+        assert_eq!(top1.len(), 20);
+        assert!(top1.query(&data[0]).is_ok());
+    }
+
+    // test that every point appears once in the exported assignments, with its bucket
+    #[test]
+    fn test_export_assignments_csv() {
+        let data = generate_spherical_vectors(100, 3, 19).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.5, 0.1, 20);
+        let path =
+            std::env::temp_dir().join(format!("ann_rust_assignments_{}.csv", std::process::id()));
+        top1.export_assignments_csv(&path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("point_index,gaussian_index"));
+        let rows: Vec<(usize, usize)> = lines
+            .map(|line| {
+                let (id, i) = line.split_once(',').unwrap();
+                (id.parse().unwrap(), i.parse().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            rows.iter().map(|row| row.0).collect::<Vec<_>>(),
+            (0..100).collect::<Vec<_>>()
+        );
+        for (id, i) in rows {
+            assert!(i < top1.m);
+            assert_eq!(i, get_closest_gaussian(&data[id], &top1.gaussian_vectors));
+        }
+    }
+
+    // test query_top_p against query when p covers the Gaussian vectors above the threshold
+    #[test]
+    fn test_query_top_p() {
+        let data = generate_spherical_vectors(300, 3, 21).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.5, 0.1, 22);
+        let queries = generate_spherical_vectors(50, 3, 23).unwrap();
+        for q in &queries {
+            let hits = top1
+                .gaussian_vectors
+                .iter()
+                .filter(|g| dot_product(q, g) >= top1.threshold)
+                .count();
+            let expected = top1.query(q).unwrap();
+            let result = top1.query_top_p(q, hits).unwrap();
+            assert_eq!(result.is_some(), expected.is_some());
+            if let Some(point) = result {
+                assert!(dot_product(q, &point) >= top1.beta);
+            }
+            // A single bucket is probed with p = 1, none with p = 0
+            if let Some(point) = top1.query_top_p(q, 1).unwrap() {
+                assert!(dot_product(q, &point) >= top1.beta);
+            }
+            assert_eq!(top1.query_top_p(q, 0).unwrap(), None);
+        }
+
+        // A small p scans fewer candidates, with a beta that few points meet so that the scans
+        // do not stop at the first bucket
+        #[cfg(feature = "tracing")]
+        {
+            use crate::spans::tests::candidates_scored;
+            let mut strict = top1.clone();
+            strict.beta = 0.99;
+            let (mut small, mut large) = (0, 0);
+            for q in &queries {
+                let (_, one) = candidates_scored(|| strict.query_top_p(q, 1).unwrap());
+                let (_, all) = candidates_scored(|| strict.query_top_p(q, strict.m).unwrap());
+                assert!(one[0] <= all[0], "{} > {}", one[0], all[0]);
+                small += one[0];
+                large += all[0];
+            }
+            assert!(small < large, "{} >= {}", small, large);
+        }
+    }
+
+    // test that a built structure is valid and that a misplaced point is reported
+    #[test]
+    fn test_validate() {
+        let data = generate_spherical_vectors(100, 3, 27).unwrap();
+        let mut top1 = Top1::new_with_seed(data, 0.9, 0.5, 0.1, 28);
+        assert_eq!(top1.validate(), Ok(()));
+
+        // Move a point to another bucket
+        let mut buckets: Vec<usize> = top1.hash_table.keys().copied().collect();
+        buckets.sort_unstable();
+        let (from, to) = (buckets[0], buckets[1]);
+        let point = top1.hash_table.get_mut(&from).unwrap().pop().unwrap();
+        let id = top1.id_table.get_mut(&from).unwrap().pop().unwrap();
+        top1.hash_table.get_mut(&to).unwrap().push(point);
+        top1.id_table.get_mut(&to).unwrap().push(id);
+        let err = top1.validate().unwrap_err();
+        assert!(
+            err.contains(&format!("belongs to bucket {}", from)),
+            "{}",
+            err
+        );
+
+        top1.rehash(29);
+        assert_eq!(top1.validate(), Ok(()));
+        top1.threshold += 1.0;
+        assert!(top1.validate().unwrap_err().starts_with("threshold"));
+        top1.refresh_threshold();
+
+        // Corrupted projections, Bloom filters, locations and a pending rebuild
+        top1.sort_buckets();
+        top1.build_bloom_filters();
+        assert_eq!(top1.validate(), Ok(()));
+        let bucket = *top1.hash_table.keys().next().unwrap();
+        let projections = top1.bucket_projections.as_mut().unwrap();
+        projections.get_mut(&bucket).unwrap()[0] += 1.0;
+        assert!(top1.validate().unwrap_err().contains("projection"));
+        top1.sort_buckets();
+        top1.bloom_filters
+            .as_mut()
+            .unwrap()
+            .insert(bucket, BloomFilter::new(1));
+        assert!(top1.validate().unwrap_err().contains("Bloom filter"));
+        top1.build_bloom_filters();
+        let id = top1.id_table[&bucket][0];
+        top1.locations.insert(id, (bucket, 1));
+        assert!(top1
+            .validate()
+            .unwrap_err()
+            .starts_with(&format!("id {}", id)));
+        top1.locations = locate_ids(&top1.id_table);
+        assert_eq!(top1.validate(), Ok(()));
+        top1.auto_rebuild_factor = Some(2.0);
+        top1.tuned_n = 10;
+        assert!(top1.validate().unwrap_err().contains("rebuild is pending"));
+    }
+
+    // test the HyperLogLog count against the exact count
+    #[test]
+    fn test_count_neighbors_hll() {
+        let data = generate_spherical_vectors(5000, 3, 30).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.0, 0.1, 31);
+        for q in data.iter().take(10) {
+            let exact = top1.count_neighbors(q).unwrap() as f64;
+            let estimate = top1.count_neighbors_hll(q).unwrap();
+            // Three standard errors of the sketch
+            assert!(
+                (estimate - exact).abs() <= 0.1 * exact + 1.0,
+                "{} vs {}",
+                estimate,
+                exact
+            );
+        }
+        assert!(top1.count_neighbors_hll(&[2.0, 0.0, 0.0]).is_err());
+    }
+
+    // test that the drift statistics follow the inserted points
+    #[test]
+    fn test_drift_stats() {
+        let data = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]];
+        let untracked = Top1::new_with_seed(data.clone(), 0.9, 0.8, 0.1, 5);
+        assert_eq!(untracked.drift_stats(), None);
+
+        let config = Top1Config {
+            seed: Some(5),
+            track_drift: true,
+            ..Default::default()
+        };
+        let mut top1 = Top1::with_config(data, 0.9, 0.8, 0.1, config);
+        assert_eq!(top1.drift_stats().unwrap().points_since_build, 0);
+
+        top1.insert(vec![1.0, 0.0, 0.0]).unwrap();
+        top1.insert(vec![1.0, 0.0, 0.0]).unwrap();
+        let stats = top1.drift_stats().unwrap();
+        assert_eq!(stats.points_since_build, 2);
+        assert_eq!(stats.mean, vec![1.0, 0.0, 0.0]);
+        assert_eq!(stats.mean_norm, 1.0);
+
+        top1.insert(vec![0.0, 0.0, 1.0]).unwrap();
+        top1.insert(vec![0.0, 0.0, -1.0]).unwrap();
+        let stats = top1.drift_stats().unwrap();
+        assert_eq!(stats.points_since_build, 4);
+        assert_eq!(stats.mean, vec![0.5, 0.0, 0.0]);
+        assert_eq!(stats.mean_norm, 0.5);
+        assert_eq!(stats.mean_point_norm, 1.0);
+        // A rejected point is not tracked
+        assert!(top1.insert(vec![2.0, 0.0, 0.0]).is_err());
+        assert_eq!(top1.drift_stats().unwrap().points_since_build, 4);
+
+        // The merged points are tracked
+        let data = vec![vec![0.0, 1.0, 0.0], vec![0.0, 1.0, 0.0]];
+        let other = Top1::new_with_seed(data, 0.9, 0.8, 0.1, 5);
+        top1.merge(other).unwrap();
+        let stats = top1.drift_stats().unwrap();
+        assert_eq!(stats.points_since_build, 6);
+        for (mean, expected) in stats.mean.iter().zip([1.0 / 3.0, 1.0 / 3.0, 0.0]) {
+            assert!((mean - expected).abs() < 1e-12, "{:?}", stats.mean);
+        }
+
+        top1.rebuild();
+        assert_eq!(top1.drift_stats().unwrap().points_since_build, 0);
+    }
+
+    // test that seeded builds are structurally equal and that an extra point is reported
+    #[test]
+    fn test_structural_eq() {
+        let data = generate_spherical_vectors(500, 3, 70).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.8, 0.1, 71);
+        let same = Top1::new_with_seed(data.clone(), 0.9, 0.8, 0.1, 71);
+        assert!(top1.structural_eq(&same));
+        assert!(top1.diff(&same).is_empty());
+
+        // The order of the points of a bucket does not matter
+        let mut reordered = same.clone();
+        for (i, vectors) in reordered.hash_table.iter_mut() {
+            vectors.reverse();
+            reordered.id_table.get_mut(i).unwrap().reverse();
+        }
+        assert!(top1.structural_eq(&reordered));
+
+        // One extra point changes its bucket only
+        let mut extra = same.clone();
+        extra.insert(data[0].clone()).unwrap();
+        let bucket = *top1
+            .id_table
+            .iter()
+            .find(|(_, ids)| ids.contains(&0))
+            .unwrap()
+            .0;
+        assert!(!top1.structural_eq(&extra));
+        assert_eq!(
+            top1.diff(&extra),
+            StructureDiff {
+                projections: false,
+                parameters: vec![],
+                buckets: vec![bucket]
+            }
+        );
+
+        // Another seed changes the Gaussian vectors
+        let other = Top1::new_with_seed(data, 0.9, 0.8, 0.1, 72);
+        assert!(top1.diff(&other).projections);
+    }
+
+    // test that from_array2 and query_array agree with the Vec<Vec<f64>> constructor
+    #[cfg(feature = "ndarray")]
+    #[test]
+    fn test_from_array2_parity() {
+        let data = generate_spherical_vectors(200, 4, 82).unwrap();
+        let flat: Vec<f64> = data.iter().flatten().copied().collect();
+        let array = ndarray::Array2::from_shape_vec((200, 4), flat).unwrap();
+        let config = || Top1Config {
+            seed: Some(83),
+            ..Default::default()
+        };
+        let from_vec = Top1::with_config(data.clone(), 0.9, 0.8, 0.1, config());
+        let from_array = Top1::from_array2(array.view(), 0.9, 0.8, 0.1, config());
+        assert!(from_vec.structural_eq(&from_array));
+        for (i, q) in data.iter().enumerate().take(20) {
+            let expected = from_vec.query(q).unwrap();
+            assert_eq!(from_array.query_array(array.row(i)).unwrap(), expected);
+            // a column of the transposed array is not contiguous
+            assert_eq!(
+                from_array.query_array(array.t().column(i)).unwrap(),
+                expected
+            );
+        }
+    }
+
+    // test that expected_candidates is the number of points scored by a full scan
+    #[test]
+    fn test_expected_candidates() {
+        let data = generate_spherical_vectors(500, 3, 101).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.8, 0.1, 102);
+        // With beta = -1 every scored point is counted by count_neighbors
+        let mut accept_all = top1.clone();
+        accept_all.beta = -1.0;
+        let mut probed_some = false;
+        for q in data.iter().take(30) {
+            let expected = top1.expected_candidates(q).unwrap();
+            let probed = search(&top1.gaussian_vectors, q, top1.threshold);
+            let sizes: usize = probed
+                .iter()
+                .filter_map(|i| top1.hash_table.get(i))
+                .map(|b| b.len())
+                .sum();
+            assert_eq!(expected, sizes);
+            assert_eq!(expected, accept_all.count_neighbors(q).unwrap());
+            assert!(top1.count_neighbors(q).unwrap() <= expected);
+            probed_some |= expected > 0;
+        }
+        assert!(probed_some);
+        assert!(top1.expected_candidates(&[2.0, 0.0, 0.0]).is_err());
+
+        // A query that accepts no point scores exactly the predicted candidates
+        #[cfg(feature = "tracing")]
+        {
+            use crate::spans::tests::candidates_scored;
+            let mut reject_all = top1.clone();
+            reject_all.beta = f64::INFINITY;
+            for q in data.iter().take(30) {
+                let (result, scored) = candidates_scored(|| reject_all.query(q).unwrap());
+                assert_eq!(result, None);
+                let expected = top1.expected_candidates(q).unwrap();
+                assert_eq!(scored, vec![expected]);
+            }
+        }
+    }
+
+    // test that query_sampled_fallback scans a sample of the points when no bucket is probed
+    #[test]
+    fn test_query_sampled_fallback() {
+        let data = generate_spherical_vectors(200, 3, 99).unwrap();
+        let mut top1 = Top1::new_with_seed(data.clone(), 0.9, 0.8, 0.1, 100);
+        let mut rng = StdRng::seed_from_u64(101);
+        // When buckets are probed, the fallback answers as query
+        for q in data.iter().take(20) {
+            if !search(&top1.gaussian_vectors, q, top1.threshold).is_empty() {
+                assert_eq!(
+                    top1.query_sampled_fallback(&mut rng, q, 200).unwrap(),
+                    top1.query(q).unwrap()
+                );
+            }
+        }
+        // No bucket is probed: a sample of every point holds the query itself
+        top1.threshold = f64::INFINITY;
+        let q = &data[3];
+        assert_eq!(top1.query(q).unwrap(), None);
+        let best = top1.query_sampled_fallback(&mut rng, q, 200).unwrap().unwrap();
+        assert_eq!(&best, q);
+        assert_eq!(top1.query_sampled_fallback(&mut rng, q, 0).unwrap(), None);
+        // A sample of one point returns it only if it is close
+        if let Some(p) = top1.query_sampled_fallback(&mut rng, q, 1).unwrap() {
+            assert!(dot_product(q, &p) >= 0.8);
+        }
+        assert!(top1.query_sampled_fallback(&mut rng, &[2.0, 0.0, 0.0], 200).is_err());
+
+        // The same seed draws the same sample
+        let sampled = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut answer = |q| top1.query_sampled_fallback(&mut rng, q, 10).unwrap();
+            data[..20].iter().map(|q| answer(q)).collect::<Vec<_>>()
+        };
+        assert_eq!(sampled(102), sampled(102));
+
+        // After removing points, a sample of the remaining size still scans all of them
+        let removed = data[..100].to_vec();
+        top1.retain(|p| !removed.iter().any(|r| r[..] == *p));
+        assert_eq!(top1.len(), 100);
+        for q in &data[100..120] {
+            assert_eq!(top1.query_sampled_fallback(&mut rng, q, 100).unwrap().as_ref(), Some(q));
+        }
+    }
+
+    // test that query_grouped groups the close points by the bucket holding them
+    #[test]
+    fn test_query_grouped() {
+        let data = generate_spherical_vectors(300, 3, 94).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.5, 0.1, 95);
+        let mut groups_seen = 0;
+        for q in data.iter().take(20) {
+            let groups = top1.query_grouped(q).unwrap();
+            let probed = crate::utils::search(&top1.gaussian_vectors, q, top1.threshold);
+            for (i, candidates) in &groups {
+                assert!(probed.contains(i));
+                assert!(candidates.windows(2).all(|w| w[0].0 >= w[1].0));
+                for (score, p) in candidates {
+                    assert!(top1.hash_table[i].contains(p));
+                    assert_eq!(get_closest_gaussian(p, &top1.gaussian_vectors), *i);
+                    assert_eq!(*score, dot_product(q, p));
+                    assert!(*score >= 0.5);
+                }
+            }
+            let total: usize = groups.values().map(|c| c.len()).sum();
+            assert_eq!(total, top1.count_neighbors(q).unwrap());
+            groups_seen += groups.len();
+        }
+        assert!(groups_seen > 0);
+    }
+
+    // test that the footprint of the m returned by max_m_for_budget fits in the budget
+    #[test]
+    fn test_max_m_for_budget() {
+        let data = generate_spherical_vectors(1000, 8, 92).unwrap();
+        let mut top1 = Top1::new_with_seed(data, 0.9, 0.8, 0.1, 93);
+        for budget in [200_000, 300_000, 1_000_000] {
+            let m = Top1::max_m_for_budget(budget, 1000, 8).unwrap();
+            assert!(m < Top1::max_m_for_budget(2 * budget, 1000, 8).unwrap());
+            top1.rebuild_with_m(m);
+            assert_eq!(top1.gaussian_vectors.len(), m);
+            assert!(top1.memory_footprint() <= budget);
+        }
+        // The points alone exceed the budget, or leave no room for a Gaussian vector
+        assert_eq!(Top1::max_m_for_budget(10_000, 1000, 8), None);
+        assert_eq!(Top1::max_m_for_budget(10, 0, 8), None);
+    }
+
+    // test that the CosineRaw metric accepts raw queries and returns the raw stored vectors
+    #[test]
+    fn test_cosine_raw() {
+        let mut rng = StdRng::seed_from_u64(88);
+        let mut data = generate_normal_gaussian_vectors_seeded(500, 3, 89).unwrap();
+        for v in data.iter_mut() {
+            let scale = rng.gen_range(0.5..10.0);
+            v.iter_mut().for_each(|x| *x *= scale);
+        }
+        let config = Top1Config {
+            seed: Some(90),
+            metric: Metric::CosineRaw,
+            ..Default::default()
+        };
+        let mut top1 = Top1::with_config(data.clone(), 0.9, 0.8, 0.1, config);
+        assert!(top1.validate().is_ok());
+        let cosine = |a: &[f64], b: &[f64]| {
+            dot_product(a, b) / (dot_product(a, a) * dot_product(b, b)).sqrt()
+        };
+        let mut found = 0;
+        for q in data.iter().take(50) {
+            // A raw query is accepted, and is answered as its normalization would be
+            let raw_query: Vec<f64> = q.iter().map(|x| 3.0 * x).collect();
+            let mut unit = q.clone();
+            crate::utils::normalize_vector(&mut unit);
+            let answer = top1.query(&raw_query).unwrap();
+            assert_eq!(answer, top1.query(&unit).unwrap());
+            if let Some(p) = answer {
+                assert!(data.contains(&p));
+                assert!(cosine(q, &p) >= 0.8);
+                found += 1;
+            }
+        }
+        assert!(found > 0);
+        // The point itself is returned unless another close point is found first
+        let (id, score) = top1.query_id(&data[0]).unwrap().unwrap();
+        assert!((score - cosine(&data[0], top1.point(id).unwrap())).abs() < 1e-9);
+        assert!(score >= 0.8);
+        // The counts and the scans take raw queries and score by cosine similarity
+        for q in data.iter().take(50) {
+            let raw_query: Vec<f64> = q.iter().map(|x| 3.0 * x).collect();
+            let mut unit = q.clone();
+            crate::utils::normalize_vector(&mut unit);
+            let neighbors = top1.neighbors(&raw_query).unwrap();
+            assert_eq!(neighbors, top1.neighbors(&unit).unwrap());
+            assert_eq!(top1.count_neighbors(&raw_query).unwrap(), neighbors.len());
+            assert_eq!(
+                top1.count_neighbors_capped(&raw_query, usize::MAX).unwrap(),
+                CountResult::Exact(neighbors.len())
+            );
+            assert_eq!(
+                top1.count_within_radius(&raw_query, 0.6).unwrap(),
+                top1.count_within_radius(&unit, 0.6).unwrap()
+            );
+            let all = top1.query_all(&raw_query).unwrap();
+            assert_eq!(all.len(), neighbors.len());
+            for (score, p) in &all {
+                assert!((score - cosine(q, p)).abs() < 1e-9);
+                assert!(*score >= 0.8);
+            }
+        }
+        top1.insert(vec![5.0, 0.0, 0.0]).unwrap();
+        assert_eq!(
+            top1.query(&[2.0, 0.0, 0.0])
+                .unwrap()
+                .map(|p| cosine(&p, &[1.0, 0.0, 0.0]) >= 0.8),
+            Some(true)
+        );
+
+        // The default metric still requires normalized queries
+        let top1 = Top1::new_with_seed(vec![vec![1.0, 0.0, 0.0]], 0.9, 0.8, 0.1, 91);
+        assert!(top1.query(&[2.0, 0.0, 0.0]).is_err());
+    }
+
+    // test that point finds every id after the operations that move the points
+    #[test]
+    fn test_point_locations() {
+        let data = generate_spherical_vectors(300, 3, 103).unwrap();
+        let check = |top1: &Top1| {
+            assert_eq!(top1.locations.len(), top1.len());
+            for (i, ids) in &top1.id_table {
+                for (position, id) in ids.iter().enumerate() {
+                    assert_eq!(top1.point(*id), Some(&top1.hash_table[i][position]));
+                }
+            }
+        };
+        let config = Top1Config {
+            seed: Some(104),
+            ..Default::default()
+        };
+        let mut top1 = Top1::with_config(data[..100].to_vec(), 0.9, 0.5, 0.1, config.clone());
+        check(&top1);
+        for p in &data[100..150] {
+            top1.insert(p.clone()).unwrap();
+        }
+        check(&top1);
+        top1.retain(|p| p[0] > -0.5);
+        check(&top1);
+        assert_eq!(top1.point(top1.next_id), None);
+        top1.rehash(105);
+        check(&top1);
+        top1.sort_buckets();
+        for p in &data[150..200] {
+            top1.insert(p.clone()).unwrap();
+        }
+        check(&top1);
+        let other = Top1::with_config(data[200..].to_vec(), 0.9, 0.5, 0.1, config.clone());
+        let mut merged = Top1::with_config(data[..100].to_vec(), 0.9, 0.5, 0.1, config);
+        merged.merge(other).unwrap();
+        check(&merged);
+        assert_eq!(merged.point(150), Some(&data[250]));
+    }
+
+    // test that rebuild_with_m with a smaller m shrinks the structure and keeps its points
+    #[test]
+    fn test_rebuild_with_m() {
+        let data = generate_spherical_vectors(1000, 3, 86).unwrap();
+        let mut top1 = Top1::new_with_seed(data[1..].to_vec(), 0.9, 0.8, 0.1, 87);
+        top1.insert(data[0].clone()).unwrap();
+        let mut copy = top1.clone();
+        assert!(top1.m > 5);
+        let before = top1.memory_footprint();
+        top1.rebuild_with_m(5);
+        assert!(top1.memory_footprint() < before);
+        assert_eq!(top1.tuned_n, data.len());
+        assert_eq!(top1.m, 5);
+        assert_eq!(top1.gaussian_vectors.len(), 5);
+        assert_eq!(top1.threshold, get_threshold(0.9, 5));
+        assert_eq!(top1.len(), data.len());
+        let mut ids: Vec<usize> = top1.id_table.values().flatten().copied().collect();
+        ids.sort_unstable();
+        assert_eq!(ids, (0..data.len()).collect::<Vec<usize>>());
+        let mut found = 0;
+        for q in &data {
+            if let Some(p) = top1.query(q).unwrap() {
+                assert!(dot_product(q, &p) >= 0.8);
+                found += 1;
+            }
+        }
+        assert!(found > 0);
+
+        // The new Gaussian vectors are derived from the build seed
+        copy.rebuild_with_m(5);
+        assert_eq!(copy.gaussian_vectors, top1.gaussian_vectors);
+
+        // The projections of sorted buckets are counted
+        let unsorted = top1.memory_footprint();
+        top1.sort_buckets();
+        assert!(top1.memory_footprint() >= unsorted + data.len() * size_of::<f64>());
+    }
+
+    // test that neighbors matches count_neighbors and the close points of the probed buckets
+    #[test]
+    fn test_neighbors() {
+        let data = generate_spherical_vectors(500, 3, 84).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.8, 0.1, 85);
+        let mut bucket_of = vec![0; data.len()];
+        for (i, ids) in &top1.id_table {
+            for id in ids {
+                bucket_of[*id] = *i;
+            }
+        }
+        for q in data.iter().take(20) {
+            let probed = crate::utils::search(&top1.gaussian_vectors, q, top1.threshold);
+            let expected: Vec<usize> = (0..data.len())
+                .filter(|&id| probed.contains(&bucket_of[id]) && dot_product(q, &data[id]) >= 0.8)
+                .collect();
+            let ids = top1.neighbors(q).unwrap();
+            assert_eq!(ids, expected);
+            assert_eq!(ids.len(), top1.count_neighbors(q).unwrap());
+        }
+
+        // The sorted buckets are only scanned in range, with the same result
+        let mut sorted = top1.clone();
+        sorted.sort_buckets();
+        for q in data.iter().take(20) {
+            assert_eq!(sorted.neighbors(q).unwrap(), top1.neighbors(q).unwrap());
+        }
+
+        // The candidates are accepted by the metric of the structure
+        let mut l1 = top1.clone();
+        l1.metric = Metric::L1 { radius: 0.5 };
+        for q in data.iter().take(20) {
+            let probed = crate::utils::search(&l1.gaussian_vectors, q, l1.threshold);
+            let expected: Vec<usize> = (0..data.len())
+                .filter(|&id| {
+                    probed.contains(&bucket_of[id]) && l1.metric.accepts(q, &data[id], 0.8)
+                })
+                .collect();
+            assert_eq!(l1.neighbors(q).unwrap(), expected);
+            assert_eq!(l1.count_neighbors(q).unwrap(), expected.len());
+        }
+    }
+
+    // test that nearest_gaussians returns the k highest scoring Gaussian vectors
+    #[test]
+    fn test_nearest_gaussians() {
+        let data = generate_spherical_vectors(1000, 3, 80).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.8, 0.1, 81);
+        let q = &data[0];
+        let mut all: Vec<(usize, f64)> = top1
+            .gaussian_vectors
+            .iter()
+            .enumerate()
+            .map(|(i, g)| (i, dot_product(q, g)))
+            .collect();
+        all.sort_by(|a, b| b.1.total_cmp(&a.1));
+        for k in [0, 1, 3, top1.m, top1.m + 5] {
+            assert_eq!(top1.nearest_gaussians(q, k).unwrap(), all[..k.min(top1.m)].to_vec());
+        }
+        assert!(top1.nearest_gaussians(&[1.0, 0.0], 3).is_err());
+    }
+
+    // test the borrowed and the owned paths of query_cow
+    #[test]
+    fn test_query_cow() {
+        let data = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]];
+        let mut top1 = Top1::new_with_seed(data, 0.9, 0.8, 0.1, 3);
+        top1.threshold = f64::NEG_INFINITY;
+        let q = [0.0, 1.0, 0.0];
+
+        let owned: Vec<f64> = {
+            let point = top1.query_cow(&q).unwrap().unwrap();
+            assert!(matches!(point, Cow::Borrowed(_)));
+            assert_eq!(point.as_ref(), &[0.0, 1.0, 0.0]);
+            assert!(std::ptr::eq(
+                point.as_ptr(),
+                top1.query_ref(&q).unwrap().unwrap().as_ptr()
+            ));
+            point.into_owned()
+        };
+        drop(top1);
+        assert_eq!(owned, vec![0.0, 1.0, 0.0]);
+    }
+
+    // test that from_projections rejects zero and non-finite Gaussian vectors
+    #[test]
+    fn test_from_projections_rejects_degenerate_rows() {
+        let data = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]];
+        let zero = vec![vec![1.0, 0.0, 0.0], vec![0.0, 0.0, 0.0]];
+        assert!(matches!(
+            Top1::from_projections(data.clone(), 0.9, 0.8, zero),
+            Err(AnnError::InvalidParameter(reason)) if reason.contains("zero")
+        ));
+        let infinite = vec![vec![f64::INFINITY, 0.0, 0.0]];
+        assert!(matches!(
+            Top1::from_projections(data.clone(), 0.9, 0.8, infinite),
+            Err(AnnError::InvalidParameter(reason)) if reason.contains("non-finite")
+        ));
+        let valid = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]];
+        assert!(Top1::from_projections(data, 0.9, 0.8, valid).is_ok());
+    }
+
+    // test bucketing and querying with a hand-crafted projection matrix and threshold
+    #[test]
+    fn test_from_projections_with_threshold() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.8, 0.6, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        // Axis projections, scaled unlike unit Gaussians
+        let projections = vec![
+            vec![2.0, 0.0, 0.0],
+            vec![0.0, 2.0, 0.0],
+            vec![0.0, 0.0, 2.0],
+        ];
+        let mut top1 =
+            Top1::from_projections_with_threshold(data.clone(), projections.clone(), 1.5, 0.9, 0.7)
+                .unwrap();
+        assert_eq!(top1.threshold, 1.5);
+        assert_eq!(top1.alpha, 0.9);
+        assert!(top1.validate().is_ok());
+        assert_eq!(top1.len(), 4);
+        assert_eq!(top1.hash_table[&0], vec![data[0].clone(), data[1].clone()]);
+        assert_eq!(top1.hash_table[&1], vec![data[2].clone()]);
+        assert_eq!(top1.hash_table[&2], vec![data[3].clone()]);
+
+        // Only the first projection meets the threshold, so only the bucket 0 is probed
+        let q = vec![1.0, 0.0, 0.0];
+        assert_eq!(top1.query(&q).unwrap(), Some(data[0].clone()));
+        // Only the bucket 1 is probed, so the closer point of the bucket 0 is missed
+        let q = vec![0.6, 0.8, 0.0];
+        assert_eq!(top1.query(&q).unwrap(), Some(data[2].clone()));
+        let q = vec![0.0, 0.6, 0.8];
+        assert_eq!(top1.query(&q).unwrap(), Some(data[3].clone()));
+
+        // The explicit threshold is kept with new Gaussian vectors
+        top1.rehash(1);
+        assert_eq!(top1.threshold, 1.5);
+
+        // Invalid inputs
+        assert!(
+            Top1::from_projections_with_threshold(data.clone(), vec![], 1.5, 0.9, 0.7).is_err()
+        );
+        let bad = vec![vec![f64::NAN, 0.0, 0.0]];
+        assert!(Top1::from_projections_with_threshold(data.clone(), bad, 1.5, 0.9, 0.7).is_err());
+        let nan = f64::NAN;
+        let result =
+            Top1::from_projections_with_threshold(data.clone(), projections, nan, 0.9, 0.7);
+        assert!(result.is_err());
+        let short = vec![vec![1.0, 0.0]];
+        assert!(Top1::from_projections_with_threshold(data.clone(), short, 1.5, 0.9, 0.7).is_err());
+        let axes = vec![vec![1.0, 0.0, 0.0]];
+        assert!(Top1::from_projections_with_threshold(data, axes, 1.5, 0.7, 0.7).is_err());
+    }
+
+    // test that the robust count is closer to the exact count than a single count on average
+    #[test]
+    fn test_count_neighbors_robust() {
+        let (n, d, beta) = (300, 4, 0.7);
+        let data = generate_spherical_vectors(n, d, 1).unwrap();
+        let q = data[0].clone();
+        let exact = data.iter().filter(|v| dot_product(&q, v) >= beta).count() as f64;
+
+        let trials = 30;
+        let (mut single_error, mut robust_error) = (0.0, 0.0);
+        for trial in 0..trials {
+            let top1 = Top1::new_with_seed(data.clone(), 0.9, beta, 0.1, trial + 1000);
+            single_error += (top1.count_neighbors(&q).unwrap() as f64 - exact).powi(2);
+            let robust = top1.count_neighbors_robust(&q, 8, trial * 100).unwrap();
+            robust_error += (robust - exact).powi(2);
+        }
+        assert!(
+            robust_error < single_error,
+            "{} vs {}",
+            robust_error,
+            single_error
+        );
+
+        // The structure is not modified
+        let top1 = Top1::new_with_seed(data, 0.9, beta, 0.1, 0);
+        let before = top1.gaussian_vectors.clone();
+        top1.count_neighbors_robust(&q, 3, 5).unwrap();
+        assert_eq!(top1.gaussian_vectors, before);
+        assert!(top1.count_neighbors_robust(&q, 0, 5).is_err());
+    }
+
+    // test that the bucket members always include the close points
+    #[test]
+    fn test_count_bucket_members() {
+        let data = generate_spherical_vectors(2000, 3, 60).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.8, 0.1, 61);
+        let mut strictly = 0;
+        for q in data.iter().take(50) {
+            let members = top1.count_bucket_members(q).unwrap();
+            let neighbors = top1.count_neighbors(q).unwrap();
+            assert!(members >= neighbors, "{} < {}", members, neighbors);
+            strictly += (members > neighbors) as usize;
+        }
+        assert!(strictly > 0);
+        assert!(top1.count_bucket_members(&[2.0, 0.0, 0.0]).is_err());
+    }
+
+    // test that the capped count saturates on a large bucket and is exact on a small one
+    #[test]
+    fn test_count_neighbors_capped() {
+        let mut hash_table: HashTable = HashMap::new();
+        hash_table.insert(0, vec![vec![1.0, 0.0]; 1000]);
+        hash_table.insert(1, vec![vec![0.0, 1.0]; 3]);
+        let id_table: IdTable = [(0, (0..1000).collect()), (1, (1000..1003).collect())]
+            .into_iter()
+            .collect();
+        let gaussian_vectors = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        let mut top1 = Top1::from_buckets(gaussian_vectors, hash_table, id_table, 0.9, 0.9, 2);
+        top1.threshold = 0.5;
+
+        let large = [1.0, 0.0];
+        assert_eq!(
+            top1.count_neighbors_capped(&large, 100).unwrap(),
+            CountResult::AtLeast(100)
+        );
+        assert_eq!(
+            top1.count_neighbors_capped(&large, 1000).unwrap(),
+            CountResult::AtLeast(1000)
+        );
+        assert_eq!(
+            top1.count_neighbors_capped(&large, 1001).unwrap(),
+            CountResult::Exact(1000)
+        );
+        let small = [0.0, 1.0];
+        assert_eq!(
+            top1.count_neighbors_capped(&small, 100).unwrap(),
+            CountResult::Exact(3)
+        );
+        assert!(top1.count_neighbors_capped(&[2.0, 0.0], 100).is_err());
+    }
+
+    // test that the precomputed projections reproduce query
+    #[test]
+    fn test_query_with_projection() {
+        let data = generate_spherical_vectors(2000, 3, 50).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.8, 0.1, 51);
+        let mut found = 0;
+        for q in data.iter().take(20) {
+            let scores: Vec<f64> = top1
+                .projections()
+                .iter()
+                .map(|g| dot_product(q, g))
+                .collect();
+            let result = top1.query_with_projection(q, &scores).unwrap();
+            assert_eq!(result, top1.query(q).unwrap());
+            found += result.is_some() as usize;
+        }
+        assert!(found > 0);
+        assert!(top1.query_with_projection(&data[0], &[0.0]).is_err());
+        let scores = vec![0.0; top1.m];
+        let expected = AnnError::DimensionMismatch { expected: 3, found: 1 };
+        let err = top1.query_with_projection(&[1.0], &scores).unwrap_err();
+        assert_eq!(err.get_ref().and_then(|e| e.downcast_ref::<AnnError>()), Some(&expected));
+
+        // A raw query is normalized like in query
+        let config = Top1Config { seed: Some(51), metric: Metric::CosineRaw, ..Default::default() };
+        let top1 = Top1::with_config(data.clone(), 0.9, 0.8, 0.1, config);
+        for q in data.iter().take(20) {
+            let scores: Vec<f64> = top1.projections().iter().map(|g| dot_product(q, g)).collect();
+            let raw_query: Vec<f64> = q.iter().map(|x| 3.0 * x).collect();
+            assert_eq!(top1.query_with_projection(&raw_query, &scores).unwrap(), top1.query(q).unwrap());
+        }
+    }
+
+    // test that Debug prints the parameters without the vectors
+    #[test]
+    fn test_debug_summary() {
+        let data = generate_spherical_vectors(1000, 50, 42).unwrap();
+        let top1 = Top1::new_with_seed(data, 0.9, 0.5, 0.1, 43);
+        let debug = format!("{:?}", top1);
+        assert!(debug.starts_with("Top1 { n: 1000, d: 50, m: "), "{}", debug);
+        for field in ["alpha: 0.9", "beta: 0.5", "threshold: ", "num_buckets: "] {
+            assert!(debug.contains(field), "{}", debug);
+        }
+        assert!(debug.len() < 200, "{}", debug);
+    }
+
+    // test query_all against a brute-force beta filter over the probed buckets
+    #[test]
+    fn test_query_all() {
+        let data = generate_spherical_vectors(2000, 3, 40).unwrap();
+        let beta = 0.5;
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, beta, 0.1, 41);
+        let bucket_of: HashMap<usize, usize> = top1
+            .id_table
+            .iter()
+            .flat_map(|(bucket, ids)| ids.iter().map(move |id| (*id, *bucket)))
+            .collect();
+        let mut found = 0;
+        for q in data.iter().take(10) {
+            let all = top1.query_all(q).unwrap();
+            found += all.len();
+            let probed: HashSet<usize> = top1
+                .gaussian_vectors
+                .iter()
+                .enumerate()
+                .filter(|(_, g)| dot_product(q, g) >= top1.threshold)
+                .map(|(i, _)| i)
+                .collect();
+            let mut expected: Vec<f64> = data
+                .iter()
+                .enumerate()
+                .filter(|(id, v)| dot_product(q, v) >= beta && probed.contains(&bucket_of[id]))
+                .map(|(_, v)| dot_product(q, v))
+                .collect();
+            expected.sort_by(|a, b| b.total_cmp(a));
+            let scores: Vec<f64> = all.iter().map(|(score, _)| *score).collect();
+            assert_eq!(scores, expected);
+            assert_eq!(all.len(), top1.count_neighbors(q).unwrap());
+            for (score, vector) in &all {
+                assert_eq!(*score, dot_product(q, vector));
+            }
+        }
+        assert!(found > 0);
+        assert!(top1.query_all(&[2.0, 0.0, 0.0]).is_err());
+    }
+
+    // test that singleton buckets are skipped with a minimum bucket size of 2
+    #[test]
+    fn test_query_min_bucket() {
+        let mut hash_table: HashTable = HashMap::new();
+        hash_table.insert(0, vec![vec![1.0, 0.0], vec![0.8, 0.6]]);
+        hash_table.insert(1, vec![vec![0.0, 1.0]]);
+        let id_table: IdTable = [(0, vec![0, 1]), (1, vec![2])].into_iter().collect();
+        let gaussian_vectors = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        let mut top1 = Top1::from_buckets(gaussian_vectors, hash_table, id_table, 0.9, 0.9, 2);
+        top1.threshold = f64::NEG_INFINITY;
+
+        let q = vec![0.0, 1.0];
+        assert_eq!(top1.query_min_bucket(&q, 1).unwrap(), Some(q.clone()));
+        assert_eq!(top1.query_min_bucket(&q, 2).unwrap(), None);
+        let q = vec![1.0, 0.0];
+        assert_eq!(top1.query_min_bucket(&q, 2).unwrap(), Some(q.clone()));
+        assert_eq!(top1.query_min_bucket(&q, 3).unwrap(), None);
+    }
+
+    // test that the hash table is preallocated for the buckets and that the buckets are unchanged
+    #[test]
+    fn test_hash_table_capacity() {
+        let data = generate_spherical_vectors(300, 3, 32).unwrap();
+        let top1 = Top1::new_with_seed(data.clone(), 0.9, 0.5, 0.1, 33);
+        // Only a lower bound on the capacity is guaranteed
+        assert!(top1.hash_table.capacity() >= top1.m.min(300));
+        // The buckets do not depend on the capacity
+        let (hash_table, _) = get_hash_table(&data, &top1.gaussian_vectors);
+        assert_eq!(hash_table, top1.hash_table);
+    }
+
+    // test that only the candidates before the score cliff are returned
+    #[test]
+    fn test_query_until_gap() {
+        let at = |dot: f64| vec![dot, (1.0 - dot * dot).sqrt()];
+        let mut hash_table: HashTable = HashMap::new();
+        hash_table.insert(0, vec![at(0.99), at(0.6), at(0.97)]);
+        hash_table.insert(1, vec![at(0.95), at(0.55), at(0.2)]);
+        let id_table: IdTable = [(0, vec![0, 1, 2]), (1, vec![3, 4, 5])]
+            .into_iter()
+            .collect();
+        let gaussian_vectors = vec![vec![1.0, 0.0], vec![0.0, 1.0]];
+        let mut top1 = Top1::from_buckets(gaussian_vectors, hash_table, id_table, 0.9, 0.5, 2);
+        top1.threshold = f64::NEG_INFINITY;
+
+        let q = vec![1.0, 0.0];
+        let ids =
+            |result: Vec<(usize, f64)>| result.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        // The cliff is between 0.95 and 0.6, the point at 0.2 is below beta
+        assert_eq!(ids(top1.query_until_gap(&q, 0.1).unwrap()), vec![0, 2, 3]);
+        assert_eq!(
+            ids(top1.query_until_gap(&q, 0.5).unwrap()),
+            vec![0, 2, 3, 1, 4]
+        );
+        assert_eq!(ids(top1.query_until_gap(&q, 0.0).unwrap()), vec![0]);
+    }
+
+    // test that the calibrated threshold probes about the target number of Gaussian vectors
+    #[test]
+    fn test_calibrate_threshold() {
+        let data = generate_spherical_vectors(300, 3, 35).unwrap();
+        let mut top1 = Top1::new_with_seed(data.clone(), 0.9, 0.5, 0.1, 36);
+        let sample = &data[..50];
+        let average_hits = |top1: &Top1| {
+            let hits: usize = sample
+                .iter()
+                .map(|q| {
+                    top1.gaussian_vectors
+                        .iter()
+                        .filter(|g| dot_product(q, g) >= top1.threshold)
+                        .count()
+                })
+                .sum();
+            hits as f64 / sample.len() as f64
+        };
+        for target in [1, 5, 20] {
+            top1.calibrate_threshold(sample, target);
+            let hits = average_hits(&top1);
+            assert!(
+                (hits - target as f64).abs() <= 1.0,
+                "target {}, hits {}",
+                target,
+                hits
+            );
+        }
+        // The calibrated threshold survives a rehash and a rebuild
+        let calibrated = top1.threshold;
+        assert_eq!(top1.threshold_override(), Some(calibrated));
+        top1.refresh_threshold();
+        top1.rehash(37);
+        assert_eq!(top1.threshold, calibrated);
+        top1.rebuild_with_m(40);
+        assert_eq!(top1.threshold, calibrated);
+        assert!(top1.validate().is_ok());
+        top1.threshold = get_threshold(0.9, top1.m);
+        assert!(top1.validate().unwrap_err().contains("override"));
+
+        top1.set_threshold_override(None);
+        assert_eq!(top1.threshold, get_threshold(0.9, top1.m));
+        assert!(top1.validate().is_ok());
+    }
+
+    // test that querying a structure emptied by retain returns None
+    #[test]
+    fn test_query_empty() {
+        let data = generate_spherical_vectors(50, 3, 40).unwrap();
+        let config = Top1Config {
+            seed: Some(41),
+            sorted_buckets: true,
+            ..Default::default()
+        };
+        let mut top1 = Top1::with_config(data.clone(), 0.9, 0.5, 0.1, config);
+        top1.threshold = f64::NEG_INFINITY;
+        top1.retain(|_| false);
+        assert!(top1.is_empty());
+        assert_eq!(top1.query(&data[0]).unwrap(), None);
+        assert_eq!(top1.query_angle(&data[0], 90.0).unwrap(), None);
+        assert!(top1.query(&[2.0, 0.0, 0.0]).is_err());
+    }
+
+    // test that query_checked errors on mismatched parameters
+    #[test]
+    fn test_query_checked() {
+        let data = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0]];
+        let mut top1 = Top1::new_with_seed(data, 0.9, 0.5, 0.5, 42);
+        top1.threshold = f64::NEG_INFINITY;
+        let q = vec![1.0, 0.0, 0.0];
+        assert_eq!(top1.query_checked(&q, 0.9, 0.5).unwrap(), Some(q.clone()));
+
+        let err = top1.query_checked(&q, 0.8, 0.5).unwrap_err();
+        let inner = err.get_ref().unwrap().downcast_ref::<AnnError>().unwrap();
+        assert!(matches!(inner, AnnError::IncompatibleParameters(p) if p.starts_with("alpha")));
+        assert!(top1.query_checked(&q, 0.9, 0.55).is_err());
+        assert!(top1.query_checked(&q, f64::NAN, 0.5).is_err());
+    }
+
+    // test that inserts past the growth factor rebuild the structure for the new size
+    #[test]
+    fn test_auto_rebuild() {
+        let data = generate_spherical_vectors(200, 3, 43).unwrap();
+        let config = Top1Config {
+            seed: Some(44),
+            auto_rebuild_factor: Some(2.0),
+            ..Default::default()
+        };
+        let mut top1 = Top1::with_config(data[..50].to_vec(), 0.9, 0.5, 0.3, config.clone());
+        let m = top1.m;
+        assert_eq!(m, compute_m(50, 0.9, 0.3));
+        for p in &data[50..100] {
+            top1.insert(p.clone()).unwrap();
+        }
+        assert_eq!(top1.m, m);
+
+        // The 101st point triggers the rebuild
+        top1.insert(data[100].clone()).unwrap();
+        assert_eq!(top1.m, compute_m(101, 0.9, 0.3));
+        assert_eq!(top1.gaussian_vectors.len(), top1.m);
+        assert_eq!(top1.threshold, get_threshold(0.9, top1.m));
+        assert_eq!(top1.tuned_n, 101);
+        assert_eq!(top1.len(), 101);
+        assert_eq!(top1.validate(), Ok(()));
+
+        // Merged points also trigger the rebuild
+        let shard = |range: std::ops::Range<usize>| {
+            Top1::with_config(data[range].to_vec(), 0.9, 0.5, 0.3, config.clone())
+        };
+        let mut top1 = shard(0..50);
+        top1.merge(shard(50..100)).unwrap();
+        assert_eq!(top1.m, m);
+        top1.merge(shard(100..150)).unwrap();
+        assert_eq!(top1.m, compute_m(150, 0.9, 0.3));
+        assert_eq!(top1.tuned_n, 150);
+        assert_eq!(top1.validate(), Ok(()));
+    }
+
+    // test that seeded structures rebuild with the same new Gaussian vectors
+    #[test]
+    fn test_rebuild_seeded() {
+        let data = generate_spherical_vectors(100, 3, 45).unwrap();
+        let config = Top1Config {
+            seed: Some(46),
+            ..Default::default()
+        };
+        let mut first = Top1::with_config(data.clone(), 0.9, 0.5, 0.3, config.clone());
+        let mut second = Top1::with_config(data, 0.9, 0.5, 0.3, config);
+        let built = first.gaussian_vectors.clone();
+        first.rebuild();
+        second.rebuild();
+        assert_ne!(first.gaussian_vectors, built);
+        assert_eq!(first.gaussian_vectors, second.gaussian_vectors);
+        assert_eq!(first.hash_table, second.hash_table);
+
+        // Each rebuild draws new Gaussian vectors
+        let rebuilt = first.gaussian_vectors.clone();
+        first.rebuild();
+        assert_ne!(first.gaussian_vectors, rebuilt);
+    }
+
+    // test that an emptied structure rebuilds with one Gaussian vector and stays usable
+    #[test]
+    fn test_rebuild_empty() {
+        let data = generate_spherical_vectors(100, 3, 47).unwrap();
+        for theta in [Some(0.3), None] {
+            let config = Top1Config { seed: Some(48), bloom_filters: true, ..Default::default() };
+            let mut top1 = Top1::with_config(data.clone(), 0.9, 0.5, 0.3, config);
+            top1.theta = theta;
+            top1.retain(|_| false);
+            top1.rebuild();
+            assert_eq!(top1.m, 1);
+            assert_eq!(top1.gaussian_vectors.len(), 1);
+            top1.insert(data[0].clone()).unwrap();
+            assert_eq!(top1.query(&data[0]).unwrap(), Some(data[0].clone()));
+            assert!(top1.validate().is_ok());
+        }
+    }
+
+    /// Test function to check if the get_hash_table function works.
+    #[test]
+    fn test_get_hash_table() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let gaussian_vectors = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let (hash_table, id_table) = get_hash_table(&data, &gaussian_vectors);
+
+        // Check if the id table is correct
+        assert_eq!(id_table[&0], vec![0, 1]);
+        assert_eq!(id_table[&1], vec![2]);
+        assert_eq!(id_table[&2], vec![3]);
 
         // Check if the hash table is correct
         assert_eq!(hash_table.len(), 3);
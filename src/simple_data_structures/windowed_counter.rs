@@ -0,0 +1,206 @@
+use super::top1::get_closest_gaussian;
+use crate::checks::{check_dimension, check_parameters};
+use crate::error::AnnError;
+use crate::utils::{
+    compute_m, dot_product, generate_normal_gaussian_vectors_seeded, get_threshold, is_normalized,
+    search,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::ops::Range;
+
+/// Counter of the close points inserted during a time window, e.g. to count the neighbors
+/// added in the last T seconds. The points are hashed like in a Top1 structure, and each bucket
+/// maps the insertion timestamps to the points inserted at that time, so that a window is
+/// located in logarithmic time and the points can be removed again when they expire.
+///
+/// This is an exact store, not a sketch: every point is kept with its timestamp, so the
+/// memory grows with the stored points until they are removed or expired, and the counts are
+/// the exact counts of the probed buckets.
+#[derive(Clone)]
+pub struct WindowedCounter {
+    pub gaussian_vectors: Vec<Vec<f64>>,
+    pub threshold: f64,
+    pub beta: f64,
+    pub d: usize,
+    // Points of each bucket by insertion timestamp, in insertion order for equal timestamps
+    pub buckets: HashMap<usize, BTreeMap<u64, Vec<Vec<f64>>>>,
+}
+
+impl WindowedCounter {
+    /// Constructor for an empty counter of points of dimension `d`, with as many Gaussian
+    /// vectors as a Top1 structure on `expected_n` points, drawn from `seed`.
+    /// An error is returned if `expected_n` or `d` is 0, or unless `0 < beta < alpha < 1` and
+    /// `theta > 0`.
+    pub fn new(
+        expected_n: usize,
+        d: usize,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        seed: u64,
+    ) -> Result<Self, io::Error> {
+        if expected_n == 0 || d == 0 {
+            return Err(AnnError::InvalidParameter(
+                "expected_n and d must be positive".to_string(),
+            )
+            .into());
+        }
+        check_parameters(alpha, beta, theta).map_err(AnnError::InvalidParameter)?;
+        let m = compute_m(expected_n, alpha, theta).max(1);
+        let gaussian_vectors = generate_normal_gaussian_vectors_seeded(m, d, seed)?;
+        Ok(WindowedCounter {
+            gaussian_vectors,
+            threshold: get_threshold(alpha, m),
+            beta,
+            d,
+            buckets: HashMap::new(),
+        })
+    }
+
+    /// Insert the point `p` with its insertion `timestamp`.
+    /// If the point does not have dimension `d` or is not normalized, an error is returned.
+    pub fn insert(&mut self, p: Vec<f64>, timestamp: u64) -> Result<(), io::Error> {
+        self.check_point(&p)?;
+        let i = get_closest_gaussian(&p, &self.gaussian_vectors);
+        let bucket = self.buckets.entry(i).or_default();
+        bucket.entry(timestamp).or_default().push(p);
+        Ok(())
+    }
+
+    /// Remove one occurrence of the point `p` inserted at `timestamp`, subtracting its
+    /// contribution from the counts. Return true if it was stored.
+    pub fn remove(&mut self, p: &[f64], timestamp: u64) -> bool {
+        let i = get_closest_gaussian(p, &self.gaussian_vectors);
+        let Some(bucket) = self.buckets.get_mut(&i) else {
+            return false;
+        };
+        let Some(points) = bucket.get_mut(&timestamp) else {
+            return false;
+        };
+        let Some(position) = points.iter().position(|point| point.as_slice() == p) else {
+            return false;
+        };
+        points.remove(position);
+        if points.is_empty() {
+            bucket.remove(&timestamp);
+        }
+        true
+    }
+
+    /// Remove every point inserted before `timestamp` and return how many were removed.
+    pub fn expire_before(&mut self, timestamp: u64) -> usize {
+        let mut removed = 0;
+        for bucket in self.buckets.values_mut() {
+            let kept = bucket.split_off(&timestamp);
+            removed += bucket.values().map(|points| points.len()).sum::<usize>();
+            *bucket = kept;
+        }
+        removed
+    }
+
+    /// Given a query `q`, count the points of the probed buckets with dot product at least
+    /// `beta` with the query and inserted during `window`.
+    /// If the query does not have dimension `d` or is not normalized, an error is returned.
+    pub fn count_neighbors_in_window(
+        &self,
+        q: &[f64],
+        window: Range<u64>,
+    ) -> Result<usize, io::Error> {
+        self.check_point(q)?;
+        // An empty window, which `BTreeMap::range` would reject
+        if window.start >= window.end {
+            return Ok(0);
+        }
+        let mut count = 0;
+        for i in search(&self.gaussian_vectors, q, self.threshold) {
+            if let Some(bucket) = self.buckets.get(&i) {
+                count += bucket
+                    .range(window.clone())
+                    .flat_map(|(_, points)| points)
+                    .filter(|point| dot_product(q, point) >= self.beta)
+                    .count();
+            }
+        }
+        Ok(count)
+    }
+
+    /// Return the number of stored points.
+    pub fn len(&self) -> usize {
+        self.buckets.values().flat_map(|bucket| bucket.values()).map(|points| points.len()).sum()
+    }
+
+    /// Return true if no point is stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn check_point(&self, p: &[f64]) -> Result<(), io::Error> {
+        check_dimension(self.d, p.len())?;
+        if !is_normalized(p) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Point is not normalized",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Test function for WindowedCounter struct.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test that the windowed count excludes the points outside the window
+    #[test]
+    fn test_count_neighbors_in_window() {
+        let mut counter = WindowedCounter::new(100, 3, 0.9, 0.8, 0.1, 1).unwrap();
+        // Probe every bucket so that the counts do not depend on the Gaussian vectors
+        counter.threshold = f64::NEG_INFINITY;
+        let x = vec![1.0, 0.0, 0.0];
+        let tilted = vec![0.9, (1.0f64 - 0.81).sqrt(), 0.0];
+        let y = vec![0.0, 1.0, 0.0];
+        for (p, timestamp) in [(&x, 10), (&tilted, 20), (&x, 30), (&y, 20), (&x, 40)] {
+            counter.insert(p.clone(), timestamp).unwrap();
+        }
+        assert_eq!(counter.len(), 5);
+
+        assert_eq!(counter.count_neighbors_in_window(&x, 0..100).unwrap(), 4);
+        assert_eq!(counter.count_neighbors_in_window(&x, 20..40).unwrap(), 2);
+        assert_eq!(counter.count_neighbors_in_window(&x, 11..20).unwrap(), 0);
+        let (start, end) = (40, 10);
+        assert_eq!(
+            counter.count_neighbors_in_window(&x, start..end).unwrap(),
+            0
+        );
+        assert_eq!(counter.count_neighbors_in_window(&y, 0..100).unwrap(), 1);
+
+        // Subtract the contributions of removed and expired points
+        assert!(counter.remove(&x, 30));
+        assert!(!counter.remove(&x, 30));
+        assert_eq!(counter.count_neighbors_in_window(&x, 20..40).unwrap(), 1);
+        assert_eq!(counter.expire_before(20), 1);
+        assert_eq!(counter.count_neighbors_in_window(&x, 0..100).unwrap(), 2);
+
+        assert!(counter.insert(vec![2.0, 0.0, 0.0], 50).is_err());
+        assert!(counter
+            .count_neighbors_in_window(&[1.0, 0.0], 0..100)
+            .is_err());
+    }
+
+    // test that the constructor rejects an empty expected size and invalid parameters
+    #[test]
+    fn test_new_validation() {
+        assert!(WindowedCounter::new(0, 3, 0.9, 0.8, 0.1, 1).is_err());
+        assert!(WindowedCounter::new(100, 0, 0.9, 0.8, 0.1, 1).is_err());
+        assert!(WindowedCounter::new(100, 3, 0.8, 0.9, 0.1, 1).is_err());
+        assert!(WindowedCounter::new(100, 3, 1.5, 0.8, 0.1, 1).is_err());
+        assert!(WindowedCounter::new(100, 3, 0.9, 0.8, 0.0, 1).is_err());
+        // A single expected point still gets a Gaussian vector
+        let mut counter = WindowedCounter::new(1, 3, 0.9, 0.8, 0.1, 1).unwrap();
+        assert!(!counter.gaussian_vectors.is_empty());
+        counter.insert(vec![1.0, 0.0, 0.0], 0).unwrap();
+        assert_eq!(counter.len(), 1);
+    }
+}
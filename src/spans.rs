@@ -0,0 +1,181 @@
+//! Structured `tracing` spans around the construction and the queries of Top1, enabled by the
+//! `tracing` feature. Without the feature every function is a no-op.
+
+/// Guard of an entered span, which exits the span when dropped.
+#[cfg(feature = "tracing")]
+pub(crate) type Span = tracing::span::EnteredSpan;
+/// Guard of an entered span, which does nothing without the `tracing` feature.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Span;
+
+/// Enter a span around the construction of a Top1 struct.
+#[cfg(feature = "tracing")]
+pub(crate) fn build_span(n: usize, d: usize, m: usize) -> Span {
+    tracing::info_span!("build", n, d, m).entered()
+}
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn build_span(_n: usize, _d: usize, _m: usize) -> Span {
+    Span
+}
+
+/// Enter a span around a query, whose fields are recorded by `record_query`.
+#[cfg(feature = "tracing")]
+pub(crate) fn query_span() -> Span {
+    tracing::info_span!(
+        "query",
+        gaussian_hits = tracing::field::Empty,
+        candidates_scored = tracing::field::Empty
+    )
+    .entered()
+}
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn query_span() -> Span {
+    Span
+}
+
+/// Record the number of Gaussian vectors that met the threshold and the number of points
+/// scored in the current query span.
+#[cfg(feature = "tracing")]
+pub(crate) fn record_query(gaussian_hits: usize, candidates_scored: usize) {
+    let span = tracing::Span::current();
+    span.record("gaussian_hits", gaussian_hits);
+    span.record("candidates_scored", candidates_scored);
+}
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record_query(_gaussian_hits: usize, _candidates_scored: usize) {}
+
+/// Test function for the tracing spans.
+#[cfg(all(test, feature = "tracing"))]
+pub(crate) mod tests {
+    use crate::simple_data_structures::top1::Top1;
+    use std::fmt;
+    use std::sync::Mutex;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+    use tracing_core::span::Current;
+
+    /// Recorded span: its metadata and its recorded fields.
+    type RecordedSpan = (&'static Metadata<'static>, Vec<(String, String)>);
+
+    /// Subscriber recording the fields of every span.
+    #[derive(Default)]
+    struct Recorder {
+        spans: Mutex<Vec<RecordedSpan>>,
+        stack: Mutex<Vec<u64>>,
+    }
+
+    struct FieldVisitor<'a>(&'a mut Vec<(String, String)>);
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .push((field.name().to_string(), format!("{:?}", value)));
+        }
+    }
+
+    impl Recorder {
+        /// Return the recorded fields of the spans called `name`.
+        fn fields(&self, name: &str) -> Vec<Vec<(String, String)>> {
+            let spans = self.spans.lock().unwrap();
+            spans
+                .iter()
+                .filter(|(metadata, _)| metadata.name() == name)
+                .map(|(_, fields)| fields.clone())
+                .collect()
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+            let mut fields = Vec::new();
+            attributes.record(&mut FieldVisitor(&mut fields));
+            let mut spans = self.spans.lock().unwrap();
+            spans.push((attributes.metadata(), fields));
+            Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, id: &Id, values: &Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut FieldVisitor(&mut spans[id.into_u64() as usize - 1].1));
+        }
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, id: &Id) {
+            self.stack.lock().unwrap().push(id.into_u64());
+        }
+        fn exit(&self, _: &Id) {
+            self.stack.lock().unwrap().pop();
+        }
+        fn current_span(&self) -> Current {
+            match self.stack.lock().unwrap().last() {
+                Some(&id) => {
+                    let metadata = self.spans.lock().unwrap()[id as usize - 1].0;
+                    Current::new(Id::from_u64(id), metadata)
+                }
+                None => Current::none(),
+            }
+        }
+    }
+
+    /// Run `f` with a recording subscriber and return its result and the `candidates_scored`
+    /// field of each query span entered by `f`, for the tests of the query paths.
+    pub(crate) fn candidates_scored<R>(f: impl FnOnce() -> R) -> (R, Vec<usize>) {
+        let recorder = std::sync::Arc::new(Recorder::default());
+        let result = tracing::subscriber::with_default(recorder.clone(), f);
+        let scored = recorder
+            .fields("query")
+            .iter()
+            .flat_map(|fields| {
+                fields
+                    .iter()
+                    .filter(|(name, _)| name == "candidates_scored")
+            })
+            .map(|(_, value)| value.parse().unwrap())
+            .collect();
+        (result, scored)
+    }
+
+    /// Return the pair of a field name and its value as recorded.
+    fn field(name: &str, value: usize) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    // test that the build and query spans carry the expected fields
+    #[test]
+    fn test_spans() {
+        let recorder = std::sync::Arc::new(Recorder::default());
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let top1 = tracing::subscriber::with_default(recorder.clone(), || {
+            let mut top1 = Top1::new(data.clone(), 0.9, 0.8, 0.5);
+            // Use the axes as Gaussian vectors so that the query hits a single bucket
+            top1.gaussian_vectors = data.clone();
+            top1.hash_table = (0..3).map(|i| (i, vec![data[i].clone()])).collect();
+            top1.threshold = 0.5;
+            top1.query(&[0.0, 1.0, 0.0]).unwrap();
+            top1
+        });
+
+        let build = recorder.fields("build");
+        assert_eq!(build.len(), 1);
+        assert_eq!(
+            build[0],
+            vec![field("n", 3), field("d", 3), field("m", top1.m)]
+        );
+
+        let query = recorder.fields("query");
+        assert_eq!(
+            query,
+            vec![vec![
+                field("gaussian_hits", 1),
+                field("candidates_scored", 1)
+            ]]
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use super::top1::Top1;
-use crate::utils::{find_close_vector, is_normalized};
-use std::collections::HashMap;
+use crate::dedup::{Dedup, DedupStrategy};
+use crate::utils::{dot_product, find_close_vector_ref, is_normalized};
+use std::collections::{HashMap, HashSet};
 use std::io;
 
 /// Query the hash table for a close vector to the query vector.
@@ -12,6 +13,7 @@ use std::io;
 /// - `top1_list`: List of Top1 structures
 /// - `hash_table`: Hash table
 /// - `beta`: Threshold value
+/// - `max_keys`: Upper bound on the number of probed composite keys, unbounded if None
 ///
 /// Returns:
 /// - `Result<Option<Vec<f64>>, io::Error>`: Close vector or None or an error
@@ -26,10 +28,11 @@ use std::io;
 /// If one of the Top1 structures has an empty hash, the Cartesian product will be empty and the query
 /// will not be searched in the hash table. In this case, None will be returned.
 pub fn query(
-    q: &Vec<f64>,
-    top1_list: &Vec<Top1>,
+    q: &[f64],
+    top1_list: &[Top1],
     hash_table: &HashMap<String, Vec<Vec<f64>>>,
     beta: f64,
+    max_keys: Option<usize>,
 ) -> Result<Option<Vec<f64>>, io::Error> {
     // Check if the query vector is normalized
     if !is_normalized(q) {
@@ -38,37 +41,191 @@ pub fn query(
             "Query vector is not normalized",
         ));
     }
+    Ok(query_unchecked(q, top1_list, hash_table, beta, max_keys))
+}
+
+/// Query the hash table for a close vector to the query vector, returning a reference into
+/// the hash table instead of a copy. If the query vector is not normalized, an error is returned.
+pub fn query_ref<'a>(
+    q: &[f64],
+    top1_list: &[Top1],
+    hash_table: &'a HashMap<String, Vec<Vec<f64>>>,
+    beta: f64,
+    max_keys: Option<usize>,
+) -> Result<Option<&'a Vec<f64>>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(q) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    Ok(probe(q, top1_list, hash_table, beta, max_keys))
+}
+
+/// Query the hash table for a close vector to the query vector, without checking that the
+/// query vector is normalized. The caller must guarantee it: a non normalized query silently
+/// gives meaningless results.
+pub fn query_unchecked(
+    q: &[f64],
+    top1_list: &[Top1],
+    hash_table: &HashMap<String, Vec<Vec<f64>>>,
+    beta: f64,
+    max_keys: Option<usize>,
+) -> Option<Vec<f64>> {
+    probe(q, top1_list, hash_table, beta, max_keys).cloned()
+}
+
+/// Count the points with dot product at least `beta` with the query vector in the composite
+/// buckets of the query, collapsing the points stored under several composite keys according
+/// to `strategy`.
+/// If the query vector is not normalized, an error is returned.
+pub fn count_neighbors_with(
+    q: &[f64],
+    top1_list: &[Top1],
+    hash_table: &HashMap<String, Vec<Vec<f64>>>,
+    id_table: &HashMap<String, Vec<usize>>,
+    beta: f64,
+    strategy: DedupStrategy,
+    max_keys: Option<usize>,
+) -> Result<usize, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(q) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
 
+    let mut dedup = Dedup::new(strategy);
+    let mut count = 0;
+    for i in search(top1_list, q, max_keys) {
+        if let (Some(vectors), Some(ids)) = (hash_table.get(&i), id_table.get(&i)) {
+            for (vector, id) in vectors.iter().zip(ids.iter()) {
+                if dot_product(q, vector) >= beta && dedup.insert(*id, vector)? {
+                    count += 1;
+                }
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Return the dot products and copies of all the points with dot product at least `beta` with
+/// the query vector in the composite buckets of the query, deduplicated by their index in
+/// `id_table` and sorted by decreasing dot product.
+/// If the query vector is not normalized, an error is returned.
+pub fn query_all(
+    q: &[f64],
+    top1_list: &[Top1],
+    hash_table: &HashMap<String, Vec<Vec<f64>>>,
+    id_table: &HashMap<String, Vec<usize>>,
+    beta: f64,
+    max_keys: Option<usize>,
+) -> Result<Vec<(f64, Vec<f64>)>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(q) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+
+    // Keep every close vector once
+    let mut seen = HashSet::new();
+    let mut neighbors: Vec<(f64, Vec<f64>)> = Vec::new();
+    for i in search(top1_list, q, max_keys) {
+        if let (Some(vectors), Some(ids)) = (hash_table.get(&i), id_table.get(&i)) {
+            for (vector, id) in vectors.iter().zip(ids.iter()) {
+                let score = dot_product(q, vector);
+                if score >= beta && seen.insert(*id) {
+                    neighbors.push((score, vector.clone()));
+                }
+            }
+        }
+    }
+    neighbors.sort_by(|a, b| b.0.total_cmp(&a.0));
+    Ok(neighbors)
+}
+
+/// Return the composite keys probed by a query, i.e. the Cartesian product of the hashes of
+/// the Gaussian vectors that meet the threshold in each Top1 structure, without scanning the
+/// buckets. The keys are empty if some structure has no Gaussian vector above the threshold.
+/// If the query vector is not normalized, an error is returned.
+pub fn probed_keys(
+    q: &[f64],
+    top1_list: &[Top1],
+    max_keys: Option<usize>,
+) -> Result<Vec<String>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(q) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    Ok(search(top1_list, q, max_keys))
+}
+
+/// Like `probed_keys`, but at most `max_size` composite keys are returned, built from the
+/// hashes of the Gaussian vectors with the highest dot products with the query, to bound the
+/// Cartesian product of `t` structures with `k` hits each, which has `k^t` keys.
+/// If the query vector is not normalized, an error is returned.
+pub fn probed_keys_capped(
+    q: &[f64],
+    top1_list: &[Top1],
+    max_size: usize,
+) -> Result<Vec<String>, io::Error> {
+    // Check if the query vector is normalized
+    if !is_normalized(q) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Query vector is not normalized",
+        ));
+    }
+    Ok(search_capped(top1_list, q, max_size))
+}
+
+/// Probe the composite buckets of the query for a close vector.
+fn probe<'a>(
+    q: &[f64],
+    top1_list: &[Top1],
+    hash_table: &'a HashMap<String, Vec<Vec<f64>>>,
+    beta: f64,
+    max_keys: Option<usize>,
+) -> Option<&'a Vec<f64>> {
     // Get the cartesian product of the hashes of the Gaussian vectors that meet the threshold
-    let indices = search(top1_list, q);
+    let indices = search(top1_list, q, max_keys);
 
     // If the indices are empty, return None
     if indices.is_empty() {
         println!("Some indices are empty. Query is not possible.");
-        return Ok(None);
+        return None;
     }
 
     // Search for a close vector in the hash table
     for i in indices {
         if let Some(vectors) = hash_table.get(&i) {
-            if let Some(close_vector) = find_close_vector(q, vectors, beta) {
+            if let Some(close_vector) = find_close_vector_ref(q, vectors, beta) {
                 println!("Found a close vector! .");
-                return Ok(Some(close_vector));
+                return Some(close_vector);
             }
         }
     }
 
     println!("No close vector found.");
     // If no vector meets the `beta` threshold, return None
-    Ok(None)
+    None
 }
 
 /// Search for the indices of the Gaussian vectors that meet the threshold in each Top1 structure.
-/// The output is the Cartesian product of the indices.
+/// The output is the Cartesian product of the indices, or at most `max_keys` of them if given,
+/// see `search_capped`.
 ///
 /// Parameters:
 /// - `top1_list`: List of Top1 structures
 /// - `q`: Query vector
+/// - `max_keys`: Upper bound on the number of composite keys
 ///
 /// Returns:
 /// - `Vec<String>`: Cartesian product of the indices
@@ -76,7 +233,10 @@ pub fn query(
 /// # Example
 /// If we have two Top1 structures with  ["0#"] and ["0#", "2#"] as the hashes of the
 /// Gaussian vectors that meet the threshold, the Cartesian product will be ["0#0#", "0#2#"].
-fn search(top1_list: &Vec<Top1>, q: &Vec<f64>) -> Vec<String> {
+fn search(top1_list: &[Top1], q: &[f64], max_keys: Option<usize>) -> Vec<String> {
+    if let Some(max_keys) = max_keys {
+        return search_capped(top1_list, q, max_keys);
+    }
     // Instantiate a collection to store the results
     let mut collection: Vec<Vec<String>> = Vec::new();
     // Iterate over each Top1 structure
@@ -86,11 +246,21 @@ fn search(top1_list: &Vec<Top1>, q: &Vec<f64>) -> Vec<String> {
         collection.push(hashes);
     });
     // Create the Cartesian product of the results
-    cartesian_product(collection)
+    cartesian_product(collection, usize::MAX)
+}
+
+/// Like `search`, but at most `max_size` composite keys are generated, combining the hashes
+/// of the Gaussian vectors of each structure by decreasing dot product with the query, so
+/// that the strongest hits are kept.
+fn search_capped(top1_list: &[Top1], q: &[f64], max_size: usize) -> Vec<String> {
+    let collection = top1_list.iter().map(|top1| top1.search_sorted(q)).collect();
+    cartesian_product(collection, max_size)
 }
 
 /// Compute the Cartesian product of a collection of collections.
-fn cartesian_product(collection: Vec<Vec<String>>) -> Vec<String> {
+/// At most `max_size` elements are generated: the product is truncated, keeping the
+/// combinations of the first elements of each collection.
+fn cartesian_product(collection: Vec<Vec<String>>, max_size: usize) -> Vec<String> {
     // If the collection is empty, return an empty vector
     if collection.is_empty() {
         return vec![];
@@ -101,6 +271,7 @@ fn cartesian_product(collection: Vec<Vec<String>>) -> Vec<String> {
         // For each prefix in the accumulator, append each suffix in the current set
         acc.into_iter()
             .flat_map(|prefix| set.iter().map(move |suffix| format!("{}{}", prefix, suffix)))
+            .take(max_size)
             .collect() // Collect the results into a vector
     })
 }
@@ -116,7 +287,7 @@ mod tests {
         let vec1 = vec!["a".to_string(), "b".to_string()];
         let vec2 = vec!["c".to_string(), "d".to_string()];
         let collection = vec![vec1, vec2];
-        let result = cartesian_product(collection);
+        let result = cartesian_product(collection, usize::MAX);
         assert_eq!(
             result,
             vec![
@@ -130,7 +301,7 @@ mod tests {
         let vec1 = vec!["a".to_string(), "b".to_string(), "c".to_string()];
         let vec2 = vec!["c".to_string()];
         let collection = vec![vec1, vec2];
-        let result = cartesian_product(collection);
+        let result = cartesian_product(collection, usize::MAX);
         assert_eq!(
             result,
             vec!["ac".to_string(), "bc".to_string(), "cc".to_string()]
@@ -138,21 +309,21 @@ mod tests {
 
         let vec1 = vec!["a".to_string()];
         let collection = vec![vec1];
-        let result = cartesian_product(collection);
+        let result = cartesian_product(collection, usize::MAX);
         assert_eq!(result, vec!["a".to_string()]);
 
         let vec1 = vec!["a".to_string(), "b".to_string()];
         let vec2 = Vec::<String>::new();
         let vec3 = vec!["c".to_string()];
         let collection = vec![vec1, vec2, vec3];
-        let result = cartesian_product(collection);
+        let result = cartesian_product(collection, usize::MAX);
         assert_eq!(result, Vec::<String>::new());
 
         let vec1 = vec!["a#".to_string(), "b#".to_string()];
         let vec2 = vec!["c#".to_string()];
         let vec3 = vec!["d#".to_string()];
         let collection = vec![vec1, vec2, vec3];
-        let result = cartesian_product(collection);
+        let result = cartesian_product(collection, usize::MAX);
         assert_eq!(
             result,
             vec![
@@ -161,4 +332,22 @@ mod tests {
             ]
         );
     }
+
+    // Test that a capped Cartesian product keeps the first combinations
+    #[test]
+    fn test_cartesian_product_capped() {
+        let set = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let collection = vec![
+            set(&["a", "b", "c"]),
+            set(&["d", "e"]),
+            set(&["f", "g", "h"]),
+        ];
+        let full = cartesian_product(collection.clone(), usize::MAX);
+        assert_eq!(full.len(), 18);
+        for max_size in [0, 1, 4, 17, 18, 100] {
+            let capped = cartesian_product(collection.clone(), max_size);
+            assert!(capped.len() <= max_size);
+            assert_eq!(capped, full[..max_size.min(18)].to_vec());
+        }
+    }
 }
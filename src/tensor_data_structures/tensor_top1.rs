@@ -1,26 +1,217 @@
-use super::query::query;
+use super::query::{
+    count_neighbors_with, probed_keys, probed_keys_capped, query, query_all, query_ref,
+    query_unchecked,
+};
 use super::top1::Top1;
-use crate::utils::get_threshold;
+use crate::checks::check_parameters;
+use crate::dedup::DedupStrategy;
+use crate::error::AnnError;
+use crate::simple_data_structures::top1::Top1 as FlatTop1;
+use crate::utils::{compute_m, get_threshold, BuildReport};
+use rayon::prelude::*;
 use std::collections::HashMap;
-use std::io;
-use rand_distr::num_traits::Pow;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
 
+/// Time spent in each phase of the construction of a TensorTop1 structure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorBuildReport {
+    /// Construction report of each Top1 structure
+    pub structures: Vec<BuildReport>,
+    /// Time spent creating the composite Hash Table
+    pub hashing: Duration,
+    /// Total construction time
+    pub total: Duration,
+}
+
+/// Default bound on the number of Top1 structures, see `TensorTop1Config::max_t`.
+pub const DEFAULT_MAX_T: usize = 100;
+
+/// Options of the construction of a TensorTop1 struct, see `TensorTop1::with_config`.
+#[derive(Debug, Clone, Default)]
+pub struct TensorTop1Config {
+    /// Seed of the Gaussian vectors, the i-th Top1 structure uses seed `seed + i`
+    pub seed: Option<u64>,
+    /// Upper bound on the number of Top1 structures `t`, which grows as `1 / (1 - alpha^2)`
+    /// when alpha tends to 1. A larger `t` is clamped with a warning, keeping the number of
+    /// Gaussian vectors of each structure, so the recall may degrade. `DEFAULT_MAX_T` if `None`.
+    pub max_t: Option<usize>,
+    /// Number of threads of a dedicated Rayon pool running the construction, so that it does
+    /// not saturate the cores shared with the rest of the application. The global pool (or the
+    /// pool of the caller, within `ThreadPool::install`) is used if `None`.
+    pub num_threads: Option<usize>,
+    /// Upper bound on the number of composite keys probed by the queries, which is `k^t` for
+    /// `t` structures with `k` hits each. The keys combining the strongest hits are kept, see
+    /// `TensorTop1::probed_keys_capped`. Unbounded if `None`.
+    pub max_probed_keys: Option<usize>,
+}
+
+type HashTable = HashMap<String, Vec<Vec<f64>>>;
+type IdTable = HashMap<String, Vec<usize>>;
+
+#[derive(Clone)]
 pub struct TensorTop1 {
     pub top1_list: Vec<Top1>,
     pub hash_table: HashMap<String, Vec<Vec<f64>>>,
+    // Indices in the data of the points in each composite bucket, in the same order as
+    // `hash_table`
+    pub id_table: HashMap<String, Vec<usize>>,
     pub alpha: f64,
     pub beta: f64,
+    pub d: usize,
+    // See `TensorTop1Config::max_probed_keys`
+    pub max_probed_keys: Option<usize>,
 }
 
 impl TensorTop1 {
-    pub fn new(data: Vec<Vec<f64>>,
-               alpha: f64,
-               beta: f64,
-               theta: f64,
-               fast_preprocessing: bool,
+    pub fn new(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        fast_preprocessing: bool,
+    ) -> Self {
+        Self::build(
+            data,
+            alpha,
+            beta,
+            theta,
+            fast_preprocessing,
+            &TensorTop1Config::default(),
+        )
+        .0
+    }
+
+    /// Constructor that also reports the time spent building each Top1 structure and
+    /// the composite Hash Table.
+    pub fn new_timed(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        fast_preprocessing: bool,
+    ) -> (Self, TensorBuildReport) {
+        Self::build(
+            data,
+            alpha,
+            beta,
+            theta,
+            fast_preprocessing,
+            &TensorTop1Config::default(),
+        )
+    }
+
+    /// Constructor with seeded Gaussian vectors. The i-th Top1 structure uses seed `seed + i`,
+    /// so the same seed always produces the same structure.
+    pub fn new_with_seed(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        fast_preprocessing: bool,
+        seed: u64,
     ) -> Self {
+        let config = TensorTop1Config {
+            seed: Some(seed),
+            ..Default::default()
+        };
+        Self::build(data, alpha, beta, theta, fast_preprocessing, &config).0
+    }
+
+    /// Constructor with the options of `config`.
+    pub fn with_config(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        fast_preprocessing: bool,
+        config: TensorTop1Config,
+    ) -> Self {
+        Self::build(data, alpha, beta, theta, fast_preprocessing, &config).0
+    }
+
+    /// Constructor building one Top1 structure per value of `alphas`, each with its own number
+    /// of Gaussian vectors and threshold, e.g. to tune some structures tighter than others.
+    /// Each structure uses the exponent `theta / alphas.len()`, and the i-th structure uses
+    /// seed `seed + i` if a seed is given. The `alpha` of the structure is the smallest value.
+    /// An error is returned if `alphas` or `data` is empty, or if an alpha does not satisfy
+    /// `0 < beta < alpha < 1`.
+    pub fn new_heterogeneous(
+        data: Vec<Vec<f64>>,
+        alphas: &[f64],
+        beta: f64,
+        theta: f64,
+        seed: Option<u64>,
+    ) -> Result<Self, AnnError> {
+        if alphas.is_empty() {
+            return Err(AnnError::InvalidParameter("At least one alpha is required".to_string()));
+        }
+        if data.is_empty() {
+            return Err(AnnError::InvalidParameter("data must not be empty".to_string()));
+        }
+        for &alpha in alphas {
+            check_parameters(alpha, beta, theta).map_err(AnnError::InvalidParameter)?;
+        }
+        let d = data[0].len();
+        let theta = theta / alphas.len() as f64;
+
+        let top1_list: Vec<Top1> = alphas
+            .par_iter()
+            .enumerate()
+            .map(|(i, &alpha)| {
+                let seed = seed.map(|seed| seed.wrapping_add(i as u64));
+                Top1::build(&data, alpha, beta, theta, seed).0
+            })
+            .collect();
+        let (hash_table, id_table) = get_hash_table(data, &top1_list);
+
+        Ok(TensorTop1 {
+            top1_list,
+            hash_table,
+            id_table,
+            alpha: alphas.iter().copied().fold(f64::INFINITY, f64::min),
+            beta,
+            d,
+            max_probed_keys: None,
+        })
+    }
+
+    fn build(
+        data: Vec<Vec<f64>>,
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        fast_preprocessing: bool,
+        config: &TensorTop1Config,
+    ) -> (Self, TensorBuildReport) {
+        if let Some(num_threads) = config.num_threads {
+            match rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+            {
+                Ok(pool) => {
+                    let config = TensorTop1Config {
+                        num_threads: None,
+                        ..config.clone()
+                    };
+                    return pool.install(|| {
+                        Self::build(data, alpha, beta, theta, fast_preprocessing, &config)
+                    });
+                }
+                Err(err) => log::warn!(
+                    "Thread pool creation failed, using the global pool: {}",
+                    err
+                ),
+            }
+        }
+        let start = Instant::now();
         // Number of data points
         let n = data.len() as f64;
+        // Dimension of the data points
+        let d = data[0].len();
         // Number of Top1 structures
         let t = if fast_preprocessing{
             // Fast preprocessing n^{1+o(1)}
@@ -30,48 +221,236 @@ impl TensorTop1 {
             // Pre-processing as query time n^{1+o(1)}
             (1. / (1. - alpha.powi(2))).ceil() as usize
         };
-        // Update theta
+        // Update theta, from the unclamped t so that each structure keeps its number of
+        // Gaussian vectors
         let theta = theta / (t as f64);
+        // Bound t, which is huge (or saturated) when alpha is close to 1
+        let max_t = config.max_t.unwrap_or(DEFAULT_MAX_T).max(1);
+        let t = if t > max_t {
+            log::warn!(
+                "Number of Top1 structures {} exceeds max_t, clamped to {}: recall may degrade.",
+                t,
+                max_t
+            );
+            max_t
+        } else {
+            t
+        };
 
-        //// Print parameters
+        //// Log parameters
         {
-            println!("\nParameters:");
-            println!("Number of data points: {}", n);
-            println!("Dimension of the data points: {}", data[0].len());
-            println!("Alpha: {}", alpha);
-            println!("Beta: {}", beta);
-            println!("Fast Pre-processing: {}", fast_preprocessing);
-            println!("Number of Top1 structures: {}", t);
-            let m = (n as f64).pow(theta / (1. - alpha.powi(2))).ceil() as usize;
-            println!("Number of Gaussian vectors for each Top1 structure: {}", m);
+            log::info!("Parameters:");
+            log::info!("Number of data points: {}", n);
+            log::info!("Dimension of the data points: {}", d);
+            log::info!("Alpha: {}", alpha);
+            log::info!("Beta: {}", beta);
+            log::info!("Fast Pre-processing: {}", fast_preprocessing);
+            log::info!("Number of Top1 structures: {}", t);
+            let m = compute_m(data.len(), alpha, theta);
+            log::info!("Number of Gaussian vectors for each Top1 structure: {}", m);
             let threshold = get_threshold(alpha, m);
-            println!("Threshold: {}", threshold);
-            println!("\n");
+            log::info!("Threshold: {}", threshold);
         }
 
-        //// Store t Top1 structures
-        let mut top1_list = Vec::new();
-        for i in 0..t {
-            println!("Creating Top1 structure {}/{}", i, t);
-            let top1 = Top1::new(&data, alpha, beta, theta);
-            top1_list.push(top1);
-        }
+        //// Store t Top1 structures, built in parallel since they are independent
+        let (top1_list, structures): (Vec<Top1>, Vec<BuildReport>) = (0..t)
+            .into_par_iter()
+            .map(|i| {
+                log::info!("Creating Top1 structure {}/{}", i, t);
+                let seed = config.seed.map(|seed| seed.wrapping_add(i as u64));
+                Top1::build(&data, alpha, beta, theta, seed)
+            })
+            .unzip();
 
         //// Create the Hash Table (move data into the hash table)
-        println!("Creating the Hash Table");
-        let hash_table = get_hash_table(data, &top1_list);
+        log::info!("Creating the Hash Table");
+        let hashing_start = Instant::now();
+        let (hash_table, id_table) = get_hash_table(data, &top1_list);
+        let hashing = hashing_start.elapsed();
 
-        TensorTop1 {
+        let tensor_top1 = TensorTop1 {
             top1_list,
             hash_table,
+            id_table,
             alpha,
             beta,
+            d,
+            max_probed_keys: config.max_probed_keys,
+        };
+        let report = TensorBuildReport {
+            structures,
+            hashing,
+            total: start.elapsed(),
+        };
+        (tensor_top1, report)
+    }
+
+    pub fn query(&self, q: &[f64]) -> Result<Option<Vec<f64>>, io::Error> {
+        log::info!("Querying the TensorTop1 structure");
+        query(
+            q,
+            &self.top1_list,
+            &self.hash_table,
+            self.beta,
+            self.max_probed_keys,
+        )
+    }
+
+    /// Query the structure, returning a reference into the hash table instead of a copy.
+    pub fn query_ref(&self, q: &[f64]) -> Result<Option<&Vec<f64>>, io::Error> {
+        query_ref(
+            q,
+            &self.top1_list,
+            &self.hash_table,
+            self.beta,
+            self.max_probed_keys,
+        )
+    }
+
+    /// Query without checking that `q` is normalized. The caller must guarantee it:
+    /// garbage in, garbage out.
+    pub fn query_unchecked(&self, q: &[f64]) -> Option<Vec<f64>> {
+        query_unchecked(
+            q,
+            &self.top1_list,
+            &self.hash_table,
+            self.beta,
+            self.max_probed_keys,
+        )
+    }
+
+    /// Count the distinct points with dot product at least `beta` with the query `q` in the
+    /// probed composite buckets. A point stored under several composite keys is counted once.
+    pub fn count_neighbors(&self, q: &[f64]) -> Result<usize, io::Error> {
+        self.count_neighbors_with(q, DedupStrategy::ByIndex)
+    }
+
+    /// Like `count_neighbors`, but the points stored under several composite keys are collapsed
+    /// according to `strategy` instead of by index.
+    pub fn count_neighbors_with(
+        &self,
+        q: &[f64],
+        strategy: DedupStrategy,
+    ) -> Result<usize, io::Error> {
+        count_neighbors_with(
+            q,
+            &self.top1_list,
+            &self.hash_table,
+            &self.id_table,
+            self.beta,
+            strategy,
+            self.max_probed_keys,
+        )
+    }
+
+    /// Return the dot products and copies of all the distinct points with dot product at least
+    /// `beta` with the query `q` in the probed composite buckets, by decreasing dot product.
+    pub fn query_all(&self, q: &[f64]) -> Result<Vec<(f64, Vec<f64>)>, io::Error> {
+        let max_keys = self.max_probed_keys;
+        query_all(
+            q,
+            &self.top1_list,
+            &self.hash_table,
+            &self.id_table,
+            self.beta,
+            max_keys,
+        )
+    }
+
+    /// Return the composite keys probed by `query` for the query `q`, without scanning the
+    /// buckets, at most `TensorTop1Config::max_probed_keys` of them. An empty result means that
+    /// some Top1 structure has no Gaussian vector above the threshold, while probed keys
+    /// without a close point mean a miss in the buckets.
+    pub fn probed_keys(&self, q: &[f64]) -> Result<Vec<String>, io::Error> {
+        probed_keys(q, &self.top1_list, self.max_probed_keys)
+    }
+
+    /// Like `probed_keys`, but at most `max_size` composite keys are returned, combining the
+    /// Gaussian vectors of each structure with the highest dot products with the query.
+    pub fn probed_keys_capped(&self, q: &[f64], max_size: usize) -> Result<Vec<String>, io::Error> {
+        probed_keys_capped(q, &self.top1_list, max_size)
+    }
+
+    /// Write the composite bucket each point is assigned to as CSV rows
+    /// `point_index,composite_key`, after a header line and sorted by point index.
+    pub fn export_assignments_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut assignments: Vec<(usize, &str)> = self
+            .id_table
+            .iter()
+            .flat_map(|(hash, ids)| ids.iter().map(move |id| (*id, hash.as_str())))
+            .collect();
+        assignments.sort_unstable();
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "point_index,composite_key")?;
+        for (id, hash) in assignments {
+            writeln!(writer, "{},{}", id, hash)?;
         }
+        writer.flush()
     }
 
-    pub fn query(&self, q: &Vec<f64>) -> Result<Option<Vec<f64>>, io::Error> {
-        println!("Querying the TensorTop1 structure");
-        query(q, &self.top1_list, &self.hash_table, self.beta)
+    /// Convert a structure made of a single Top1 structure into a flat Top1 structure, whose
+    /// hash table is rebuilt from the composite buckets. Structures made of several Top1
+    /// structures must be rebuilt from the data instead, and an error is returned.
+    pub fn into_flat(self) -> Result<FlatTop1, AnnError> {
+        let t = self.top1_list.len();
+        if t != 1 {
+            return Err(AnnError::NotFlattenable { structures: t });
+        }
+        let top1 = self.top1_list.into_iter().next().unwrap();
+
+        // With a single structure the composite hash is "i#", where i is the Gaussian vector
+        let mut hash_table = HashMap::new();
+        let mut id_table = HashMap::new();
+        for (hash, vectors) in self.hash_table {
+            let i: usize = hash.trim_end_matches('#').parse().unwrap();
+            let ids = self.id_table.get(&hash).cloned().unwrap_or_default();
+            hash_table.insert(i, vectors);
+            id_table.insert(i, ids);
+        }
+        Ok(FlatTop1::from_buckets(
+            top1.gaussian_vectors,
+            hash_table,
+            id_table,
+            self.alpha,
+            self.beta,
+            self.d,
+        ))
+    }
+
+    /// Return the number of points stored in the hash table.
+    pub fn len(&self) -> usize {
+        self.hash_table.values().map(|bucket| bucket.len()).sum()
+    }
+
+    /// Return true if no point is stored in the hash table.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return the dimension of the indexed vectors.
+    pub fn dim(&self) -> usize {
+        self.d
+    }
+}
+
+/// Summarize the parameters of the structure instead of dumping its vectors.
+impl fmt::Debug for TensorTop1 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let m: Vec<usize> = self
+            .top1_list
+            .iter()
+            .map(|top1| top1.gaussian_vectors.len())
+            .collect();
+        f.debug_struct("TensorTop1")
+            .field("n", &self.len())
+            .field("d", &self.d)
+            .field("t", &self.top1_list.len())
+            .field("m", &m)
+            .field("alpha", &self.alpha)
+            .field("beta", &self.beta)
+            .field("num_buckets", &self.hash_table.len())
+            .finish()
     }
 }
 
@@ -83,14 +462,17 @@ impl TensorTop1 {
 ///
 /// Parameters:
 /// data: Vec<Vec<f64>> - The data points as reference
-/// top1_list: &Vec<Top1> - The list of Top1 structures as reference
+/// top1_list: &[Top1] - The list of Top1 structures as reference
 ///
 /// Returns:
 /// HashMap<String, Vec<Vec<f64>>> - The Hash Table indexed by the string of indices
-fn get_hash_table(data: Vec<Vec<f64>>, top1_list: &Vec<Top1>) -> HashMap<String, Vec<Vec<f64>>> {
+/// HashMap<String, Vec<usize>> - The indices in the data of the points of each bucket
+fn get_hash_table(data: Vec<Vec<f64>>, top1_list: &[Top1]) -> (HashTable, IdTable) {
 
-    // Initialize the Hash Table
-    let mut hash_table: HashMap<String, Vec<Vec<f64>>> = HashMap::new();
+    // Initialize the Hash Table, with the expected number of composite buckets
+    let capacity = expected_occupancy(data.len(), top1_list);
+    let mut hash_table: HashTable = HashMap::with_capacity(capacity);
+    let mut id_table: IdTable = HashMap::with_capacity(capacity);
 
     // Iterate over each data vector using a consuming iterator
     for (i, point) in data.into_iter().enumerate() {
@@ -105,12 +487,433 @@ fn get_hash_table(data: Vec<Vec<f64>>, top1_list: &Vec<Top1>) -> HashMap<String,
             hash += &top1.hash(i);
         }
 
-        // Insert the point in the Hash Table
-        hash_table
-            .entry(hash)
-            .or_insert_with(Vec::new)
-            .push(point)
+        // Insert the point and its index in the Hash Table
+        id_table.entry(hash.clone()).or_default().push(i);
+        hash_table.entry(hash).or_default().push(point)
     }
 
-    hash_table
+    (hash_table, id_table)
+}
+
+/// Return the expected number of non-empty composite buckets when `n` points fall uniformly
+/// in the `k` composite keys of `top1_list`, i.e. `k (1 - (1 - 1/k)^n)`, at most `n`.
+fn expected_occupancy(n: usize, top1_list: &[Top1]) -> usize {
+    let k: f64 = top1_list
+        .iter()
+        .map(|top1| top1.gaussian_vectors.len() as f64)
+        .product();
+    if k <= 1.0 {
+        return n.min(1);
+    }
+    let expected = k * (1. - (n as f64 * (-1. / k).ln_1p()).exp());
+    (expected.ceil() as usize).min(n)
+}
+
+/// Test function for TensorTop1 struct.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test len, is_empty and dim
+    #[test]
+    fn test_len_and_dim() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+            vec![1.0, 0.0, 0.0],
+        ];
+        let tensor_top1 = TensorTop1::new(data, 0.9, 0.8, 0.5, false);
+        assert_eq!(tensor_top1.len(), 4);
+        assert!(!tensor_top1.is_empty());
+        assert_eq!(tensor_top1.dim(), 3);
+    }
+
+    // test the construction time report
+    #[test]
+    fn test_new_timed() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let (tensor_top1, report) = TensorTop1::new_timed(data, 0.9, 0.8, 0.5, false);
+        assert_eq!(report.structures.len(), tensor_top1.top1_list.len());
+        for structure in report.structures.iter() {
+            assert!(structure.total >= structure.gaussian_gen + structure.hashing);
+        }
+        // The structures are built in parallel, so only the longest one is a lower bound
+        let longest = report.structures.iter().map(|s| s.total).max().unwrap();
+        assert!(report.total >= longest + report.hashing);
+    }
+
+    // test that a point stored under two composite keys is counted once
+    #[test]
+    fn test_count_neighbors_dedup() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let mut tensor_top1 = TensorTop1::new_with_seed(data, 0.9, 0.8, 0.5, false, 5);
+        // Probe every Gaussian vector of every structure
+        for top1 in tensor_top1.top1_list.iter_mut() {
+            top1.threshold = f64::NEG_INFINITY;
+        }
+        let q = vec![1.0, 0.0, 0.0];
+        assert_eq!(tensor_top1.count_neighbors(&q).unwrap(), 1);
+
+        // Store the first point, equal to q, under a second composite key
+        let t = tensor_top1.top1_list.len();
+        let key: String = tensor_top1
+            .top1_list
+            .iter()
+            .map(|top1| top1.hash(0))
+            .collect();
+        let new_key = if key == "0#".repeat(t) {
+            "1#".repeat(t)
+        } else {
+            "0#".repeat(t)
+        };
+        tensor_top1
+            .hash_table
+            .entry(new_key.clone())
+            .or_default()
+            .push(q.clone());
+        tensor_top1.id_table.entry(new_key).or_default().push(0);
+
+        assert_eq!(tensor_top1.len(), 4);
+        assert_eq!(tensor_top1.count_neighbors(&q).unwrap(), 1);
+        assert_eq!(
+            tensor_top1
+                .count_neighbors_with(&q, DedupStrategy::ByIndex)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            tensor_top1
+                .count_neighbors_with(&q, DedupStrategy::ByValue)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            tensor_top1
+                .count_neighbors_with(&q, DedupStrategy::None)
+                .unwrap(),
+            2
+        );
+        let keys = [0, 0, 0];
+        assert_eq!(
+            tensor_top1
+                .count_neighbors_with(&q, DedupStrategy::ByKey(&keys))
+                .unwrap(),
+            1
+        );
+    }
+
+    // test that every point appears once in the exported assignments, with its composite key
+    #[test]
+    fn test_export_assignments_csv() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let tensor_top1 = TensorTop1::new_with_seed(data, 0.9, 0.8, 0.5, false, 6);
+        let path = std::env::temp_dir().join(format!(
+            "ann_rust_tensor_assignments_{}.csv",
+            std::process::id()
+        ));
+        tensor_top1.export_assignments_csv(&path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("point_index,composite_key"));
+        for (i, line) in lines.enumerate() {
+            let key: String = tensor_top1
+                .top1_list
+                .iter()
+                .map(|top1| top1.hash(i))
+                .collect();
+            assert_eq!(line, format!("{},{}", i, key));
+        }
+        assert_eq!(csv.lines().count(), 4);
+    }
+
+    // test that query_all returns each close point of the probed buckets once, best first
+    #[test]
+    fn test_query_all() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.6, 0.8, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let mut tensor_top1 = TensorTop1::new_with_seed(data, 0.9, 0.5, 0.5, false, 5);
+        // Probe every Gaussian vector of every structure
+        for top1 in tensor_top1.top1_list.iter_mut() {
+            top1.threshold = f64::NEG_INFINITY;
+        }
+        let q = vec![1.0, 0.0, 0.0];
+        let expected = vec![(1.0, vec![1.0, 0.0, 0.0]), (0.6, vec![0.6, 0.8, 0.0])];
+        assert_eq!(tensor_top1.query_all(&q).unwrap(), expected);
+
+        // Store the first point under a second composite key
+        let t = tensor_top1.top1_list.len();
+        let key: String = tensor_top1
+            .top1_list
+            .iter()
+            .map(|top1| top1.hash(0))
+            .collect();
+        let new_key = if key == "0#".repeat(t) {
+            "1#".repeat(t)
+        } else {
+            "0#".repeat(t)
+        };
+        tensor_top1
+            .hash_table
+            .entry(new_key.clone())
+            .or_default()
+            .push(q.clone());
+        tensor_top1.id_table.entry(new_key).or_default().push(0);
+        assert_eq!(tensor_top1.query_all(&q).unwrap(), expected);
+        assert!(tensor_top1.query_all(&[2.0, 0.0, 0.0]).is_err());
+    }
+
+    // test a construction in a dedicated pool of 2 threads and in a pool of the caller
+    #[test]
+    fn test_build_in_thread_pool() {
+        let data = crate::utils::generate_spherical_vectors(200, 3, 8).unwrap();
+        let (alpha, beta, theta) = (0.7, 0.5, 0.1);
+        let expected = TensorTop1::new_with_seed(data.clone(), alpha, beta, theta, false, 9);
+
+        let config = TensorTop1Config {
+            seed: Some(9),
+            num_threads: Some(2),
+            ..Default::default()
+        };
+        let pooled = TensorTop1::with_config(data.clone(), alpha, beta, theta, false, config);
+        assert_eq!(pooled.hash_table, expected.hash_table);
+        assert_eq!(pooled.id_table, expected.id_table);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let installed = pool.install(|| {
+            assert_eq!(rayon::current_num_threads(), 2);
+            TensorTop1::new_with_seed(data.clone(), alpha, beta, theta, false, 9)
+        });
+        assert_eq!(installed.hash_table, expected.hash_table);
+        let q = &data[0];
+        assert_eq!(installed.query(q).unwrap(), expected.query(q).unwrap());
+    }
+
+    // test that Debug prints the parameters without the vectors
+    #[test]
+    fn test_debug_summary() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let tensor_top1 = TensorTop1::new_with_seed(data, 0.9, 0.5, 0.5, false, 5);
+        let debug = format!("{:?}", tensor_top1);
+        assert!(
+            debug.starts_with("TensorTop1 { n: 3, d: 3, t: "),
+            "{}",
+            debug
+        );
+        for field in ["alpha: 0.9", "beta: 0.5", "num_buckets: "] {
+            assert!(debug.contains(field), "{}", debug);
+        }
+        assert!(!debug.contains("1.0, 0.0"), "{}", debug);
+    }
+
+    // test probed_keys against the Cartesian product of the hashes of each structure
+    #[test]
+    fn test_probed_keys() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let config = TensorTop1Config {
+            max_probed_keys: Some(4),
+            ..Default::default()
+        };
+        let capped = TensorTop1::with_config(data.clone(), 0.9, 0.8, 0.5, false, config);
+        assert_eq!(capped.max_probed_keys, Some(4));
+        let mut tensor_top1 = TensorTop1::new_with_seed(data, 0.9, 0.8, 0.5, false, 8);
+        tensor_top1.top1_list.truncate(2);
+        for top1 in tensor_top1.top1_list.iter_mut() {
+            top1.gaussian_vectors = vec![
+                vec![1.0, 0.0, 0.0],
+                vec![0.0, 1.0, 0.0],
+                vec![0.6, 0.8, 0.0],
+            ];
+        }
+        tensor_top1.top1_list[0].threshold = 0.9;
+        tensor_top1.top1_list[1].threshold = 0.7;
+
+        // Hashes ["2#"] and ["0#", "2#"]
+        let q = vec![0.8, 0.6, 0.0];
+        assert_eq!(tensor_top1.probed_keys(&q).unwrap(), vec!["2#0#", "2#2#"]);
+        // The capped keys start from the strongest hits, 2# (0.96) before 0# (0.8)
+        assert_eq!(
+            tensor_top1.probed_keys_capped(&q, 10).unwrap(),
+            vec!["2#2#", "2#0#"]
+        );
+        assert_eq!(tensor_top1.probed_keys_capped(&q, 1).unwrap(), vec!["2#2#"]);
+        // The cap of the config applies to the keys probed by the queries
+        tensor_top1.max_probed_keys = Some(1);
+        assert_eq!(tensor_top1.probed_keys(&q).unwrap(), vec!["2#2#"]);
+        // No Gaussian vector above the threshold in the second structure
+        let q = vec![0.0, 0.0, 1.0];
+        assert!(tensor_top1.probed_keys(&q).unwrap().is_empty());
+        assert!(tensor_top1.probed_keys(&[2.0, 0.0, 0.0]).is_err());
+    }
+
+    // test that each structure of a heterogeneous index has the threshold of its alpha
+    #[test]
+    fn test_new_heterogeneous() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        let alphas = [0.8, 0.9, 0.95];
+        let mut tensor_top1 =
+            TensorTop1::new_heterogeneous(data.clone(), &alphas, 0.7, 0.9, Some(9)).unwrap();
+        assert_eq!(tensor_top1.top1_list.len(), 3);
+        assert_eq!(tensor_top1.alpha, 0.8);
+        for (top1, alpha) in tensor_top1.top1_list.iter().zip(alphas) {
+            let m = top1.gaussian_vectors.len();
+            assert_eq!(m, compute_m(3, alpha, 0.3));
+            assert_eq!(top1.threshold.to_bits(), get_threshold(alpha, m).to_bits());
+        }
+        assert_eq!(tensor_top1.len(), 3);
+
+        // Probe every composite bucket
+        for top1 in tensor_top1.top1_list.iter_mut() {
+            top1.threshold = f64::NEG_INFINITY;
+        }
+        let q = vec![0.0, 1.0, 0.0];
+        assert_eq!(tensor_top1.query(&q).unwrap(), Some(q));
+
+        // Empty alphas or data, and an alpha that is not above beta
+        for (data, alphas) in [
+            (data.clone(), &[][..]),
+            (Vec::new(), &alphas[..]),
+            (data, &[0.8, 0.6][..]),
+        ] {
+            assert!(matches!(
+                TensorTop1::new_heterogeneous(data, alphas, 0.7, 0.9, Some(9)),
+                Err(AnnError::InvalidParameter(_))
+            ));
+        }
+    }
+
+    // test that the number of Top1 structures is clamped when alpha is close to 1
+    #[test]
+    fn test_max_t() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+        ];
+        // 1 / (1 - alpha^2) is about 5000 for alpha = 0.9999
+        let tensor_top1 = TensorTop1::new(data.clone(), 0.9999, 0.8, 0.5, false);
+        assert_eq!(tensor_top1.top1_list.len(), DEFAULT_MAX_T);
+
+        let config = TensorTop1Config {
+            seed: Some(7),
+            max_t: Some(4),
+            ..Default::default()
+        };
+        let tensor_top1 = TensorTop1::with_config(data.clone(), 0.9999, 0.8, 0.5, false, config);
+        assert_eq!(tensor_top1.top1_list.len(), 4);
+        assert_eq!(tensor_top1.len(), 3);
+
+        // A bound above t has no effect
+        let config = TensorTop1Config {
+            seed: Some(7),
+            max_t: Some(1000),
+            ..Default::default()
+        };
+        let tensor_top1 = TensorTop1::with_config(data, 0.9, 0.8, 0.5, false, config);
+        assert_eq!(tensor_top1.top1_list.len(), 6);
+    }
+
+    // test that a flattened structure answers queries identically
+    #[test]
+    fn test_into_flat() {
+        let data = vec![
+            vec![1.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 1.0],
+            vec![1.0, 0.0, 0.0],
+        ];
+        // The constructors always use at least two Top1 structures, so assemble a single one
+        let (alpha, beta) = (0.9, 0.8);
+        let top1_list = vec![Top1::new_with_seed(&data, alpha, beta, 0.5, 3)];
+        let (hash_table, id_table) = get_hash_table(data.clone(), &top1_list);
+        let tensor_top1 = TensorTop1 {
+            top1_list,
+            hash_table,
+            id_table,
+            alpha,
+            beta,
+            d: 3,
+            max_probed_keys: None,
+        };
+        let expected: Vec<_> = data.iter().map(|q| tensor_top1.query(q).unwrap()).collect();
+        let threshold = tensor_top1.top1_list[0].threshold;
+
+        let flat = tensor_top1.into_flat().unwrap();
+        assert_eq!(flat.len(), 4);
+        assert_eq!(flat.threshold, threshold);
+        for (q, expected) in data.iter().zip(expected) {
+            assert_eq!(flat.query(q).unwrap(), expected);
+        }
+
+        // Several structures cannot be flattened
+        let tensor_top1 = TensorTop1::new(data, alpha, beta, 0.5, false);
+        assert!(tensor_top1.top1_list.len() > 1);
+        assert!(matches!(
+            tensor_top1.into_flat(),
+            Err(AnnError::NotFlattenable { .. })
+        ));
+    }
+
+    // test that the parallel construction matches a sequential one with the same seed
+    #[test]
+    fn test_parallel_construction_matches_sequential() {
+        let data = crate::utils::generate_spherical_vectors(50, 5, 7).unwrap();
+        let (alpha, beta, theta, seed) = (0.9, 0.5, 0.5, 42);
+        let tensor_top1 = TensorTop1::new_with_seed(data.clone(), alpha, beta, theta, false, seed);
+
+        // Build the same Top1 structures sequentially
+        let t = tensor_top1.top1_list.len();
+        let theta_inner = theta / (t as f64);
+        let mut top1_list = Vec::new();
+        for i in 0..t {
+            top1_list.push(Top1::new_with_seed(
+                &data,
+                alpha,
+                beta,
+                theta_inner,
+                seed + i as u64,
+            ));
+        }
+        let (hash_table, id_table) = get_hash_table(data.clone(), &top1_list);
+        assert_eq!(tensor_top1.hash_table, hash_table);
+        assert_eq!(tensor_top1.id_table, id_table);
+
+        // Both answer queries identically
+        for q in data.iter().take(10) {
+            let expected = query(q, &top1_list, &hash_table, beta, None).unwrap();
+            assert_eq!(tensor_top1.query(q).unwrap(), expected);
+        }
+    }
 }
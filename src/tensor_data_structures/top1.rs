@@ -1,8 +1,13 @@
 use crate::checks::check_input;
-use crate::utils::{dot_product, generate_normal_gaussian_vectors, get_threshold};
-use rand_distr::num_traits::Pow;
+use crate::utils;
+use crate::utils::{
+    compute_m, dot_product, generate_normal_gaussian_vectors,
+    generate_normal_gaussian_vectors_seeded, get_threshold, BuildReport,
+};
 use rayon::prelude::*;
+use std::time::Instant;
 
+#[derive(Clone)]
 pub struct Top1 {
     // Random Gaussian vectors
     pub gaussian_vectors: Vec<Vec<f64>>,
@@ -14,10 +19,32 @@ pub struct Top1 {
 
 impl Top1 {
     /// Constructor for the Top1 struct.
-    pub fn new(data: &Vec<Vec<f64>>, alpha: f64, beta: f64, theta: f64) -> Self {
+    pub fn new(data: &[Vec<f64>], alpha: f64, beta: f64, theta: f64) -> Self {
+        Self::build(data, alpha, beta, theta, None).0
+    }
+
+    /// Constructor for the Top1 struct with Gaussian vectors drawn from a seeded generator,
+    /// so that the same seed always produces the same structure.
+    pub fn new_with_seed(data: &[Vec<f64>], alpha: f64, beta: f64, theta: f64, seed: u64) -> Self {
+        Self::build(data, alpha, beta, theta, Some(seed)).0
+    }
+
+    /// Constructor for the Top1 struct that also reports the time spent in each phase.
+    pub fn new_timed(data: &[Vec<f64>], alpha: f64, beta: f64, theta: f64) -> (Self, BuildReport) {
+        Self::build(data, alpha, beta, theta, None)
+    }
+
+    pub(crate) fn build(
+        data: &[Vec<f64>],
+        alpha: f64,
+        beta: f64,
+        theta: f64,
+        seed: Option<u64>,
+    ) -> (Self, BuildReport) {
+        let start = Instant::now();
 
         // Check inputs
-        match check_input(&data, alpha, beta, theta) {
+        match check_input(data, alpha, beta, theta) {
             Ok(_) => {}
             Err(err) => eprintln!("Input validation failed: {}", err),
         }
@@ -27,33 +54,58 @@ impl Top1 {
         // Number of vectors in the data
         let n = data.len();
         // Number of Gaussian vectors
-        let m = (n as f64).pow(theta / (1. - alpha.powi(2))).ceil() as usize;
+        let m = compute_m(n, alpha, theta);
         // Generate Gaussian vectors
-        let gaussian_vectors = generate_normal_gaussian_vectors(m, d).unwrap();
+        let gaussian_start = Instant::now();
+        let gaussian_vectors = match seed {
+            Some(seed) => generate_normal_gaussian_vectors_seeded(m, d, seed).unwrap(),
+            None => generate_normal_gaussian_vectors(m, d).unwrap(),
+        };
+        let gaussian_gen = gaussian_start.elapsed();
         // Create match_list using parallel computation
+        let hashing_start = Instant::now();
         let match_list = get_match_list_parallel(data, &gaussian_vectors);
+        let hashing = hashing_start.elapsed();
         // Create Top1 struct
-        Top1 {
+        let top1 = Top1 {
             gaussian_vectors,
             match_list,
             threshold: get_threshold(alpha, m),
-        }
+        };
+        let report = BuildReport {
+            gaussian_gen,
+            hashing,
+            total: start.elapsed(),
+        };
+        (top1, report)
     }
 
     /// Given a `query`, return all the indices of the Gaussian vectors with dot product
     /// greater than or equal to the `threshold`. The output is encoded as Vec<String>.
     ///
     /// Parameters:
-    /// query: &Vec<f64> - The query vector as reference
+    /// query: &[f64] - The query vector as reference
     ///
     /// Returns:
     /// Vec<String> - The hashes of the Gaussian vectors that meet the threshold
     ///
     /// Example: if Gaussian vectors 1 and 4 meet the threshold, the output will be ["1#", "4#"].
-    pub fn search(&self, query: &Vec<f64>) -> Vec<String> {
+    pub fn search(&self, query: &[f64]) -> Vec<String> {
         search(&self.gaussian_vectors, query, self.threshold)
     }
 
+    /// Like `search`, but the hashes are sorted by decreasing dot product of their Gaussian
+    /// vector with the query.
+    pub fn search_sorted(&self, query: &[f64]) -> Vec<String> {
+        let mut hits: Vec<(usize, f64)> =
+            utils::search(&self.gaussian_vectors, query, self.threshold)
+                .into_iter()
+                .map(|i| (i, dot_product(query, &self.gaussian_vectors[i])))
+                .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1));
+        hits.into_iter().map(|(i, _)| format!("{}#", i)).collect()
+    }
+
     /// Given a number from 0 to n-1, return a hash, which is the index of the closest Gaussian vector.
     ///
     /// Parameters:
@@ -73,27 +125,19 @@ impl Top1 {
 /// greater than or equal to the `threshold`.
 ///
 /// Parameters:
-/// gaussian_vectors: &Vec<Vec<f64>> - The Gaussian vectors as reference
-/// query: &Vec<f64> - The query vector as reference
+/// gaussian_vectors: &[Vec<f64>] - The Gaussian vectors as reference
+/// query: &[f64] - The query vector as reference
 /// threshold: f64 - The threshold value
 ///
 /// Returns:
 /// Vec<String> - The hashes of the Gaussian vectors
 ///
 /// It might return a null vector if no Gaussian vector meets the threshold.
-fn search(gaussian_vectors: &Vec<Vec<f64>>,
-          query: &Vec<f64>,
-          threshold: f64) -> Vec<String> {
-    gaussian_vectors
-        .iter()
-        .enumerate()
-        .filter_map(|(i, gaussian_vector)| {
-            if dot_product(query, gaussian_vector) >= threshold {
-                Some(format!("{}#", i))
-            } else {
-                None
-            }
-        })
+/// This is `utils::search` with each index encoded as a tensor hash.
+fn search(gaussian_vectors: &[Vec<f64>], query: &[f64], threshold: f64) -> Vec<String> {
+    utils::search(gaussian_vectors, query, threshold)
+        .into_iter()
+        .map(|i| format!("{}#", i))
         .collect()
 }
 
@@ -101,15 +145,15 @@ fn search(gaussian_vectors: &Vec<Vec<f64>>,
 /// Store the indices of the closest Gaussian vector in a Vec<usize>.
 ///
 /// Parameters:
-/// data: &Vec<Vec<f64> - The input data vectors as reference
-/// gaussian_vectors: &Vec<Vec<f64> - The Gaussian vectors as reference
+/// data: &[Vec<f64>] - The input data vectors as reference
+/// gaussian_vectors: &[Vec<f64>] - The Gaussian vectors as reference
 ///
 /// Returns:
 /// Vec<usize> - The indices of the closest Gaussian vectors
 #[allow(dead_code)]
 fn get_match_list(
-    data: &Vec<Vec<f64>>,             // Input data vectors
-    gaussian_vectors: &Vec<Vec<f64>>, // Gaussian vectors
+    data: &[Vec<f64>],             // Input data vectors
+    gaussian_vectors: &[Vec<f64>], // Gaussian vectors
 ) -> Vec<usize> {
     data.iter()
         .map(|point| {
@@ -129,15 +173,15 @@ fn get_match_list(
 /// This function uses Rayon to parallelize the computation.
 ///
 /// Parameters:
-/// data: &Vec<Vec<f64> - The input data vectors as reference
-/// gaussian_vectors: &Vec<Vec<f64> - The Gaussian vectors as reference
+/// data: &[Vec<f64>] - The input data vectors as reference
+/// gaussian_vectors: &[Vec<f64>] - The Gaussian vectors as reference
 ///
 /// Returns:
 /// Vec<usize> - The indices of the closest Gaussian vectors
 #[allow(dead_code)]
 fn get_match_list_parallel(
-    data: &Vec<Vec<f64>>,             // Input data vectors
-    gaussian_vectors: &Vec<Vec<f64>>, // Gaussian vectors
+    data: &[Vec<f64>],             // Input data vectors
+    gaussian_vectors: &[Vec<f64>], // Gaussian vectors
 ) -> Vec<usize> {
     // Use par_iter() to convert into a parallel iterator
     data.par_iter()
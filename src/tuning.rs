@@ -0,0 +1,96 @@
+use crate::error::AnnError;
+use crate::eval::self_neighbor_misses;
+use crate::simple_data_structures::top1::{Top1, Top1Config};
+
+/// Seed of the Gaussian vectors of the index built by `beta_for_recall`, so that the
+/// calibration is reproducible.
+const TUNING_SEED: u64 = 0;
+
+/// Step of the grid of the values of beta tried by `beta_for_recall`.
+const BETA_STEP: f64 = 0.01;
+
+/// Return the loosest `beta` on a grid of step 0.01 whose self-neighbor recall, i.e.
+/// `1 - eval::false_negative_rate` over `sample` points of `data`, is at least
+/// `target_recall`, together with every larger value of the grid. The recall is measured on a
+/// Top1 structure built on `data` with `alpha` and `theta`, whose buckets do not depend on
+/// beta. Closer neighbors collide more often, so the recall shrinks as beta decreases.
+/// Return None if the target is missed already at the largest beta where some sampled point
+/// has a neighbor, or if no sampled point has a neighbor.
+/// An error is returned if `sample` is 0, if `target_recall` is not in [0, 1], or if the
+/// structure cannot be built on `data` with `alpha` and `theta`, see `Top1::try_with_config`.
+pub fn beta_for_recall(
+    data: &[Vec<f64>],
+    alpha: f64,
+    theta: f64,
+    target_recall: f64,
+    sample: usize,
+) -> Result<Option<f64>, AnnError> {
+    if sample == 0 {
+        return Err(AnnError::InvalidParameter(
+            "sample must be positive".to_string(),
+        ));
+    }
+    if !(0.0..=1.0).contains(&target_recall) {
+        return Err(AnnError::InvalidParameter(format!(
+            "target_recall must be in [0, 1], got {}",
+            target_recall
+        )));
+    }
+    let index = tuning_index(data, alpha, theta)?;
+    let steps = (2. / BETA_STEP).round() as usize;
+    let mut best = None;
+    for k in 1..=steps {
+        let beta = 1. - k as f64 * BETA_STEP;
+        let (misses, with_neighbor) = self_neighbor_misses(&index, data, beta, sample);
+        if with_neighbor == 0 {
+            continue;
+        }
+        let recall = 1. - misses as f64 / with_neighbor as f64;
+        if recall < target_recall {
+            break;
+        }
+        best = Some(beta);
+    }
+    Ok(best)
+}
+
+/// Build the structure on which `beta_for_recall` measures the recall. Its buckets do not
+/// depend on beta, which only has to pass the checks of `Top1::try_with_config`.
+fn tuning_index(data: &[Vec<f64>], alpha: f64, theta: f64) -> Result<Top1, AnnError> {
+    let config = Top1Config { seed: Some(TUNING_SEED), ..Default::default() };
+    Top1::try_with_config(data.to_vec(), alpha, alpha / 2., theta, config)
+}
+
+/// Test function for the calibration of beta.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::false_negative_rate;
+    use crate::utils::generate_clustered_vectors;
+
+    // test that the returned beta achieves the target recall on the sample
+    #[test]
+    fn test_beta_for_recall() {
+        let data = generate_clustered_vectors(20, 25, 20, 0.3, 11).unwrap();
+        let (alpha, theta, target, sample) = (0.9, 0.1, 0.8, 100);
+        let beta = beta_for_recall(&data, alpha, theta, target, sample)
+            .unwrap()
+            .unwrap();
+        assert!(beta > -1. && beta < 1., "beta = {}", beta);
+
+        let index = tuning_index(&data, alpha, theta).unwrap();
+        let recall = 1. - false_negative_rate(&index, &data, beta, sample);
+        assert!(recall >= target, "recall = {}", recall);
+        // A looser beta misses the target
+        let looser = 1. - false_negative_rate(&index, &data, beta - BETA_STEP, sample);
+        assert!(looser < target, "recall = {}", looser);
+
+        // Invalid parameters
+        assert!(beta_for_recall(&data, alpha, theta, target, 0).is_err());
+        assert!(beta_for_recall(&data, alpha, theta, 1.5, sample).is_err());
+        assert!(beta_for_recall(&data, alpha, theta, f64::NAN, sample).is_err());
+        assert!(beta_for_recall(&[], alpha, theta, target, sample).is_err());
+        assert!(beta_for_recall(&data, 1.5, theta, target, sample).is_err());
+        assert!(beta_for_recall(&data, alpha, 0.0, target, sample).is_err());
+    }
+}
@@ -1,17 +1,65 @@
 use rand::distributions::Distribution;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rand_distr::Normal;
 use std::io;
+use std::time::Duration;
 use rayon::prelude::*;
 
+/// Time spent in each phase of the construction of a data structure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BuildReport {
+    /// Time spent generating the Gaussian vectors
+    pub gaussian_gen: Duration,
+    /// Time spent assigning the data to the Gaussian vectors
+    pub hashing: Duration,
+    /// Total construction time, including the input checks
+    pub total: Duration,
+}
+
 /// Computes the dot product of two vectors.
 pub fn dot_product(vec1: &[f64], vec2: &[f64]) -> f64 {
     vec1.iter().zip(vec2.iter()).map(|(a, b)| a * b).sum()
 }
 
+/// Former name of `dot_product`, kept for backward compatibility.
+#[deprecated(note = "use `dot_product` instead")]
+pub fn get_dot_product(vec1: &[f64], vec2: &[f64]) -> f64 {
+    dot_product(vec1, vec2)
+}
+
+/// Computes the squared Euclidean (L2) distance between two vectors.
+pub fn l2_distance_sq(vec1: &[f64], vec2: &[f64]) -> f64 {
+    vec1.iter()
+        .zip(vec2.iter())
+        .map(|(a, b)| (a - b).powi(2))
+        .sum()
+}
+
+/// Computes the Manhattan (L1) distance between two vectors.
+pub fn l1_distance(vec1: &[f64], vec2: &[f64]) -> f64 {
+    vec1.iter()
+        .zip(vec2.iter())
+        .map(|(a, b)| (a - b).abs())
+        .sum()
+}
+
 /// Generates n random Normal Gaussian vectors of dimension d.
 pub fn generate_normal_gaussian_vectors(n: usize, d: usize) -> Result<Vec<Vec<f64>>, io::Error> {
-    // Step 1: Define the normal distribution with mean 0 and standard deviation sigma
-    let normal = Normal::new(0.0, 1.0).map_err(|e| {
+    generate_normal_gaussian_vectors_with_std(n, d, 1.0)
+}
+
+/// Generates n random Gaussian vectors of dimension d with mean 0 and standard deviation `std`.
+///
+/// Warning: `get_threshold` assumes standard Normal projections. Using a standard deviation
+/// different from 1 requires recalibrating the threshold (e.g. multiplying it by `std`).
+pub fn generate_normal_gaussian_vectors_with_std(
+    n: usize,
+    d: usize,
+    std: f64,
+) -> Result<Vec<Vec<f64>>, io::Error> {
+    // Step 1: Define the normal distribution with mean 0 and standard deviation std
+    let normal = Normal::new(0.0, std).map_err(|e| {
         io::Error::new(
             io::ErrorKind::InvalidInput,
             format!("Failed to create normal distribution: {}", e),
@@ -31,6 +79,96 @@ pub fn generate_normal_gaussian_vectors(n: usize, d: usize) -> Result<Vec<Vec<f6
     Ok(vectors)
 }
 
+/// Generates n random Normal Gaussian vectors of dimension d from a seeded generator.
+/// The same seed always produces the same vectors.
+pub fn generate_normal_gaussian_vectors_seeded(
+    n: usize,
+    d: usize,
+    seed: u64,
+) -> Result<Vec<Vec<f64>>, io::Error> {
+    // Step 1: Define the normal distribution with mean 0 and standard deviation 1
+    let normal = Normal::new(0.0, 1.0).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Failed to create normal distribution: {}", e),
+        )
+    })?;
+
+    // Step 2: Generate N random Gaussian vectors of dimension d with the seeded generator
+    let mut rng = StdRng::seed_from_u64(seed);
+    let vectors = (0..n)
+        .map(|_| (0..d).map(|_| normal.sample(&mut rng)).collect())
+        .collect();
+
+    // Return the generated vectors
+    Ok(vectors)
+}
+
+/// Generates n random directions of dimension d, uniform on the unit sphere, by normalizing
+/// seeded Gaussian vectors. Unlike raw Gaussian vectors, their projections do not grow with
+/// the dimension, so `get_threshold`, derived for Gaussian projections, may need adjustment
+/// when they are used as projection vectors.
+pub fn generate_spherical_vectors(
+    n: usize,
+    d: usize,
+    seed: u64,
+) -> Result<Vec<Vec<f64>>, io::Error> {
+    if d == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Spherical vectors must have a positive dimension",
+        ));
+    }
+    let mut vectors = generate_normal_gaussian_vectors_seeded(n, d, seed)?;
+    for vector in vectors.iter_mut() {
+        normalize_vector(vector);
+    }
+    Ok(vectors)
+}
+
+/// Generates `num_clusters` groups of `points_per_cluster` unit vectors of dimension d, seeded
+/// for reproducible benchmarks. Each point is a random unit center plus Gaussian noise of norm
+/// about `spread`, normalized, so two points of a cluster have cosine similarity about
+/// `1 / (1 + spread^2)` while random centers are nearly orthogonal in high dimension.
+/// The points of cluster i are at indices `i * points_per_cluster..(i + 1) * points_per_cluster`.
+pub fn generate_clustered_vectors(
+    num_clusters: usize,
+    points_per_cluster: usize,
+    d: usize,
+    spread: f64,
+    seed: u64,
+) -> Result<Vec<Vec<f64>>, io::Error> {
+    if !(spread >= 0.0 && spread.is_finite()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The spread must be non-negative, got {}", spread),
+        ));
+    }
+    let centers = generate_spherical_vectors(num_clusters, d, seed)?;
+    let noise = generate_normal_gaussian_vectors_seeded(
+        num_clusters * points_per_cluster,
+        d,
+        seed.wrapping_add(1),
+    )?;
+    // Gaussian noise of dimension d has norm about sqrt(d)
+    let scale = spread / (d as f64).sqrt();
+    let vectors = noise
+        .into_iter()
+        .enumerate()
+        .map(|(i, noise)| {
+            let center = &centers[i / points_per_cluster];
+            let mut point: Vec<f64> = center
+                .iter()
+                .zip(noise.iter())
+                .map(|(c, e)| c + scale * e)
+                .collect();
+            normalize_vector(&mut point);
+            point
+        })
+        .collect();
+    Ok(vectors)
+}
+
 /// Generates n random Normal Gaussian vectors of dimension d.
 pub fn generate_normal_gaussian_vectors_parallel(n: usize, d: usize) -> Result<Vec<Vec<f64>>, io::Error> {
     // Step 1: Define the normal distribution with mean 0 and standard deviation sigma
@@ -54,37 +192,159 @@ pub fn generate_normal_gaussian_vectors_parallel(n: usize, d: usize) -> Result<V
     Ok(vectors)
 }
 
+/// Generates n random Normal Gaussian vectors of dimension d in parallel, row `i` being drawn
+/// from a generator seeded with `row_seed(seed, i)`. The output only depends on `seed`, not on
+/// the number of threads or the scheduling.
+pub fn generate_normal_gaussian_vectors_parallel_seeded(
+    n: usize,
+    d: usize,
+    seed: u64,
+) -> Result<Vec<Vec<f64>>, io::Error> {
+    // Step 1: Define the normal distribution with mean 0 and standard deviation 1
+    let normal = Normal::new(0.0, 1.0).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Failed to create normal distribution: {}", e),
+        )
+    })?;
+
+    // Step 2: Generate N random Gaussian vectors of dimension d in parallel, one generator per row
+    let vectors: Vec<Vec<f64>> = (0..n)
+        .into_par_iter()
+        .map(|i| {
+            let mut rng = StdRng::seed_from_u64(row_seed(seed, i));
+            (0..d).map(|_| normal.sample(&mut rng)).collect()
+        })
+        .collect();
+
+    // Return the generated vectors
+    Ok(vectors)
+}
+
+/// Derive the seed of row `i` from the master `seed` with the SplitMix64 finalizer, so that
+/// consecutive rows get unrelated seeds.
+fn row_seed(seed: u64, i: usize) -> u64 {
+    let mut z = seed.wrapping_add(
+        (i as u64)
+            .wrapping_add(1)
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15),
+    );
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
 /// Helper function to check if a vector is normalized.
-pub fn is_normalized(vector: &Vec<f64>) -> bool {
+pub fn is_normalized(vector: &[f64]) -> bool {
+    if !vector.iter().all(|x| x.is_finite()) {
+        return false;
+    }
     let norm = vector.iter().map(|x| x * x).sum::<f64>();
     (norm - 1.0).abs() <= 1e-6
 }
 
 /// Normalizes a vector to have unit length.
-pub fn normalize_vector(vector: &mut Vec<f64>) {
+pub fn normalize_vector(vector: &mut [f64]) {
     let norm: f64 = vector.iter().map(|x| x.powi(2)).sum::<f64>().sqrt();
-    for i in 0..vector.len() {
-        vector[i] /= norm;
+    for x in vector.iter_mut() {
+        *x /= norm;
     }
 }
 
 /// Helper function to find a close vector in a list of vectors.
-pub fn find_close_vector(query: &Vec<f64>, vectors: &Vec<Vec<f64>>, beta: f64) -> Option<Vec<f64>> {
-    for vector in vectors {
-        if dot_product(query, vector) >= beta {
-            return Some(vector.clone());
-        }
+pub fn find_close_vector(query: &[f64], vectors: &[Vec<f64>], beta: f64) -> Option<Vec<f64>> {
+    find_close_vector_ref(query, vectors, beta).cloned()
+}
+
+/// Helper function to find a close vector in a list of vectors, returning a reference to it
+/// instead of a copy.
+pub fn find_close_vector_ref<'a>(
+    query: &[f64],
+    vectors: &'a [Vec<f64>],
+    beta: f64,
+) -> Option<&'a Vec<f64>> {
+    vectors
+        .iter()
+        .find(|vector| dot_product(query, vector) >= beta)
+}
+
+/// Given a `q`uery, return the indices of all the Gaussian vectors with dot product greater
+/// than or equal to the `threshold`, in increasing order. The result is empty when no
+/// Gaussian vector meets the threshold; it is shared by all the structures of the crate.
+pub fn search(gaussian_vectors: &[Vec<f64>], q: &[f64], threshold: f64) -> Vec<usize> {
+    gaussian_vectors
+        .iter()
+        .enumerate()
+        .filter(|(_, gaussian_vector)| dot_product(q, gaussian_vector) >= threshold)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Return the optimal LSH exponent `theta = (1 - alpha^2)(1 - beta^2) / (1 - alpha beta)^2`
+/// for close points at dot product `alpha` and far points at dot product `beta`.
+/// Requires `0 < beta < alpha < 1`.
+pub fn optimal_theta(alpha: f64, beta: f64) -> Result<f64, String> {
+    if !(0.0 < beta && beta < alpha && alpha < 1.0) {
+        return Err(format!(
+            "Expected 0 < beta < alpha < 1, got alpha = {} and beta = {}.",
+            alpha, beta
+        ));
     }
-    None
+    Ok((1. - alpha.powi(2)) * (1. - beta.powi(2)) / (1. - alpha * beta).powi(2))
+}
+
+/// Return the number of Gaussian vectors `m = ceil(n^(theta / (1 - alpha^2)))` of a Top1
+/// structure on `n` points.
+pub fn compute_m(n: usize, alpha: f64, theta: f64) -> usize {
+    (n as f64).powf(theta / (1. - alpha.powi(2))).ceil() as usize
 }
 
+/// Return the threshold `alpha sqrt(2 ln m) - sqrt(2 (1 - alpha^2) ln ln m)` above which the
+/// query probes the bucket of a Gaussian vector, among `m`.
+/// For `m < e`, `ln ln m` is negative and the second term is clamped to 0, as is `1 - alpha^2`
+/// for `alpha` beyond 1, so the threshold is always finite.
 pub fn get_threshold(alpha: f64, m: usize) -> f64 {
-    let ln_m = (m as f64).ln();
-    let ln_ln_m = ln_m.ln();
+    let ln_m = (m.max(1) as f64).ln();
+    let ln_ln_m = ln_m.ln().max(0.);
     let first_term = alpha * (2. * ln_m).sqrt();
-    let second_term = -(2. * (1. - alpha.powi(2)) * ln_ln_m).sqrt();
-    let threshold = first_term + second_term;
-    threshold
+    let second_term = -(2. * (1. - alpha.powi(2)).max(0.) * ln_ln_m).sqrt();
+    first_term + second_term
+}
+
+/// Standard normal cumulative distribution function, computed with the complementary error
+/// function of libm, which keeps a small relative error in the lower tail, where
+/// `privacy::gaussian_delta` evaluates it at probabilities far below 1e-7.
+pub fn normal_cdf(x: f64) -> f64 {
+    0.5 * libm::erfc(-x / std::f64::consts::SQRT_2)
+}
+
+/// Inverse of `normal_cdf` for a probability `p` in (0, 1), computed by bisection.
+pub fn normal_quantile(p: f64) -> f64 {
+    let (mut low, mut high) = (-10., 10.);
+    for _ in 0..100 {
+        let mid = 0.5 * (low + high);
+        if normal_cdf(mid) < p {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    0.5 * (low + high)
+}
+
+/// Probability that a point at dot product `similarity` with the query lands in a probed
+/// bucket, given the dot product `projection` of the point with its closest Gaussian vector.
+///
+/// The component of the Gaussian vector orthogonal to the point is independent of the argmax,
+/// so its dot product with the query is `similarity * projection + sqrt(1 - similarity^2) Z`
+/// with Z standard normal, and the bucket is probed if it meets the `threshold`.
+pub fn collision_probability(similarity: f64, projection: f64, threshold: f64) -> f64 {
+    let mean = similarity * projection;
+    let std = (1. - similarity.powi(2)).max(0.).sqrt();
+    if std == 0. {
+        return if mean >= threshold { 1. } else { 0. };
+    }
+    1. - normal_cdf((threshold - mean) / std)
 }
 
 mod tests {
@@ -107,6 +367,24 @@ mod tests {
         assert_eq!(result, 0.5);
     }
 
+    /// Test function to check if the squared L2 distance is computed correctly.
+    #[test]
+    fn test_l2_distance_sq() {
+        let vec1 = vec![1.0, 2.0, 3.0];
+        let vec2 = vec![4.0, 6.0, 3.0];
+        assert_eq!(l2_distance_sq(&vec1, &vec2), 25.0);
+        assert_eq!(l2_distance_sq(&vec1, &vec1), 0.0);
+    }
+
+    /// Test function to check if the L1 distance is computed correctly.
+    #[test]
+    fn test_l1_distance() {
+        let vec1 = vec![1.0, 2.0, 3.0];
+        let vec2 = vec![4.0, 0.0, 3.0];
+        assert_eq!(l1_distance(&vec1, &vec2), 5.0);
+        assert_eq!(l1_distance(&vec1, &vec1), 0.0);
+    }
+
     /// Test function to check if the generate_gaussian_vectors function works.
     /// The test checks if the generated vectors have the correct length and dimension.
     #[test]
@@ -118,6 +396,133 @@ mod tests {
         assert_eq!(vectors[0].len(), d);
     }
 
+    /// Test function to check if the generate_spherical_vectors function works.
+    /// The test checks that the vectors are unit-length and that their mean is near zero.
+    #[test]
+    fn test_generate_spherical_vectors() {
+        let vectors = generate_spherical_vectors(5000, 3, 4).unwrap();
+        assert!(vectors.iter().all(|v| is_normalized(v)));
+        for j in 0..3 {
+            let mean = vectors.iter().map(|v| v[j]).sum::<f64>() / vectors.len() as f64;
+            // Each coordinate has variance 1/3, the sample mean has standard deviation ~0.008
+            assert!(mean.abs() < 0.04, "mean = {}", mean);
+        }
+        assert_eq!(generate_spherical_vectors(5000, 3, 4).unwrap(), vectors);
+        assert!(generate_spherical_vectors(10, 0, 4).is_err());
+    }
+
+    /// Test function to check that the threshold is finite for m < e and for alpha close to 1.
+    #[test]
+    fn test_get_threshold_small_m() {
+        // ln ln 2 < 0, so only the first term remains
+        let threshold = get_threshold(0.9, 2);
+        assert!(threshold.is_finite());
+        assert!((threshold - 0.9 * (2. * 2f64.ln()).sqrt()).abs() < 1e-12);
+        assert_eq!(get_threshold(0.9, 1), 0.0);
+        assert_eq!(get_threshold(0.9, 0), 0.0);
+        // m = 3 is the first m above e, where ln ln m is barely positive
+        let threshold = get_threshold(0.9, 3);
+        assert!(threshold.is_finite() && threshold < 0.9 * (2. * 3f64.ln()).sqrt());
+        // alpha close to and beyond 1
+        assert!((get_threshold(1. - 1e-17, 100) - (2. * 100f64.ln()).sqrt()).abs() < 1e-9);
+        assert!(get_threshold(1.5, 100).is_finite());
+    }
+
+    /// Test function to check that clustered vectors are close within a cluster and far across.
+    #[test]
+    fn test_generate_clustered_vectors() {
+        let (num_clusters, points_per_cluster) = (5, 20);
+        let generate = || generate_clustered_vectors(num_clusters, points_per_cluster, 100, 0.3, 6);
+        let vectors = generate().unwrap();
+        assert_eq!(vectors.len(), num_clusters * points_per_cluster);
+        assert!(vectors.iter().all(|v| is_normalized(v)));
+        for i in 0..vectors.len() {
+            for j in (i + 1)..vectors.len() {
+                let similarity = dot_product(&vectors[i], &vectors[j]);
+                if i / points_per_cluster == j / points_per_cluster {
+                    assert!(similarity > 0.7, "within: {}", similarity);
+                } else {
+                    assert!(similarity < 0.5, "across: {}", similarity);
+                }
+            }
+        }
+        assert_eq!(generate().unwrap(), vectors);
+        assert!(generate_clustered_vectors(2, 2, 3, -1.0, 6).is_err());
+        assert!(generate_clustered_vectors(2, 2, 0, 0.3, 6).is_err());
+    }
+
+    /// Test function to check that the seeded parallel generator does not depend on the number
+    /// of threads.
+    #[test]
+    fn test_generate_gaussian_vectors_parallel_seeded() {
+        let generate = |num_threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap();
+            pool.install(|| generate_normal_gaussian_vectors_parallel_seeded(500, 7, 42).unwrap())
+        };
+        let bits = |vectors: Vec<Vec<f64>>| -> Vec<u64> {
+            vectors.into_iter().flatten().map(f64::to_bits).collect()
+        };
+        let single = generate(1);
+        assert_eq!(single.len(), 500);
+        assert!(single.iter().all(|v| v.len() == 7));
+        assert_eq!(bits(single.clone()), bits(generate(8)));
+        assert_ne!(
+            single,
+            generate_normal_gaussian_vectors_parallel_seeded(500, 7, 43).unwrap()
+        );
+        // The rows are not copies of each other
+        assert_ne!(single[0], single[1]);
+    }
+
+    /// Test function to check if the generate_normal_gaussian_vectors_with_std function works.
+    /// The test checks if the sample variance matches the requested std^2.
+    #[test]
+    fn test_generate_gaussian_vectors_with_std() {
+        let std = 3.0;
+        let vectors = generate_normal_gaussian_vectors_with_std(1000, 100, std).unwrap();
+        let values: Vec<f64> = vectors.into_iter().flatten().collect();
+        let count = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / count;
+        let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (count - 1.);
+        assert!((variance - std.powi(2)).abs() <= 0.05 * std.powi(2));
+
+        // A non-finite standard deviation is invalid
+        assert!(generate_normal_gaussian_vectors_with_std(10, 5, f64::INFINITY).is_err());
+    }
+
+    /// Test function to check if the seeded generator is reproducible.
+    #[test]
+    fn test_generate_gaussian_vectors_seeded() {
+        let vectors = generate_normal_gaussian_vectors_seeded(10, 5, 42).unwrap();
+        assert_eq!(vectors.len(), 10);
+        assert_eq!(vectors[0].len(), 5);
+        assert_eq!(
+            vectors,
+            generate_normal_gaussian_vectors_seeded(10, 5, 42).unwrap()
+        );
+        assert_ne!(
+            vectors,
+            generate_normal_gaussian_vectors_seeded(10, 5, 43).unwrap()
+        );
+    }
+
+    /// Test function to check that find_close_vector_ref points into the list of vectors.
+    #[test]
+    fn test_find_close_vector_ref() {
+        let vectors = vec![vec![0.0, 1.0], vec![0.8, 0.6], vec![1.0, 0.0]];
+        let query = vec![1.0, 0.0];
+        let result = find_close_vector_ref(&query, &vectors, 0.7).unwrap();
+        assert!(std::ptr::eq(result, &vectors[1]));
+        assert_eq!(
+            Some(result.clone()),
+            find_close_vector(&query, &vectors, 0.7)
+        );
+        assert_eq!(find_close_vector_ref(&query, &vectors, 1.1), None);
+    }
+
     /// Test function to check if the normalize_vector function works.
     #[test]
     fn test_normalize_vector() {
@@ -131,4 +536,52 @@ mod tests {
         let norm: f64 = vector.iter().map(|x| x.powi(2)).sum::<f64>().sqrt();
         assert!((norm - 1.0).abs() <= 1e-6);
     }
+
+    /// Test function to check the optimal theta formula and its validation.
+    #[test]
+    fn test_optimal_theta() {
+        let theta = optimal_theta(0.9, 0.55).unwrap();
+        assert!((theta - 0.19 * 0.6975 / 0.505f64.powi(2)).abs() < 1e-12);
+        let theta = optimal_theta(0.8, 0.5).unwrap();
+        assert!((theta - 0.36 * 0.75 / 0.36).abs() < 1e-12);
+
+        assert!(optimal_theta(0.5, 0.5).is_err());
+        assert!(optimal_theta(0.5, 0.8).is_err());
+        assert!(optimal_theta(1.0, 0.5).is_err());
+        assert!(optimal_theta(0.9, 0.0).is_err());
+    }
+
+    /// Test function to check that non finite vectors are not normalized.
+    #[test]
+    fn test_is_normalized_non_finite() {
+        assert!(is_normalized(&[1.0, 0.0]));
+        assert!(!is_normalized(&[f64::NAN, 0.0]));
+        assert!(!is_normalized(&[f64::INFINITY, 0.0]));
+        assert!(!is_normalized(&[1.0, f64::NEG_INFINITY]));
+    }
+
+    /// Test function to check the normal distribution helpers and the collision probability.
+    #[test]
+    fn test_collision_probability() {
+        assert!((normal_cdf(0.) - 0.5).abs() < 1e-6);
+        assert!((normal_cdf(1.96) - 0.975).abs() < 1e-4);
+        assert!((normal_cdf(-1.) - 0.158655).abs() < 1e-4);
+        assert!((normal_quantile(0.975) - 1.96).abs() < 1e-3);
+
+        // Identical points collide exactly when the bucket meets the threshold
+        assert_eq!(collision_probability(1., 2., 1.5), 1.);
+        assert_eq!(collision_probability(1., 1., 1.5), 0.);
+        // The probability increases with the similarity and decreases with the threshold
+        assert!(collision_probability(0.9, 2., 1.5) > collision_probability(0.5, 2., 1.5));
+        assert!(collision_probability(0.9, 2., 1.) > collision_probability(0.9, 2., 1.5));
+    }
+
+    /// Test function to check that the deprecated get_dot_product matches dot_product.
+    #[test]
+    #[allow(deprecated)]
+    fn test_get_dot_product_alias() {
+        let vec1 = vec![1.0, -2.0, 0.5];
+        let vec2 = vec![0.3, 4.0, 2.0];
+        assert_eq!(get_dot_product(&vec1, &vec2), dot_product(&vec1, &vec2));
+    }
 }
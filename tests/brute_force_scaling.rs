@@ -0,0 +1,36 @@
+mod common;
+
+use ann_rust::eval::BruteForce;
+use common::normalized_vectors;
+use std::time::{Duration, Instant};
+
+/// Return the time spent answering all the queries with `query` and the answers.
+fn measure<F>(queries: &[Vec<f64>], query: F) -> (Duration, Vec<Option<(usize, f64)>>)
+where
+    F: Fn(&[f64]) -> Option<(usize, f64)>,
+{
+    let start = Instant::now();
+    let answers = queries.iter().map(|q| query(q)).collect();
+    (start.elapsed(), answers)
+}
+
+// The parallel scan of BruteForce beats the sequential one and returns the same answers.
+// Ignored by default since it needs idle cores: run with `cargo test --release -- --ignored`.
+#[test]
+#[ignore]
+fn test_brute_force_scaling() {
+    let brute_force = BruteForce::new(normalized_vectors(200_000, 32, 1));
+    let queries = normalized_vectors(50, 32, 2);
+    // Warm up the caches and the thread pool
+    measure(&queries, |q| brute_force.query(q));
+
+    let (sequential, expected) = measure(&queries, |q| brute_force.query_sequential(q));
+    let (parallel, answers) = measure(&queries, |q| brute_force.query(q));
+    assert_eq!(answers, expected);
+    assert!(
+        parallel < sequential,
+        "parallel: {:?}, sequential: {:?}",
+        parallel,
+        sequential
+    );
+}
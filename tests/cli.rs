@@ -0,0 +1,92 @@
+use std::process::Command;
+
+/// Path of a data file that does not exist, so that the binaries generate their data.
+fn missing_data_file(name: &str) -> String {
+    let path =
+        std::env::temp_dir().join(format!("ann_rust_cli_{}_{}.bin", name, std::process::id()));
+    path.to_string_lossy().into_owned()
+}
+
+// Test that each experiment binary runs with command line arguments and reports a result
+#[test]
+fn test_experiment_binaries() {
+    let binaries = [
+        env!("CARGO_BIN_EXE_top1"),
+        env!("CARGO_BIN_EXE_close_top1"),
+        env!("CARGO_BIN_EXE_tensor_top1"),
+    ];
+    for binary in binaries {
+        let output = Command::new(binary)
+            .args(["--n", "50", "--d", "8", "--alpha", "0.9", "--beta", "0.5"])
+            .args([
+                "--theta",
+                "0.1",
+                "--seed",
+                "3",
+                "--data",
+                &missing_data_file("run"),
+            ])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{} failed: {:?}", binary, output);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.contains("Close point found") || stdout.contains("No close point found"),
+            "{} printed: {}",
+            binary,
+            stdout
+        );
+    }
+}
+
+// Test that invalid parameters are rejected with an error
+#[test]
+fn test_invalid_parameters() {
+    let output = Command::new(env!("CARGO_BIN_EXE_top1"))
+        .args([
+            "--alpha",
+            "0.5",
+            "--beta",
+            "0.6",
+            "--data",
+            &missing_data_file("invalid"),
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid parameter"));
+}
+
+// Test that generated data is saved and loaded back by the experiment binaries
+#[test]
+fn test_generate_data() {
+    let path = std::env::temp_dir()
+        .join(format!("ann_rust_cli_data_{}", std::process::id()))
+        .join("sample.bin");
+    let path = path.to_string_lossy().into_owned();
+    let status = Command::new(env!("CARGO_BIN_EXE_generate_data"))
+        .args(["--n", "40", "--d", "6", "--seed", "1", "--data", &path])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_top1"))
+        .args([
+            "--n", "40", "--d", "6", "--theta", "0.1", "--seed", "2", "--data", &path,
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Successfully loaded 40 vectors"));
+
+    // Data that does not match --n or --d is rejected
+    for (n, d) in [("41", "6"), ("40", "5")] {
+        let output = Command::new(env!("CARGO_BIN_EXE_top1"))
+            .args(["--n", n, "--d", d, "--theta", "0.1", "--data", &path])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Error"));
+    }
+    std::fs::remove_dir_all(std::path::Path::new(&path).parent().unwrap()).unwrap();
+}
@@ -0,0 +1,6 @@
+use ann_rust::utils::generate_spherical_vectors;
+
+/// Return `n` normalized Gaussian vectors of dimension `d` drawn with `seed`.
+pub fn normalized_vectors(n: usize, d: usize, seed: u64) -> Vec<Vec<f64>> {
+    generate_spherical_vectors(n, d, seed).unwrap()
+}
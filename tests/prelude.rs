@@ -0,0 +1,38 @@
+use ann_rust::prelude::*;
+
+fn data() -> Vec<Vec<f64>> {
+    vec![
+        vec![1.0, 0.0, 0.0],
+        vec![0.0, 1.0, 0.0],
+        vec![0.0, 0.0, 1.0],
+    ]
+}
+
+#[test]
+fn prelude_builds_and_queries_each_index() {
+    let theta = optimal_theta(0.9, 0.8).unwrap();
+    assert!(theta > 0.0);
+    let mut q = vec![2.0, 0.0, 0.0];
+    normalize_vector(&mut q);
+
+    let mut top1 = Top1::new_with_seed(data(), 0.9, 0.8, 0.5, 1);
+    top1.threshold = f64::NEG_INFINITY;
+    assert_eq!(top1.query(&q).unwrap(), Some(vec![1.0, 0.0, 0.0]));
+    assert_eq!(top1.query_k(&q, 1).unwrap(), vec![0]);
+
+    // Whether a close point is found depends on the Gaussian vectors
+    let close_top1 = CloseTop1::new(data(), 0.9, 0.8, 0.5);
+    if let Some(point) = close_top1.query(&q).unwrap() {
+        assert!(dot_product(&q, &point) >= 0.8);
+    }
+
+    let mut tensor_top1 = TensorTop1::new_with_seed(data(), 0.9, 0.8, 0.5, false, 2);
+    for top1 in tensor_top1.top1_list.iter_mut() {
+        top1.threshold = f64::NEG_INFINITY;
+    }
+    assert_eq!(tensor_top1.query(&q).unwrap(), Some(vec![1.0, 0.0, 0.0]));
+
+    let other = Top1::new_with_seed(vec![vec![1.0, 0.0]], 0.9, 0.8, 0.5, 1);
+    let err: AnnError = top1.merge(other).unwrap_err();
+    assert!(matches!(err, AnnError::DimensionMismatch { .. }));
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use ann_rust::simple_data_structures::top1::Top1;
+use common::normalized_vectors;
+use std::time::{Duration, Instant};
+
+/// Return the shortest of `runs` times spent answering all the queries with `query`.
+fn measure<F>(queries: &[Vec<f64>], runs: usize, query: F) -> Duration
+where
+    F: Fn(&[f64]) -> Option<Vec<f64>>,
+{
+    (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            for q in queries {
+                std::hint::black_box(query(q));
+            }
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+// For d = 10000 skipping the O(d) normalization check of `query` is a measurable saving.
+// Ignored by default since it is a timing test: run with `cargo test --release -- --ignored`.
+#[test]
+#[ignore]
+fn test_query_unchecked_cost() {
+    let d = 10_000;
+    let top1 = Top1::new_with_seed(normalized_vectors(1000, d, 1), 0.9, 0.8, 0.1, 2);
+    let queries = normalized_vectors(200, d, 3);
+    // Warm up the caches
+    measure(&queries, 1, |q| top1.query(q).unwrap());
+
+    let checked = measure(&queries, 10, |q| top1.query(q).unwrap());
+    let unchecked = measure(&queries, 10, |q| top1.query_unchecked(q));
+    for q in queries.iter() {
+        assert_eq!(top1.query_unchecked(q), top1.query(q).unwrap());
+    }
+    assert!(
+        unchecked < checked,
+        "unchecked: {:?}, checked: {:?}",
+        unchecked,
+        checked
+    );
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use ann_rust::simple_data_structures::top1::Top1;
+use common::normalized_vectors;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Query throughput of a shared Top1 structure for a number of reader threads.
+#[derive(Debug)]
+struct ReadScalingReport {
+    /// Number of reader threads
+    threads: usize,
+    /// Total number of queries answered
+    queries: usize,
+    /// Wall-clock time of the run
+    elapsed: Duration,
+}
+
+impl ReadScalingReport {
+    /// Return the number of queries answered per second.
+    fn throughput(&self) -> f64 {
+        self.queries as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Run the queries on `threads` reader threads sharing `top1`, each thread answering all of
+/// them.
+fn measure(top1: &Arc<Top1>, queries: &Arc<Vec<Vec<f64>>>, threads: usize) -> ReadScalingReport {
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let (top1, queries) = (Arc::clone(top1), Arc::clone(queries));
+            thread::spawn(move || {
+                for q in queries.iter() {
+                    std::hint::black_box(top1.query_ref(q).unwrap());
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    ReadScalingReport {
+        threads,
+        queries: threads * queries.len(),
+        elapsed: start.elapsed(),
+    }
+}
+
+// Queries take `&self` and no lock, so a Top1 structure can be shared by reader threads
+#[test]
+fn test_top1_is_shareable() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Top1>();
+
+    let top1 = Arc::new(Top1::new_with_seed(
+        normalized_vectors(200, 8, 1),
+        0.9,
+        0.5,
+        0.1,
+        2,
+    ));
+    let queries = Arc::new(normalized_vectors(20, 8, 3));
+    let sequential: Vec<_> = queries.iter().map(|q| top1.query(q).unwrap()).collect();
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let (top1, queries) = (Arc::clone(&top1), Arc::clone(&queries));
+            thread::spawn(move || {
+                queries
+                    .iter()
+                    .map(|q| top1.query(q).unwrap())
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), sequential);
+    }
+}
+
+// Throughput of 4 reader threads is at least twice the single-thread throughput. Ignored by
+// default since it needs 4 idle cores: run with `cargo test --release -- --ignored`.
+#[test]
+#[ignore]
+fn test_read_scaling() {
+    let top1 = Arc::new(Top1::new_with_seed(
+        normalized_vectors(50_000, 16, 4),
+        0.9,
+        0.5,
+        0.1,
+        5,
+    ));
+    let queries = Arc::new(normalized_vectors(2_000, 16, 6));
+    // Warm up the caches
+    measure(&top1, &queries, 1);
+
+    let single = measure(&top1, &queries, 1);
+    let parallel = measure(&top1, &queries, 4);
+    assert!(
+        parallel.throughput() >= 2.0 * single.throughput(),
+        "{} threads: {:.0} queries/s, 1 thread: {:.0} queries/s ({:?}, {:?})",
+        parallel.threads,
+        parallel.throughput(),
+        single.throughput(),
+        parallel,
+        single
+    );
+}